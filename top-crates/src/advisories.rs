@@ -0,0 +1,217 @@
+use crate::TopCratesError;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// A security advisory from a checkout of the RustSec advisory
+/// database, as far as is needed to tell which versions it affects.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Advisory {
+    pub(crate) id: String,
+    pub(crate) package: String,
+    pub(crate) title: Option<String>,
+    /// Such as `unmaintained`, for advisories that aren't about a
+    /// vulnerability.
+    pub(crate) informational: Option<String>,
+    patched: Vec<VersionReq>,
+    unaffected: Vec<VersionReq>,
+}
+
+#[derive(Deserialize)]
+struct Document {
+    advisory: AdvisorySection,
+    #[serde(default)]
+    versions: VersionsSection,
+}
+
+#[derive(Deserialize)]
+struct AdvisorySection {
+    id: String,
+    package: String,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    informational: Option<String>,
+    #[serde(default)]
+    withdrawn: Option<toml::Value>,
+}
+
+#[derive(Default, Deserialize)]
+struct VersionsSection {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+impl Advisory {
+    /// Whether `version` is neither patched nor unaffected.
+    pub(crate) fn affects(&self, version: &Version) -> bool {
+        !self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .any(|req| req.matches(version))
+    }
+
+    /// Reads an advisory written either as Markdown with a TOML code
+    /// block at the top, or as the TOML of older databases. Withdrawn
+    /// advisories are `None`.
+    fn parse(content: &str, markdown: bool) -> Result<Option<Advisory>, String> {
+        let (front_matter, body) = if markdown {
+            let rest = content
+                .trim_start()
+                .strip_prefix("```toml")
+                .ok_or("it does not start with a TOML code block")?;
+            rest.split_once("\n```")
+                .ok_or("its TOML code block is not closed")?
+        } else {
+            (content, "")
+        };
+        let document: Document = toml::from_str(front_matter).map_err(|e| e.to_string())?;
+        if document.advisory.withdrawn.is_some() {
+            return Ok(None);
+        }
+
+        let requirements = |reqs: Vec<String>| {
+            reqs.iter()
+                .map(|req| {
+                    VersionReq::parse(req)
+                        .map_err(|e| format!("invalid version requirement `{}`: {}", req, e))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let title = document.advisory.title.or_else(|| {
+            body.lines()
+                .find_map(|line| line.strip_prefix("# "))
+                .map(|title| title.trim().to_owned())
+        });
+        Ok(Some(Advisory {
+            id: document.advisory.id,
+            package: document.advisory.package,
+            title,
+            informational: document.advisory.informational,
+            patched: requirements(document.versions.patched)?,
+            unaffected: requirements(document.versions.unaffected)?,
+        }))
+    }
+}
+
+/// Advisories keyed by crate name, and a message for each file that
+/// could not be understood.
+type Loaded = (BTreeMap<String, Vec<Advisory>>, Vec<String>);
+
+/// Every advisory in the `crates` directory of the advisory database
+/// checked out at `dir`, keyed by crate name, along with a message for
+/// each file that could not be understood.
+pub(crate) fn load(dir: &Path) -> Result<Loaded, TopCratesError> {
+    let crates = dir.join("crates");
+    let read_dir = |dir: &Path| {
+        fs::read_dir(dir).map_err(TopCratesError::io(format!(
+            "Unable to read the advisory database in {}",
+            dir.display()
+        )))
+    };
+
+    let mut advisories = BTreeMap::<_, Vec<_>>::new();
+    let mut errors = Vec::new();
+    for krate in read_dir(&crates)? {
+        let krate = krate.map_err(TopCratesError::io("Unable to list the advisory database"))?;
+        if !krate.path().is_dir() {
+            continue;
+        }
+        for file in read_dir(&krate.path())? {
+            let path = file
+                .map_err(TopCratesError::io("Unable to list the advisory database"))?
+                .path();
+            let markdown = match path.extension().and_then(|e| e.to_str()) {
+                Some("md") => true,
+                Some("toml") => false,
+                _ => continue,
+            };
+            let content = fs::read_to_string(&path).map_err(TopCratesError::io(format!(
+                "Unable to read advisory {}",
+                path.display()
+            )))?;
+            match Advisory::parse(&content, markdown) {
+                Ok(Some(advisory)) => advisories
+                    .entry(advisory.package.clone())
+                    .or_default()
+                    .push(advisory),
+                Ok(None) => {}
+                Err(e) => errors.push(format!("Unable to parse {}: {}", path.display(), e)),
+            }
+        }
+    }
+    for advisories in advisories.values_mut() {
+        advisories.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+    Ok((advisories, errors))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn affected_versions() {
+        let content = r#"
+```toml
+[advisory]
+id = "RUSTSEC-2021-0003"
+package = "smallvec"
+date = "2021-01-08"
+
+[versions]
+patched = [">= 1.6.1"]
+unaffected = ["< 1.0.0"]
+```
+
+# Buffer overflow in `SmallVec::insert_many`
+
+A bug in `insert_many` could write past the end of the buffer.
+"#;
+        let advisory = Advisory::parse(content, true).unwrap().unwrap();
+        assert_eq!(advisory.id, "RUSTSEC-2021-0003");
+        assert_eq!(
+            advisory.title.as_deref(),
+            Some("Buffer overflow in `SmallVec::insert_many`")
+        );
+        assert!(advisory.affects(&"1.6.0".parse().unwrap()));
+        assert!(!advisory.affects(&"1.6.1".parse().unwrap()));
+        assert!(!advisory.affects(&"0.6.14".parse().unwrap()));
+
+        let withdrawn = r#"
+[advisory]
+id = "RUSTSEC-2020-0999"
+package = "tokio"
+title = "Not actually a problem"
+withdrawn = "2020-12-01"
+"#;
+        assert_eq!(Advisory::parse(withdrawn, false), Ok(None));
+        assert!(Advisory::parse("# No front matter", true).is_err());
+    }
+
+    #[test]
+    fn database_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let krate = dir.path().join("crates").join("time");
+        fs::create_dir_all(&krate).unwrap();
+        fs::write(
+            krate.join("RUSTSEC-2020-0071.md"),
+            "```toml\n[advisory]\nid = \"RUSTSEC-2020-0071\"\npackage = \"time\"\n\n\
+             [versions]\npatched = [\">= 0.2.23\"]\n```\n\n# Potential segfault\n",
+        )
+        .unwrap();
+        fs::write(krate.join("README.txt"), "ignored").unwrap();
+        fs::write(krate.join("RUSTSEC-2099-0001.md"), "not an advisory").unwrap();
+
+        let (advisories, errors) = load(dir.path()).unwrap();
+        assert_eq!(advisories["time"].len(), 1);
+        assert_eq!(
+            advisories["time"][0].title.as_deref(),
+            Some("Potential segfault")
+        );
+        assert_eq!(errors.len(), 1);
+    }
+}
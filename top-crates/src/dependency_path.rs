@@ -0,0 +1,85 @@
+use semver::Version;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How a crate came to be included: the shortest chain of dependencies
+/// from a top-level crate to each of its versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub name: String,
+    /// Each path starts at a top-level crate and ends at a version of
+    /// the crate. There are none when the crate wasn't resolved.
+    pub paths: Vec<Vec<(String, Version)>>,
+}
+
+/// Searches breadth-first from all of `roots` at once, so the path
+/// found to `target` is one of the shortest from any of them.
+pub(crate) fn shortest<T, I>(
+    roots: impl IntoIterator<Item = T>,
+    target: T,
+    mut dependencies: impl FnMut(T) -> I,
+) -> Option<Vec<T>>
+where
+    T: Ord + Copy,
+    I: IntoIterator<Item = T>,
+{
+    let mut parents = BTreeMap::new();
+    let mut visited = BTreeSet::new();
+    let mut to_visit = Vec::new();
+    for root in roots {
+        if visited.insert(root) {
+            to_visit.push(root);
+        }
+    }
+
+    while !to_visit.is_empty() && !visited.contains(&target) {
+        let mut visit_next = Vec::new();
+        for node in to_visit {
+            for dependency in dependencies(node) {
+                if visited.insert(dependency) {
+                    parents.insert(dependency, node);
+                    visit_next.push(dependency);
+                }
+            }
+        }
+        to_visit = visit_next;
+    }
+
+    if !visited.contains(&target) {
+        return None;
+    }
+    let mut path = vec![target];
+    while let Some(&parent) = parents.get(path.last()?) {
+        path.push(parent);
+    }
+    path.reverse();
+    Some(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shortest_path_from_any_root() {
+        let graph = BTreeMap::from([
+            ("a", vec!["b"]),
+            ("b", vec!["c"]),
+            ("c", vec!["target"]),
+            ("d", vec!["target"]),
+            ("target", vec![]),
+            ("unused", vec![]),
+        ]);
+        let dependencies = |node| graph[node].iter().copied();
+
+        assert_eq!(
+            shortest(["a", "d"], "target", dependencies),
+            Some(vec!["d", "target"])
+        );
+        assert_eq!(
+            shortest(["a"], "target", dependencies),
+            Some(vec!["a", "b", "c", "target"])
+        );
+        assert_eq!(shortest(["a"], "a", dependencies), Some(vec!["a"]));
+        assert_eq!(shortest(["a", "d"], "unused", dependencies), None);
+    }
+}
@@ -0,0 +1,245 @@
+use serde::Serialize;
+use std::fmt;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Worth knowing about but never a problem on its own.
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Note => "note".fmt(f),
+            Severity::Warning => "warning".fmt(f),
+            Severity::Error => "error".fmt(f),
+        }
+    }
+}
+
+/// A single problem noticed while generating the crate list.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The crate the problem is about, if any.
+    pub krate: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        if let Some(krate) = &self.krate {
+            write!(f, "{}: ", krate)?;
+        }
+        self.message.fmt(f)
+    }
+}
+
+impl Diagnostic {
+    /// Formats the diagnostic as a GitHub Actions workflow command so
+    /// that it is shown as an annotation.
+    pub fn github_annotation(&self) -> String {
+        let command = match self.severity {
+            Severity::Note => "notice",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let mut annotation = format!("::{}", command);
+        if let Some(krate) = &self.krate {
+            annotation += &format!(" title={}", escape_property(krate));
+        }
+        annotation += "::";
+        annotation += &escape_data(&self.message);
+        annotation
+    }
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Collects the warnings and errors emitted while generating the
+/// crate list so that they can be inspected once the run is complete.
+///
+/// A streaming collector also prints each diagnostic as soon as it is
+/// recorded; otherwise nothing is printed until [`Diagnostics::report`].
+/// A quiet one never prints notes, though it still collects them.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    stream: bool,
+    quiet: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn streaming() -> Self {
+        Diagnostics {
+            stream: true,
+            ..Self::default()
+        }
+    }
+
+    /// Stops notes from being printed.
+    pub fn quiet(self) -> Self {
+        Diagnostics {
+            quiet: true,
+            ..self
+        }
+    }
+
+    fn printed(&self, diagnostic: &Diagnostic) -> bool {
+        !self.quiet || diagnostic.severity > Severity::Note
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        if self.stream && self.printed(&diagnostic) {
+            eprintln!("{}", diagnostic);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn add(&mut self, severity: Severity, krate: Option<String>, message: String) {
+        self.push(Diagnostic {
+            severity,
+            krate,
+            message,
+        });
+    }
+
+    pub fn note(&mut self, message: impl Into<String>) {
+        self.add(Severity::Note, None, message.into());
+    }
+
+    pub fn note_for(&mut self, krate: impl fmt::Display, message: impl Into<String>) {
+        self.add(Severity::Note, Some(krate.to_string()), message.into());
+    }
+
+    pub fn warn(&mut self, message: impl Into<String>) {
+        self.add(Severity::Warning, None, message.into());
+    }
+
+    pub fn warn_for(&mut self, krate: impl fmt::Display, message: impl Into<String>) {
+        self.add(Severity::Warning, Some(krate.to_string()), message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.add(Severity::Error, None, message.into());
+    }
+
+    pub fn error_for(&mut self, krate: impl fmt::Display, message: impl Into<String>) {
+        self.add(Severity::Error, Some(krate.to_string()), message.into());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn has_warnings(&self) -> bool {
+        self.count(Severity::Warning) != 0
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) != 0
+    }
+
+    /// Print every collected diagnostic that has not already been
+    /// streamed.
+    pub fn report(&self) {
+        if self.stream {
+            return;
+        }
+        for diagnostic in self.iter().filter(|d| self.printed(d)) {
+            eprintln!("{}", diagnostic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn github_annotations() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warn_for("serde 1.0.0 (registry+https://x)", "50% bad\nmetadata");
+        diagnostics.note_for("rand", "nothing to see");
+        diagnostics.error("no crates");
+
+        let shown = diagnostics
+            .iter()
+            .map(Diagnostic::github_annotation)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            [
+                "::warning title=serde 1.0.0 (registry+https%3A//x)::50%25 bad%0Ametadata",
+                "::notice title=rand::nothing to see",
+                "::error::no crates",
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_by_severity() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warn("one");
+        diagnostics.warn_for("serde", "two");
+        diagnostics.error("three");
+        diagnostics.note_for("rand", "four");
+
+        assert_eq!(diagnostics.count(Severity::Warning), 2);
+        assert_eq!(diagnostics.count(Severity::Error), 1);
+        assert!(diagnostics.has_warnings());
+        assert!(diagnostics.has_errors());
+    }
+
+    #[test]
+    fn displays_crate_context() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warn_for("serde 1.0.0", "bad metadata");
+        diagnostics.error("no crates");
+
+        let shown = diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            ["warning: serde 1.0.0: bad metadata", "error: no crates"]
+        );
+    }
+
+    #[test]
+    fn quiet_collects_notes_without_printing_them() {
+        let mut diagnostics = Diagnostics::new().quiet();
+        diagnostics.note("resolved 3 crates");
+        diagnostics.warn("no advisories");
+
+        let printed = diagnostics
+            .iter()
+            .filter(|d| diagnostics.printed(d))
+            .map(|d| d.severity)
+            .collect::<Vec<_>>();
+        assert_eq!(diagnostics.count(Severity::Note), 1);
+        assert_eq!(printed, [Severity::Warning]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Describes how a regenerated manifest differs from an existing one.
+
+use itertools::Itertools;
+use semver::Version;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+use toml::Value;
+
+/// The sections compared, and whether their nested tables are
+/// flattened into one line per value or kept as one line per entry.
+const SECTIONS: &[(&str, bool)] = &[("dependencies", false), ("profile", true)];
+
+/// Produces unified-diff-like hunks of the lines that were removed
+/// from `old` or added in `new`, one hunk per changed section. Nothing
+/// is returned when the sections are the same.
+pub fn manifest_diff(old: &Value, new: &Value) -> String {
+    let mut diff = String::new();
+
+    for &(section, flatten) in SECTIONS {
+        let lines = |manifest: &Value| {
+            let mut lines = BTreeMap::new();
+            if let Some(table) = manifest.get(section) {
+                collect_lines(section, table, flatten, &mut lines);
+            }
+            lines
+        };
+        let old = lines(old);
+        let new = lines(new);
+        if old == new {
+            continue;
+        }
+
+        writeln!(diff, "@@ [{}] @@", section).unwrap();
+        let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+        for key in keys {
+            match (old.get(key), new.get(key)) {
+                (Some(o), Some(n)) if o == n => {}
+                (o, n) => {
+                    if let Some(o) = o {
+                        writeln!(diff, "-{}", o).unwrap();
+                    }
+                    if let Some(n) = n {
+                        writeln!(diff, "+{}", n).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+/// Lists the crates that were added, removed, or changed version
+/// between `old` and `new`, each a crate name with one of its
+/// versions, for writing release notes. Nothing is returned when they
+/// have the same crates at the same versions.
+pub fn crate_changelog(
+    old: impl IntoIterator<Item = (String, Version)>,
+    new: impl IntoIterator<Item = (String, Version)>,
+) -> String {
+    fn by_name(
+        crates: impl IntoIterator<Item = (String, Version)>,
+    ) -> BTreeMap<String, BTreeSet<Version>> {
+        let mut versions = BTreeMap::<_, BTreeSet<_>>::new();
+        for (name, version) in crates {
+            versions.entry(name).or_default().insert(version);
+        }
+        versions
+    }
+
+    let old = by_name(old);
+    let new = by_name(new);
+    let mut added = String::new();
+    let mut removed = String::new();
+    let mut changed = String::new();
+    for name in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+        match (old.get(name), new.get(name)) {
+            (Some(o), Some(n)) if o == n => {}
+            (Some(o), Some(n)) => writeln!(
+                changed,
+                "  {} {} -> {}",
+                name,
+                o.iter().join(", "),
+                n.iter().join(", ")
+            )
+            .unwrap(),
+            (Some(o), None) => writeln!(removed, "  {} {}", name, o.iter().join(", ")).unwrap(),
+            (None, Some(n)) => writeln!(added, "  {} {}", name, n.iter().join(", ")).unwrap(),
+            (None, None) => unreachable!("Every name comes from one of them"),
+        }
+    }
+
+    let mut changelog = String::new();
+    for (heading, lines) in [("Added", added), ("Removed", removed), ("Changed", changed)] {
+        if !lines.is_empty() {
+            writeln!(changelog, "{}:", heading).unwrap();
+            changelog += &lines;
+        }
+    }
+    changelog
+}
+
+fn collect_lines(prefix: &str, value: &Value, flatten: bool, lines: &mut BTreeMap<String, String>) {
+    let table = match value.as_table() {
+        Some(table) => table,
+        None => return,
+    };
+    for (key, value) in table {
+        match value {
+            Value::Table(_) if flatten => {
+                collect_lines(&format!("{}.{}", prefix, key), value, flatten, lines)
+            }
+            _ => {
+                let key = if flatten {
+                    format!("{}.{}", prefix, key)
+                } else {
+                    key.clone()
+                };
+                let line = format!("{} = {}", key, inline(value));
+                lines.insert(key, line);
+            }
+        }
+    }
+}
+
+/// Formats a value the way it would appear on one line of TOML.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Table(table) => {
+            let entries = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, inline(value)))
+                .collect::<Vec<_>>();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Value::Array(values) => {
+            let values = values.iter().map(inline).collect::<Vec<_>>();
+            format!("[{}]", values.join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changed_dependencies_and_profiles() {
+        let old: Value = toml::from_str(
+            r#"
+            [dependencies]
+            rand = "=0.8.4"
+            regex = "=1.7.0"
+            serde = { version = "=1.0.152", features = ["derive"] }
+
+            [profile.dev]
+            codegen-units = 1
+            incremental = false
+            "#,
+        )
+        .unwrap();
+        let new: Value = toml::from_str(
+            r#"
+            [dependencies]
+            rand = "=0.8.5"
+            regex = "=1.7.0"
+            serde = { version = "=1.0.152", features = ["derive"] }
+            tokio = "=1.24.1"
+
+            [profile.dev]
+            codegen-units = 1
+            incremental = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest_diff(&old, &new),
+            "@@ [dependencies] @@\n\
+             -rand = \"=0.8.4\"\n\
+             +rand = \"=0.8.5\"\n\
+             +tokio = \"=1.24.1\"\n\
+             @@ [profile] @@\n\
+             -profile.dev.incremental = false\n\
+             +profile.dev.incremental = true\n"
+        );
+        assert_eq!(manifest_diff(&new, &new), "");
+    }
+
+    #[test]
+    fn added_removed_and_changed_crates() {
+        let crates = |crates: &[(&str, &str)]| {
+            crates
+                .iter()
+                .map(|&(name, version)| (name.to_owned(), version.parse().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let old = crates(&[
+            ("rand", "0.8.4"),
+            ("openssl", "0.10.45"),
+            ("serde", "1.0.152"),
+            ("syn", "1.0.107"),
+            ("syn", "2.0.0"),
+        ]);
+        let new = crates(&[
+            ("rand", "0.8.5"),
+            ("serde", "1.0.152"),
+            ("syn", "2.0.0"),
+            ("tokio", "1.24.1"),
+        ]);
+
+        assert_eq!(
+            crate_changelog(old.clone(), new),
+            "Added:\n\
+             \x20 tokio 1.24.1\n\
+             Removed:\n\
+             \x20 openssl 0.10.45\n\
+             Changed:\n\
+             \x20 rand 0.8.4 -> 0.8.5\n\
+             \x20 syn 1.0.107, 2.0.0 -> 2.0.0\n"
+        );
+        assert_eq!(crate_changelog(old.clone(), old), "");
+    }
+}
@@ -0,0 +1,102 @@
+use semver::Version;
+use std::fmt;
+
+/// How much the resolved crates take up as downloaded `.crate` files.
+#[derive(Debug, Default, Clone)]
+pub struct DownloadSize {
+    /// The size of every `.crate` file that was found, in bytes.
+    pub total: u64,
+    /// How many crates had no `.crate` file to measure, such as those
+    /// that are patched to come from elsewhere.
+    pub unmeasured: usize,
+    /// The largest crates, largest first, with their sizes in bytes.
+    pub largest: Vec<(String, Version, u64)>,
+}
+
+impl DownloadSize {
+    /// How many of the largest crates are kept.
+    pub const LARGEST: usize = 10;
+
+    /// Adds up the size of each crate, where it is known.
+    pub fn new(sizes: impl IntoIterator<Item = (String, Version, Option<u64>)>) -> Self {
+        let mut download_size = DownloadSize::default();
+        let mut measured = Vec::new();
+        for (name, version, size) in sizes {
+            match size {
+                Some(size) => {
+                    download_size.total += size;
+                    measured.push((name, version, size));
+                }
+                None => download_size.unmeasured += 1,
+            }
+        }
+        measured.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (&a.0, &a.1).cmp(&(&b.0, &b.1))));
+        measured.truncate(Self::LARGEST);
+        download_size.largest = measured;
+        download_size
+    }
+}
+
+/// The total, followed by a line for each of the largest crates.
+impl fmt::Display for DownloadSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The crates take up {} to download",
+            human_size(self.total)
+        )?;
+        if self.unmeasured > 0 {
+            write!(f, " ({} crates could not be measured)", self.unmeasured)?;
+        }
+        if self.largest.is_empty() {
+            return writeln!(f);
+        }
+        writeln!(f, "; the largest are:")?;
+        for (name, version, size) in &self.largest {
+            writeln!(f, "  {:>9}  {} {}", human_size(*size), name, version)?;
+        }
+        Ok(())
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn largest_first() {
+        let crate_size = |name: &str, size| (name.to_owned(), Version::new(1, 0, 0), size);
+        let sizes = (0..12).map(|i| crate_size("small", Some(i))).chain([
+            crate_size("huge", Some(5 * 1024 * 1024)),
+            crate_size("patched", None),
+        ]);
+
+        let download_size = DownloadSize::new(sizes);
+
+        assert_eq!(download_size.total, 5 * 1024 * 1024 + 66);
+        assert_eq!(download_size.unmeasured, 1);
+        assert_eq!(download_size.largest.len(), DownloadSize::LARGEST);
+        assert_eq!(download_size.largest[0].0, "huge");
+        assert_eq!(download_size.largest[1].2, 11);
+        assert_eq!(human_size(download_size.total), "5.0 MiB");
+        assert_eq!(human_size(512), "512 B");
+    }
+}
@@ -0,0 +1,383 @@
+use cargo::util::errors::HttpNotSuccessful;
+use std::{error::Error, fmt, io, time::Duration};
+
+/// Everything that can stop the crate list from being generated.
+///
+/// Each variant that wraps another error also describes what was
+/// being attempted when it happened.
+pub enum TopCratesError {
+    /// An HTTP request could not be made.
+    Network {
+        context: String,
+        source: reqwest::Error,
+    },
+    /// An HTTP request was answered with an unsuccessful status.
+    HttpStatus {
+        context: String,
+        status: reqwest::StatusCode,
+        body: String,
+        /// How long the server asked to be waited for before trying
+        /// again, such as when rate limiting.
+        retry_after: Option<Duration>,
+    },
+    /// Cargo could not set up, query, or download from the registry.
+    Registry {
+        context: String,
+        source: anyhow::Error,
+    },
+    /// A line of a list of crates to use is not a crate name,
+    /// optionally followed by `@` and a version requirement.
+    InvalidCrateList {
+        line: usize,
+        message: String,
+    },
+    /// The registry listed far fewer top crates than were asked for,
+    /// which is more likely a problem with its API than the truth.
+    TooFewTopCrates {
+        fetched: usize,
+        count: usize,
+    },
+    /// Curated additions that the registry has never heard of.
+    UnknownAdditions {
+        names: Vec<String>,
+    },
+    /// A downloaded package is not what the registry says was
+    /// published.
+    ChecksumMismatch {
+        id: String,
+        expected: String,
+        actual: String,
+    },
+    /// A package has no library for the playground to use.
+    NoLibrary {
+        id: String,
+    },
+    /// Something that has to be downloaded was needed while working
+    /// offline.
+    Offline {
+        what: String,
+    },
+    /// Cargo rejected the generated manifest.
+    InvalidManifest {
+        message: String,
+    },
+    /// The generated manifest and crate information don't describe
+    /// the same crates, which is a bug.
+    Inconsistent {
+        mismatches: Vec<String>,
+    },
+    /// The crates could not be resolved together.
+    Resolution {
+        source: anyhow::Error,
+    },
+    Io {
+        context: String,
+        source: io::Error,
+    },
+    Json {
+        context: String,
+        source: serde_json::Error,
+    },
+    TomlParse {
+        context: String,
+        source: toml::de::Error,
+    },
+    TomlSerialize {
+        context: String,
+        source: toml::ser::Error,
+    },
+}
+
+impl TopCratesError {
+    /// Whether trying again later might succeed, such as after a
+    /// timeout, a server error, or being rate limited.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            TopCratesError::Network { source, .. } => source.is_timeout() || source.is_connect(),
+            TopCratesError::HttpStatus { status, .. } => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            TopCratesError::Registry { source, .. } => is_spurious(source),
+            _ => false,
+        }
+    }
+
+    /// Whether the local copy of the registry index looks to be
+    /// damaged, such as a missing git object or an entry that isn't
+    /// valid JSON, rather than the registry being unreachable.
+    pub fn is_index_corruption(&self) -> bool {
+        match self {
+            TopCratesError::Registry { source, .. } => source.chain().any(|cause| {
+                if let Some(e) = cause.downcast_ref::<git2::Error>() {
+                    return matches!(e.class(), git2::ErrorClass::Odb | git2::ErrorClass::Object);
+                }
+                if let Some(e) = cause.downcast_ref::<io::Error>() {
+                    return e.kind() == io::ErrorKind::InvalidData;
+                }
+                cause.is::<serde_json::Error>()
+            }),
+            _ => false,
+        }
+    }
+
+    /// How long the server asked to be waited for before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TopCratesError::HttpStatus { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn network(context: impl Into<String>) -> impl FnOnce(reqwest::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::Network { context, source }
+    }
+
+    pub fn registry(context: impl Into<String>) -> impl FnOnce(anyhow::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::Registry { context, source }
+    }
+
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::Io { context, source }
+    }
+
+    pub fn json(context: impl Into<String>) -> impl FnOnce(serde_json::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::Json { context, source }
+    }
+
+    pub fn toml_parse(context: impl Into<String>) -> impl FnOnce(toml::de::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::TomlParse { context, source }
+    }
+
+    pub fn toml_serialize(context: impl Into<String>) -> impl FnOnce(toml::ser::Error) -> Self {
+        let context = context.into();
+        move |source| TopCratesError::TomlSerialize { context, source }
+    }
+}
+
+/// The `[package.metadata.playground]` table of a crate could not be
+/// understood, so the crate's default features are used instead.
+#[derive(Debug)]
+pub struct MetadataError {
+    pub name: String,
+    pub version: semver::Version,
+    /// The offending table, as TOML.
+    pub metadata: String,
+    pub source: Box<dyn Error + Send + Sync>,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to parse the playground metadata of {} {}: {}\n{}",
+            self.name,
+            self.version,
+            self.source,
+            self.metadata.trim_end(),
+        )
+    }
+}
+
+impl Error for MetadataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// The same errors cargo itself considers worth retrying, apart from
+/// those of curl, which isn't available to check for.
+fn is_spurious(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<git2::Error>() {
+            return matches!(
+                e.class(),
+                git2::ErrorClass::Net
+                    | git2::ErrorClass::Os
+                    | git2::ErrorClass::Zlib
+                    | git2::ErrorClass::Http
+            );
+        }
+        if let Some(e) = cause.downcast_ref::<HttpNotSuccessful>() {
+            return (500..600).contains(&e.code);
+        }
+        false
+    })
+}
+
+impl fmt::Display for TopCratesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TopCratesError::*;
+
+        // Cargo's errors keep most of their detail in the chain of
+        // causes, which the alternate format includes.
+        match self {
+            Network { context, source } => write!(f, "{}: {}", context, source),
+            HttpStatus {
+                context,
+                status,
+                body,
+                ..
+            } => write!(f, "{}; HTTP status was {}: {}", context, status, body),
+            Registry { context, source } => write!(f, "{}: {:#}", context, source),
+            InvalidCrateList { line, message } => {
+                write!(f, "Invalid crate list, on line {}: {}", line, message)
+            }
+            TooFewTopCrates { fetched, count } => write!(
+                f,
+                "Only {} of the top {} crates were listed by the registry; \
+                 pass --allow-empty to use them anyway",
+                fetched, count
+            ),
+            UnknownAdditions { names } => write!(
+                f,
+                "The registry has no crates named {} \
+                 (are they misspelled in the additions of crate-modifications.toml?)",
+                names.join(", ")
+            ),
+            ChecksumMismatch {
+                id,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The download of {} has checksum {}, but the registry says it is {}",
+                id, actual, expected
+            ),
+            NoLibrary { id } => write!(f, "{} did not have a library", id),
+            Offline { what } => write!(f, "{} is not available offline", what),
+            InvalidManifest { message } => {
+                write!(f, "Cargo rejected the generated manifest: {}", message)
+            }
+            Inconsistent { mismatches } => write!(
+                f,
+                "Cargo.toml and crate-information.json disagree: {}",
+                mismatches.join("; ")
+            ),
+            Resolution { source } => write!(f, "Unable to resolve dependencies: {:#}", source),
+            Io { context, source } => write!(f, "{}: {}", context, source),
+            Json { context, source } => write!(f, "{}: {}", context, source),
+            TomlParse { context, source } => write!(f, "{}: {}", context, source),
+            TomlSerialize { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+/// Shows the same message as `Display` so that returning the error
+/// from `main` prints something readable.
+impl fmt::Debug for TopCratesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for TopCratesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use TopCratesError::*;
+
+        match self {
+            Network { source, .. } => Some(source),
+            Registry { source, .. } | Resolution { source } => Some(source.as_ref()),
+            Io { source, .. } => Some(source),
+            Json { source, .. } => Some(source),
+            TomlParse { source, .. } => Some(source),
+            TomlSerialize { source, .. } => Some(source),
+            HttpStatus { .. }
+            | InvalidCrateList { .. }
+            | TooFewTopCrates { .. }
+            | UnknownAdditions { .. }
+            | ChecksumMismatch { .. }
+            | NoLibrary { .. }
+            | InvalidManifest { .. }
+            | Inconsistent { .. }
+            | Offline { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages_fit_on_one_line() {
+        let cause = anyhow::anyhow!("network down").context("failed to update the index");
+        let error = TopCratesError::registry("Unable to query registry for serde")(cause);
+        assert_eq!(
+            error.to_string(),
+            "Unable to query registry for serde: failed to update the index: network down"
+        );
+        assert_eq!(format!("{:?}", error), error.to_string());
+    }
+
+    #[test]
+    fn rate_limiting_is_transient() {
+        let rate_limited = TopCratesError::HttpStatus {
+            context: "Could not download top crates".to_owned(),
+            status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+            retry_after: Some(Duration::from_secs(30)),
+        };
+        assert!(rate_limited.is_transient());
+        assert_eq!(rate_limited.retry_after(), Some(Duration::from_secs(30)));
+
+        let forbidden = TopCratesError::HttpStatus {
+            context: "Could not download top crates".to_owned(),
+            status: reqwest::StatusCode::FORBIDDEN,
+            body: String::new(),
+            retry_after: None,
+        };
+        assert!(!forbidden.is_transient());
+    }
+
+    #[test]
+    fn transient_registry_errors() {
+        let server_error = HttpNotSuccessful {
+            code: 503,
+            url: "https://index.crates.io/se/rd/serde".to_owned(),
+            body: vec![],
+        };
+        let error = TopCratesError::registry("query")(anyhow::Error::new(server_error));
+        assert!(error.is_transient());
+
+        let not_found = HttpNotSuccessful {
+            code: 404,
+            url: "https://index.crates.io/se/rd/serde".to_owned(),
+            body: vec![],
+        };
+        let error = TopCratesError::registry("query")(anyhow::Error::new(not_found));
+        assert!(!error.is_transient());
+
+        let cause = anyhow::Error::new(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Net,
+            "connection reset",
+        ))
+        .context("failed to fetch the index");
+        assert!(TopCratesError::registry("query")(cause).is_transient());
+    }
+
+    #[test]
+    fn index_corruption() {
+        let missing_object = anyhow::Error::new(git2::Error::new(
+            git2::ErrorCode::NotFound,
+            git2::ErrorClass::Odb,
+            "object not found - no match for id",
+        ))
+        .context("failed to load the index entry for serde");
+        let error = TopCratesError::registry("query")(missing_object);
+        assert!(error.is_index_corruption());
+        assert!(!error.is_transient());
+
+        let reset = anyhow::Error::new(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Net,
+            "connection reset",
+        ));
+        assert!(!TopCratesError::registry("query")(reset).is_index_corruption());
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// The exposed crates with the most features enabled, which are the
+/// likeliest to be slowing the playground's build down.
+#[derive(Debug, Default, Clone)]
+pub struct FeatureCounts {
+    /// The crates with features enabled, most first, with how many and
+    /// whether their default features are also enabled.
+    pub most: Vec<(String, usize, bool)>,
+}
+
+impl FeatureCounts {
+    /// How many of the crates with the most features are kept.
+    pub const MOST: usize = 10;
+
+    /// Ranks each crate by the number of features it is exposed with.
+    pub fn new(counts: impl IntoIterator<Item = (String, usize, bool)>) -> Self {
+        let mut most = counts
+            .into_iter()
+            .filter(|&(_, count, _)| count > 0)
+            .collect::<Vec<_>>();
+        most.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        most.truncate(Self::MOST);
+        FeatureCounts { most }
+    }
+}
+
+/// A line for each of the crates with the most features, if any have
+/// features enabled.
+impl fmt::Display for FeatureCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.most.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "The crates with the most features enabled are:")?;
+        for (name, count, default_features) in &self.most {
+            let defaults = if *default_features {
+                " and the default features"
+            } else {
+                ""
+            };
+            writeln!(f, "  {:>4}  {}{}", count, name, defaults)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn most_first() {
+        let counts = (0..12)
+            .map(|i| (format!("crate{:02}", i), i, true))
+            .chain([("tokio".to_owned(), 20, false)]);
+
+        let feature_counts = FeatureCounts::new(counts);
+
+        assert_eq!(feature_counts.most.len(), FeatureCounts::MOST);
+        assert_eq!(feature_counts.most[0], ("tokio".to_owned(), 20, false));
+        assert_eq!(feature_counts.most[1].0, "crate11");
+        assert!(feature_counts.most.iter().all(|&(_, count, _)| count > 0));
+        assert_eq!(
+            feature_counts.to_string().lines().nth(1),
+            Some("    20  tokio")
+        );
+        assert_eq!(
+            FeatureCounts::new([("log".to_owned(), 0, true)]).to_string(),
+            ""
+        );
+    }
+}
@@ -2,36 +2,474 @@
 
 use cargo::{
     core::{
-        compiler::{CompileKind, CompileTarget, TargetInfo},
+        compiler::{CompileKind, CompileTarget, CrateType, RustcTargetData, TargetInfo},
+        dependency::DepKind,
         package::PackageSet,
         registry::PackageRegistry,
-        resolver::{self, features::RequestedFeatures, ResolveOpts, VersionPreferences},
+        resolver::{
+            self,
+            features::{
+                self, CliFeatures, FeatureOpts, FeaturesFor, HasDevUnits, RequestedFeatures,
+            },
+            Resolve, ResolveBehavior, ResolveOpts, VersionPreferences,
+        },
         source::SourceMap,
-        Dependency, Package, PackageId, QueryKind, Source, SourceId, Summary, Target,
+        Dependency, Edition, EitherManifest, FeatureValue, Package, PackageId, PackageIdSpec,
+        QueryKind, Shell, Source, SourceId, Summary, Target, TargetKind, Workspace,
     },
     sources::RegistrySource,
-    util::{interning::InternedString, Config, VersionExt},
+    util::{
+        config::PackageCacheLock, interning::InternedString, short_hash, toml::read_manifest,
+        Config, IntoUrl, VersionExt,
+    },
 };
+use cargo_util::Sha256;
 use itertools::Itertools;
-use semver::Version;
+use log::{debug, info};
+use regex::Regex;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, BTreeSet, HashSet},
-    io::Read,
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashSet},
+    env, fmt,
+    fs::{self, File},
+    io::BufReader,
     mem,
+    path::{Path, PathBuf},
     rc::Rc,
     task::Poll,
+    time::{Duration, Instant},
+};
+
+pub use dependency_path::Explanation;
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use diff::{crate_changelog, manifest_diff};
+pub use download_size::DownloadSize;
+pub use error::{MetadataError, TopCratesError};
+pub use feature_counts::FeatureCounts;
+pub use manifest::{
+    BuildOverride, PackageProfile, Patch, Profile, Profiles, TomlManifest, TomlPackage, TomlTarget,
 };
+pub use report::markdown_report;
+pub use resolution::ResolvedPackage;
+
+mod advisories;
+mod dependency_path;
+mod diagnostics;
+mod diff;
+mod download_size;
+mod error;
+mod feature_counts;
+mod license;
+mod manifest;
+mod progress;
+mod provenance;
+mod query_cache;
+mod report;
+mod resolution;
+mod retry;
+mod schema;
+mod timings;
+mod top_crates_cache;
+mod trim;
+
+use progress::Progress;
+pub use provenance::{FeatureProvenance, FeatureSource, FeatureUnification};
+use query_cache::QueryCache;
+use retry::Backoff;
+pub use schema::CRATE_INFORMATION_SCHEMA;
+pub use timings::Timings;
+use top_crates_cache::TopCratesCache;
+pub use trim::TrimSuggestion;
 
 const PLAYGROUND_TARGET_PLATFORM: &str = "x86_64-unknown-linux-gnu";
 
+/// Overrides the `User-Agent` sent with every HTTP request.
+const USER_AGENT_ENV: &str = "TOP_CRATES_USER_AGENT";
+const DEFAULT_USER_AGENT: &str = "rust-playground-top-crates (admin@play.rust-lang.org)";
+
+/// How many seconds to wait for a connection to a server before
+/// giving up on it.
+const CONNECT_TIMEOUT_ENV: &str = "TOP_CRATES_CONNECT_TIMEOUT";
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many seconds an HTTP request may take from start to finish,
+/// so that a stalled response fails instead of hanging.
+const TIMEOUT_ENV: &str = "TOP_CRATES_TIMEOUT";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The index URL of a registry to use instead of crates.io, when
+/// [`GenerateOptions::registry`] is not set.
+const REGISTRY_ENV: &str = "TOP_CRATES_REGISTRY";
+const CRATES_IO_API: &str = "https://crates.io";
+
+/// Where crates.io's web API is, such as a local mock or a staging
+/// instance, when the index is crates.io's.
+const CRATES_IO_API_ENV: &str = "CRATES_IO_API_BASE";
+
+/// Where to find how many registry queries to have in flight at once,
+/// when [`GenerateOptions::jobs`] is not set.
+const CONCURRENCY_ENV: &str = "TOP_CRATES_CONCURRENCY";
+/// How many registry queries are in flight at once when neither
+/// [`GenerateOptions::jobs`] nor [`CONCURRENCY_ENV`] is set.
+const DEFAULT_CONCURRENCY: usize = 8;
+
 struct GlobalState<'cfg> {
     config: &'cfg Config,
     target_info: TargetInfo,
     registry: PackageRegistry<'cfg>,
-    crates_io: SourceId,
+    /// The registry crates are found in, crates.io unless a mirror
+    /// was asked for.
+    registry_id: SourceId,
+    /// Where the registry's web API is, for listing the top crates.
+    api: String,
     source: RegistrySource<'cfg>,
     modifications: &'cfg Modifications,
+    options: &'cfg GenerateOptions,
+    diagnostics: &'cfg mut Diagnostics,
+    /// Crates whose playground metadata was ignored because it could
+    /// not be parsed.
+    metadata_errors: BTreeMap<PackageId, MetadataError>,
+    query_cache: Option<QueryCache>,
+    http: Http,
+    backoff: Backoff,
+    /// The most registry queries to have in flight at once.
+    jobs: usize,
+    /// What we know about each top-level crate from where it was listed.
+    listings: BTreeMap<InternedString, Crate>,
+    /// Curated additions that would have been listed anyway.
+    redundant_additions: BTreeSet<InternedString>,
+    /// How many top-level crates were listed, before any were left out.
+    listed: usize,
+    /// The logins of the owners of each crate that has been looked up.
+    owners: BTreeMap<InternedString, Vec<String>>,
+    /// The registry packages of `GenerateOptions::locked_from`.
+    locked: BTreeSet<PackageId>,
+    /// Packages that were left out because they could not be
+    /// downloaded, with `GenerateOptions::skip_failed_downloads`.
+    failed_downloads: BTreeSet<PackageId>,
+    timings: Timings,
+}
+
+/// Where the Rust Cookbook's manifest is, whose dependencies are all
+/// included.
+pub const RUST_COOKBOOK_MANIFEST: &str =
+    "https://raw.githubusercontent.com/rust-lang-nursery/rust-cookbook/master/Cargo.toml";
+
+/// How many crates are included from each category when
+/// `category-count` isn't set.
+pub const DEFAULT_CATEGORY_COUNT: usize = 10;
+
+/// How many crates are included for each keyword when `keyword-count`
+/// isn't set.
+pub const DEFAULT_KEYWORD_COUNT: usize = 10;
+
+/// The largest page of crates that crates.io will return at once.
+pub const CRATES_IO_MAX_PAGE_SIZE: u32 = 100;
+
+/// How versions are written in the generated files
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum VersionFormat {
+    /// Exactly as published.
+    #[default]
+    Raw,
+    /// Only the `major.minor.patch` and prerelease parts, without any
+    /// build metadata.
+    Semver3,
+}
+
+impl std::str::FromStr for VersionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(VersionFormat::Raw),
+            "semver3" => Ok(VersionFormat::Semver3),
+            _ => Err(format!("unknown version format `{}`", s)),
+        }
+    }
+}
+
+impl VersionFormat {
+    fn normalize(self, version: &Version) -> Version {
+        match self {
+            VersionFormat::Raw => version.clone(),
+            VersionFormat::Semver3 => Version {
+                build: semver::BuildMetadata::EMPTY,
+                ..version.clone()
+            },
+        }
+    }
+}
+
+/// How crates.io orders the crates it lists
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TopCratesSort {
+    /// By how many times they have ever been downloaded.
+    #[default]
+    Downloads,
+    /// By how many times they were downloaded in the last 90 days.
+    RecentDownloads,
+    /// Most recently updated first.
+    RecentUpdates,
+    /// Most recently created first.
+    New,
+    /// Alphabetically.
+    Alpha,
+}
+
+impl std::str::FromStr for TopCratesSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "downloads" => Ok(TopCratesSort::Downloads),
+            "recent-downloads" => Ok(TopCratesSort::RecentDownloads),
+            "recent-updates" => Ok(TopCratesSort::RecentUpdates),
+            "new" => Ok(TopCratesSort::New),
+            "alpha" => Ok(TopCratesSort::Alpha),
+            _ => Err(format!(
+                "unknown sort `{}`; expected downloads, recent-downloads, \
+                 recent-updates, new, or alpha",
+                s
+            )),
+        }
+    }
+}
+
+impl TopCratesSort {
+    /// The value of the `sort` parameter that crates.io understands.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TopCratesSort::Downloads => "downloads",
+            TopCratesSort::RecentDownloads => "recent-downloads",
+            TopCratesSort::RecentUpdates => "recent-updates",
+            TopCratesSort::New => "new",
+            TopCratesSort::Alpha => "alpha",
+        }
+    }
+}
+
+/// A toolchain channel the playground offers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Channel {
+    Stable,
+    Beta,
+    Nightly,
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(Channel::Stable),
+            "beta" => Ok(Channel::Beta),
+            "nightly" => Ok(Channel::Nightly),
+            _ => Err(format!(
+                "unknown channel `{}`; expected stable, beta, or nightly",
+                s
+            )),
+        }
+    }
+}
+
+/// Read from a string, as the toml crate can't read table keys, such
+/// as those of `[channels.nightly]`, as an enum.
+impl<'de> Deserialize<'de> for Channel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+        }
+    }
+}
+
+/// Knobs that control how the crate list is generated
+#[derive(Debug)]
+pub struct GenerateOptions {
+    /// A previously-captured crates.io response to use instead of
+    /// downloading the current list of top crates.
+    pub top_crates_snapshot: Option<PathBuf>,
+    /// A `Cargo.lock` whose versions of the registry's crates are
+    /// preferred when resolving, so that transitive crates keep them
+    /// unless something needs another version.
+    pub locked_from: Option<PathBuf>,
+    /// The lines of a list of crates to use instead of the top crates,
+    /// each a crate name optionally followed by `@` and a version
+    /// requirement. Blank lines and those starting with `#` are
+    /// skipped.
+    pub crate_list: Option<Vec<String>>,
+    /// Use only the curated additions as top-level crates, leaving out
+    /// the top crates, the Rust Cookbook's crates, categories, and
+    /// keywords.
+    pub include_only: bool,
+    /// How many of the top crates on crates.io to include.
+    pub count: usize,
+    /// Carry on when crates.io lists fewer than half of the `count`
+    /// top crates, or when crates can't be listed or their owners
+    /// checked offline, rather than failing.
+    pub allow_empty: bool,
+    /// Fail when a resolved crate is affected by a vulnerability in
+    /// the advisory database, rather than only warning.
+    pub deny_advisories: bool,
+    /// How crates.io orders the crates before the top ones are taken.
+    pub sort: TopCratesSort,
+    /// How many crates to request from crates.io at once. Values
+    /// larger than [`CRATES_IO_MAX_PAGE_SIZE`] are clamped.
+    pub page_size: u32,
+    /// When a crate is included at several versions, enable the
+    /// union of their features on each of them.
+    pub unify_duplicate_features: bool,
+    /// Leave `default` among the features a crate is exposed with,
+    /// rather than folding it into `default-features`, so the features
+    /// are listed as they were enabled.
+    pub keep_default_feature: bool,
+    /// Resolve and expose every crate without its default features or
+    /// any others, to find crates that don't build without them.
+    /// Crates still get whatever the crates depending on them enable.
+    pub minimal_features: bool,
+    /// A directory to remember registry query results in between runs.
+    pub query_cache: Option<PathBuf>,
+    /// A file to remember the downloaded list of top crates in.
+    pub top_crates_cache: Option<PathBuf>,
+    /// How long a remembered list of top crates is used for.
+    pub top_crates_ttl: Duration,
+    /// Download the list of top crates even if a remembered one is
+    /// still young enough.
+    pub refresh_top_crates: bool,
+    /// Never use the network, relying on the remembered list of top
+    /// crates (however old) and what cargo has already downloaded.
+    pub offline: bool,
+    /// Use the registry index as it already is locally, without
+    /// updating it first, which may choose outdated versions.
+    pub skip_update: bool,
+    /// A git revision of the registry index to resolve against instead
+    /// of its newest, so that an earlier run can be reproduced. The
+    /// index is only updated when it doesn't have the revision yet.
+    pub index_rev: Option<String>,
+    /// The Rust version the playground provides. When set, top-level
+    /// crates use their newest version that declares it can be built
+    /// with it.
+    pub msrv: Option<Version>,
+    /// The newest edition the playground's Rust supports. When set,
+    /// top-level crates use their newest version written in it or an
+    /// older edition.
+    ///
+    /// With `msrv` as well, the newest version that satisfies both is
+    /// used; a version that only satisfies one of them is never
+    /// preferred over the newest.
+    pub max_edition: Option<Edition>,
+    /// Choose the newest version of every top-level crate, even when
+    /// it is a prerelease, rather than only for the crates listed in
+    /// `allow-prerelease`.
+    pub allow_prerelease: bool,
+    /// The index URL of a registry, such as a mirror, to use instead
+    /// of crates.io. The top crates are listed by the web API that the
+    /// index's `config.json` points to.
+    pub registry: Option<String>,
+    /// The URL of the Rust Cookbook's `Cargo.toml`.
+    pub rust_cookbook_manifest: String,
+    /// Suggest top-level crates to exclude to get the total number of
+    /// crates down to this.
+    pub trim_to: Option<usize>,
+    pub version_format: VersionFormat,
+    /// Work out why each feature of each crate is enabled.
+    pub feature_provenance: bool,
+    /// Work out which features are unified on the crates that other
+    /// crates depend on.
+    pub feature_unification: bool,
+    /// Describe every package of the full resolve and what it depends
+    /// on.
+    pub resolution_graph: bool,
+    /// Leave the crates that are only needed to build other crates,
+    /// such as the dependencies of build scripts, out of the resolution
+    /// graph and the download size. Cargo always resolves them, so the
+    /// generated files are the same either way.
+    pub exclude_build_dependencies: bool,
+    /// Find out which top-level crates pulled in the crate of this
+    /// name.
+    pub explain: Option<String>,
+    /// When not empty, only these crates (and their dependencies) are
+    /// included, whether or not they are among the top crates.
+    pub only: Vec<String>,
+    /// How many times to retry network requests and registry queries
+    /// that fail transiently.
+    pub retries: u32,
+    /// How many packages to download at once.
+    pub download_concurrency: usize,
+    /// Leave out packages that still fail to download after being
+    /// retried, along with the top-level crates that need them,
+    /// rather than stopping.
+    pub skip_failed_downloads: bool,
+    /// How many registry queries to have in flight at once. When
+    /// absent, the `TOP_CRATES_CONCURRENCY` environment variable is
+    /// used, or else 8.
+    pub jobs: Option<usize>,
+    /// The longest a rate-limited request waits for, whatever the
+    /// server asks for, before it is retried.
+    pub max_retry_after: Duration,
+    /// Profile settings that take precedence over those in the
+    /// crate modifications.
+    pub profile: ProfileModifications,
+    /// Only write manifest fields that this version of cargo
+    /// understands.
+    pub cargo_compat: Option<Version>,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            top_crates_snapshot: None,
+            locked_from: None,
+            crate_list: None,
+            include_only: false,
+            allow_empty: false,
+            deny_advisories: false,
+            count: 100,
+            sort: TopCratesSort::default(),
+            page_size: CRATES_IO_MAX_PAGE_SIZE,
+            unify_duplicate_features: false,
+            keep_default_feature: false,
+            minimal_features: false,
+            query_cache: None,
+            top_crates_cache: None,
+            top_crates_ttl: Duration::from_secs(24 * 60 * 60),
+            refresh_top_crates: false,
+            offline: false,
+            skip_update: false,
+            index_rev: None,
+            msrv: None,
+            max_edition: None,
+            allow_prerelease: false,
+            registry: None,
+            rust_cookbook_manifest: RUST_COOKBOOK_MANIFEST.to_owned(),
+            trim_to: None,
+            version_format: VersionFormat::default(),
+            feature_provenance: false,
+            feature_unification: false,
+            resolution_graph: false,
+            exclude_build_dependencies: false,
+            explain: None,
+            only: Vec::new(),
+            retries: 3,
+            download_concurrency: 16,
+            skip_failed_downloads: false,
+            jobs: None,
+            max_retry_after: Duration::from_secs(60),
+            profile: ProfileModifications::default(),
+            cargo_compat: None,
+        }
+    }
 }
 
 /// The list of crates from crates.io
@@ -41,10 +479,125 @@ struct TopCrates {
 }
 
 /// The shared description of a crate
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct Crate {
     #[serde(rename = "id")]
     name: InternedString,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    updated_at: Option<String>,
+    /// How many times the crate has ever been downloaded.
+    #[serde(default)]
+    downloads: Option<u64>,
+    /// How many times the crate was downloaded in the last 90 days.
+    #[serde(default)]
+    recent_downloads: Option<u64>,
+    /// The versions that may be chosen, for additions written as
+    /// `name@version`.
+    #[serde(skip)]
+    requirement: Option<VersionReq>,
+    /// Whether the crate is one of the curated additions.
+    #[serde(skip)]
+    curated: bool,
+}
+
+impl Crate {
+    /// A crate that we only know the name of.
+    fn named(name: InternedString) -> Self {
+        Crate {
+            name,
+            created_at: None,
+            updated_at: None,
+            downloads: None,
+            recent_downloads: None,
+            requirement: None,
+            curated: false,
+        }
+    }
+}
+
+/// Why a top-level crate is included
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrateOrigin {
+    /// Listed by crates.io, the Rust Cookbook, a category, a keyword,
+    /// or the crate list used instead.
+    Top,
+    /// One of the curated additions.
+    Curated,
+}
+
+/// A crate that is part of the resolved dependency tree
+#[derive(Debug, Serialize)]
+pub struct ResolvedCrate {
+    pub name: String,
+    pub version: Version,
+    /// Only needed by the build scripts of other crates.
+    pub build_only: bool,
+    /// The oldest Rust version the crate declares support for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
+}
+
+/// How many crates were resolved, and which of them more than once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionSummary {
+    /// The top-level crates that were listed, before any were left
+    /// out for being excluded, having no viable version, and so on.
+    pub listed: usize,
+    pub top_level: usize,
+    /// Every package in the resolve, including those for other
+    /// platforms.
+    pub packages: usize,
+    /// The names of crates included at more than one version, which
+    /// are exposed with a version suffix.
+    pub duplicated: Vec<String>,
+}
+
+impl ResolutionSummary {
+    fn new(
+        listed: usize,
+        top_level: usize,
+        packages: usize,
+        resolved_crates: &[ResolvedCrate],
+    ) -> Self {
+        let duplicated = resolved_crates
+            .iter()
+            .map(|krate| &krate.name)
+            .counts()
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name.clone())
+            .sorted()
+            .collect();
+        ResolutionSummary {
+            listed,
+            top_level,
+            packages,
+            duplicated,
+        }
+    }
+}
+
+impl fmt::Display for ResolutionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} top-level crates, {} packages in total",
+            self.top_level, self.packages
+        )?;
+        if self.duplicated.is_empty() {
+            write!(f, "; no crate is included at more than one version")
+        } else {
+            write!(
+                f,
+                "; {} crates are included at more than one version: {}",
+                self.duplicated.len(),
+                self.duplicated.join(", ")
+            )
+        }
+    }
 }
 
 /// A mapping of a crates name to its identifier used in source code
@@ -53,15 +606,412 @@ pub struct CrateInformation {
     pub name: String,
     pub version: Version,
     pub id: String,
+    /// Why the crate is included, for top-level crates. Crates that
+    /// are only included as dependencies have none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<CrateOrigin>,
+    /// When the crate was first published, for crates listed by
+    /// crates.io.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// When the crate was last updated, for crates listed by
+    /// crates.io.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Where the crate's source can be found, for linking to it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    /// The SPDX license expression of the crate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// The Rust edition the crate's library is written in, such as
+    /// `2021`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    /// The features enabled on the crate, besides its default ones.
+    pub features: Vec<String>,
+    /// Whether the crate's default features are enabled.
+    pub default_features: bool,
 }
 
 /// Hand-curated changes to the crate list
+///
+/// Every key is optional, but unknown ones are rejected so that a typo
+/// doesn't silently leave a setting out.
 #[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Modifications {
+    /// Crate names, or glob patterns such as `windows-sys*` when they
+    /// contain `*`, `?` or `[`.
+    #[serde(default)]
+    pub exclusions: Vec<Exclusion>,
+    /// Crate names, each optionally followed by `@` and the version
+    /// requirement that the chosen version must meet, such as
+    /// `serde@1.0.150`.
+    #[serde(default, deserialize_with = "additions")]
+    pub additions: BTreeMap<InternedString, Option<VersionReq>>,
+    /// Regular expressions; any crate whose name matches one of them
+    /// is excluded.
+    #[serde(default, deserialize_with = "regexes")]
+    pub exclusion_patterns: Vec<Regex>,
+    /// SPDX identifiers of the licenses crates may use. When absent,
+    /// licenses are not checked.
+    #[serde(default)]
+    pub license_allowlist: Option<Vec<String>>,
+    /// A checkout of the RustSec advisory database, such as
+    /// `../advisory-db`. When set, every resolved crate is checked
+    /// against its advisories; nothing is downloaded to do so.
+    #[serde(default)]
+    pub advisory_db: Option<PathBuf>,
+    /// Requirements that the chosen version of a top-level crate must
+    /// meet, to hold it back from newer versions.
+    #[serde(default)]
+    pub pinned: BTreeMap<InternedString, VersionReq>,
+    /// Versions of top-level crates that are never chosen, such as
+    /// `tokio = ["=1.24.0"]` for a release that is known to be broken
+    /// in the playground. When every viable version is blocked, the
+    /// newest is used anyway.
+    #[serde(default)]
+    pub blocked_versions: BTreeMap<InternedString, Vec<VersionReq>>,
+    /// Top-level crates whose prerelease versions may be chosen.
+    #[serde(default)]
+    pub allow_prerelease: BTreeSet<InternedString>,
+    /// Crates whose dev-dependencies are resolved along with their
+    /// other dependencies. Everything they pull in becomes part of the
+    /// playground, so this can enlarge the resolved set considerably.
+    #[serde(default)]
+    pub with_dev_deps: BTreeSet<InternedString>,
+    /// Features that are never enabled by playground metadata, such as
+    /// those that need a nightly compiler.
+    #[serde(default)]
+    pub blacklist_features: BTreeSet<InternedString>,
+    /// Registry logins, such as `someone` or `github:some-org:team`,
+    /// whose top-level crates are left out, even when they are only
+    /// one of several owners. When set, the registry's web API is asked
+    /// for the owners of every top-level crate that isn't excluded by
+    /// name, one request per crate.
+    #[serde(default)]
+    pub blacklist_owners: BTreeSet<String>,
+    /// Features to enable for crates whose playground metadata we
+    /// can't change, keyed by crate name. They are enabled on top of
+    /// whatever the crate's own metadata asks for, even when one of
+    /// the blacklists names them.
+    #[serde(default)]
+    pub features: BTreeMap<InternedString, BTreeSet<InternedString>>,
+    /// Crates to enable every optional dependency of, through the
+    /// features named after them, apart from blacklisted ones. This can
+    /// bring in many more crates, and is no different from the crate's
+    /// own `all-features` for the optional dependencies.
+    #[serde(default)]
+    pub enable_optional_deps: BTreeSet<InternedString>,
+    /// crates.io category slugs, such as `game-development`, whose most
+    /// downloaded crates are included as well.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// How many crates to include from each of the `categories`,
+    /// [`DEFAULT_CATEGORY_COUNT`] unless set.
+    #[serde(default)]
+    pub category_count: Option<usize>,
+    /// crates.io keywords, such as `cli` or `async`, whose most
+    /// downloaded crates are included as well.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// How many crates to include for each of the `keywords`,
+    /// [`DEFAULT_KEYWORD_COUNT`] unless set.
+    #[serde(default)]
+    pub keyword_count: Option<usize>,
+    /// Top-level crates downloaded fewer times than this are left out,
+    /// unless they are additions.
+    #[serde(default)]
+    pub min_downloads: Option<u64>,
+    /// Leave out top-level crates whose only library is a procedural
+    /// macro, as those can't be `use`d like other libraries. Additions
+    /// are kept, and other crates may still depend on them.
+    #[serde(default)]
+    pub exclude_proc_macros: bool,
+    /// The most crates to resolve in total. When there would be more,
+    /// the least downloaded top-level crates that aren't additions are
+    /// left out, resolving everything again each time, which can make
+    /// generating much slower.
+    #[serde(default)]
+    pub max_crates: Option<usize>,
+    /// Settings for the profiles of the generated manifest.
+    #[serde(default)]
+    pub profile: ProfileModifications,
+    /// Settings for single crates that are slow to build, such as
+    /// `image = { opt-level = 3 }`, written to every profile as
+    /// `[profile.*.package."name"]`.
+    #[serde(default)]
+    pub profile_overrides: BTreeMap<InternedString, ProfileOverride>,
+    /// The `[package]` of the generated manifest.
+    #[serde(default)]
+    pub package: PackageSettings,
+    /// The `[features]` of the generated manifest, each enabling
+    /// other features such as `rand/small_rng`.
+    #[serde(default)]
+    pub playground_features: BTreeMap<String, Vec<String>>,
+    /// The names to expose crates as, keyed by crate name and then by
+    /// a version requirement, such as `serde = { "1" = "serde1" }`.
+    /// A version matching one of the requirements, which shouldn't
+    /// overlap, is exposed under that name instead of the one it
+    /// would otherwise get.
+    #[serde(default, deserialize_with = "renames")]
+    pub renames: Renames,
+    /// Crates to build from a git repository or a path instead of
+    /// crates.io, written to the `[patch.crates-io]` of the generated
+    /// manifest.
+    #[serde(default)]
+    pub patches: BTreeMap<InternedString, Patch>,
+    /// Crates only to depend on for some targets, keyed by crate name,
+    /// with the `cfg` expression that picks those targets, such as
+    /// `unix` or `target_os = "linux"`.
+    #[serde(default)]
+    pub target_deps: BTreeMap<InternedString, String>,
+    /// Modifications for a single channel, such as
+    /// `[channels.nightly]`, layered over the rest when generating for
+    /// that channel.
+    #[serde(default)]
+    pub channels: BTreeMap<Channel, Modifications>,
+}
+
+impl Modifications {
+    /// Layers `other` on top of `self`. Lists and sets are combined,
+    /// while per-crate settings and single values in `other` replace
+    /// those of `self`.
+    pub fn merge(mut self, other: Modifications) -> Modifications {
+        fn union<T: PartialEq>(mut ours: Vec<T>, theirs: Vec<T>) -> Vec<T> {
+            for item in theirs {
+                if !ours.contains(&item) {
+                    ours.push(item);
+                }
+            }
+            ours
+        }
+
+        self.exclusions = union(self.exclusions, other.exclusions);
+        self.additions.extend(other.additions);
+        for pattern in other.exclusion_patterns {
+            if !self
+                .exclusion_patterns
+                .iter()
+                .any(|p| p.as_str() == pattern.as_str())
+            {
+                self.exclusion_patterns.push(pattern);
+            }
+        }
+        self.license_allowlist = match (self.license_allowlist, other.license_allowlist) {
+            (Some(ours), Some(theirs)) => Some(union(ours, theirs)),
+            (ours, theirs) => theirs.or(ours),
+        };
+        self.advisory_db = other.advisory_db.or(self.advisory_db);
+        self.pinned.extend(other.pinned);
+        self.blocked_versions.extend(other.blocked_versions);
+        self.allow_prerelease.extend(other.allow_prerelease);
+        self.with_dev_deps.extend(other.with_dev_deps);
+        self.blacklist_features.extend(other.blacklist_features);
+        self.blacklist_owners.extend(other.blacklist_owners);
+        self.enable_optional_deps.extend(other.enable_optional_deps);
+        for (name, features) in other.features {
+            self.features.entry(name).or_default().extend(features);
+        }
+        self.categories = union(self.categories, other.categories);
+        self.category_count = other.category_count.or(self.category_count);
+        self.keywords = union(self.keywords, other.keywords);
+        self.keyword_count = other.keyword_count.or(self.keyword_count);
+        self.min_downloads = other.min_downloads.or(self.min_downloads);
+        self.exclude_proc_macros |= other.exclude_proc_macros;
+        self.max_crates = other.max_crates.or(self.max_crates);
+        self.profile = other.profile.or(&self.profile);
+        self.profile_overrides.extend(other.profile_overrides);
+        self.package = PackageSettings {
+            name: other.package.name.or(self.package.name),
+            version: other.package.version.or(self.package.version),
+            authors: other.package.authors.or(self.package.authors),
+            edition: other.package.edition.or(self.package.edition),
+            description: other.package.description.or(self.package.description),
+            resolver: other.package.resolver.or(self.package.resolver),
+        };
+        self.playground_features.extend(other.playground_features);
+        self.patches.extend(other.patches);
+        self.target_deps.extend(other.target_deps);
+        self.renames.extend(other.renames);
+        for (channel, theirs) in other.channels {
+            let ours = self.channels.remove(&channel).unwrap_or_default();
+            self.channels.insert(channel, ours.merge(theirs));
+        }
+        self
+    }
+
+    /// The modifications to use for `channel`: its own are layered
+    /// over the rest, and those of the other channels are dropped.
+    pub fn for_channel(mut self, channel: Option<Channel>) -> Modifications {
+        let mut channels = mem::take(&mut self.channels);
+        match channel.and_then(|channel| channels.remove(&channel)) {
+            Some(overlay) => self.merge(overlay),
+            None => self,
+        }
+    }
+}
+
+/// The package the generated manifest describes; fields that are absent
+/// keep the playground's own values.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageSettings {
+    pub name: Option<String>,
+    /// Checked to be a valid semver version when it is read.
+    pub version: Option<Version>,
+    pub authors: Option<Vec<String>>,
+    /// Such as `2021`; cargo assumes 2015 when it's absent.
+    pub edition: Option<String>,
+    pub description: Option<String>,
+    /// The feature resolver cargo uses for the playground, version 2
+    /// unless set.
+    pub resolver: Option<FeatureResolver>,
+}
+
+/// A version of cargo's feature resolver, written as `resolver = "1"`
+/// or `resolver = "2"`.
+///
+/// Version 1 enables the union of everything every crate asks for,
+/// wherever a crate is used. Version 2 keeps the features that
+/// build-dependencies and proc macros ask for apart from those of
+/// normal dependencies, and ignores dependencies of other platforms, so
+/// crates that are only reachable through those may get fewer
+/// features. The crates are resolved the way version 1 unifies
+/// features, so with version 2 cargo's own feature resolver is run
+/// again for the features listed in `crate-information.json`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+pub enum FeatureResolver {
+    #[serde(rename = "1")]
+    V1,
+    #[serde(rename = "2")]
+    V2,
+}
+
+impl FeatureResolver {
+    /// The version written to the playground's manifest for `package`.
+    /// Before cargo 1.51 only version 1 existed, so nothing is written.
+    pub fn written(package: &PackageSettings, cargo_compat: Option<&Version>) -> Option<Self> {
+        let supported = cargo_compat.is_none_or(|compat| *compat >= Version::new(1, 51, 0));
+        supported.then(|| package.resolver.unwrap_or(FeatureResolver::V2))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FeatureResolver::V1 => "1",
+            FeatureResolver::V2 => "2",
+        }
+    }
+}
+
+/// Changes to the profiles of the generated manifest
+///
+/// The `dev` and `release` profiles are always written unless they are
+/// omitted, while `test` and `bench` are only written when they have a
+/// table of their own.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileModifications {
+    #[serde(default)]
+    pub dev: ProfileSettings,
+    #[serde(default)]
+    pub release: ProfileSettings,
     #[serde(default)]
-    pub exclusions: Vec<InternedString>,
+    pub test: Option<ProfileSettings>,
     #[serde(default)]
-    pub additions: BTreeSet<InternedString>,
+    pub bench: Option<ProfileSettings>,
+    /// Profiles to leave out of the manifest, such as `["dev"]` for a
+    /// deployment that only builds in release mode.
+    #[serde(default)]
+    pub omit: BTreeSet<String>,
+}
+
+/// Settings for one profile; those that are absent keep their defaults.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileSettings {
+    pub codegen_units: Option<u32>,
+    pub incremental: Option<bool>,
+    /// Written either as a number or as `s` or `z`.
+    #[serde(default, deserialize_with = "opt_level")]
+    pub opt_level: Option<String>,
+    pub lto: Option<bool>,
+}
+
+/// Settings for a single crate in every profile. Cargo doesn't let a
+/// single crate change `lto`, so that can't be set.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileOverride {
+    pub codegen_units: Option<u32>,
+    pub incremental: Option<bool>,
+    /// Written either as a number or as `s` or `z`.
+    #[serde(default, deserialize_with = "opt_level")]
+    pub opt_level: Option<String>,
+}
+
+impl ProfileModifications {
+    /// Uses the settings of `self`, falling back to those of `other`.
+    pub fn or(self, other: &ProfileModifications) -> ProfileModifications {
+        let or = |ours: Option<ProfileSettings>, theirs: &Option<ProfileSettings>| match ours {
+            Some(ours) => Some(ours.or(theirs.as_ref().unwrap_or(&ProfileSettings::default()))),
+            None => theirs.clone(),
+        };
+        ProfileModifications {
+            dev: self.dev.or(&other.dev),
+            release: self.release.or(&other.release),
+            test: or(self.test, &other.test),
+            bench: or(self.bench, &other.bench),
+            omit: self.omit.union(&other.omit).cloned().collect(),
+        }
+    }
+}
+
+impl ProfileSettings {
+    /// Uses the settings of `self`, falling back to those of `other`.
+    pub fn or(self, other: &ProfileSettings) -> ProfileSettings {
+        ProfileSettings {
+            codegen_units: self.codegen_units.or(other.codegen_units),
+            incremental: self.incremental.or(other.incremental),
+            opt_level: self.opt_level.or_else(|| other.opt_level.clone()),
+            lto: self.lto.or(other.lto),
+        }
+    }
+}
+
+/// Checks that `s` is one of the optimization levels cargo accepts.
+pub fn parse_opt_level(s: &str) -> Result<String, String> {
+    match s {
+        "0" | "1" | "2" | "3" | "s" | "z" => Ok(s.to_owned()),
+        _ => Err(format!(
+            "invalid opt-level `{}`; expected 0 to 3, `s` or `z`",
+            s
+        )),
+    }
+}
+
+fn opt_level<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OptLevel {
+        Number(u32),
+        Name(String),
+    }
+
+    let level = match Option::<OptLevel>::deserialize(deserializer)? {
+        Some(OptLevel::Number(level)) => level.to_string(),
+        Some(OptLevel::Name(level)) => level,
+        None => return Ok(None),
+    };
+    parse_opt_level(&level)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -77,12 +1027,22 @@ pub struct DependencySpec {
     pub default_features: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct ResolvedDep {
     summary: Summary,
     lib_target: Target,
     features: BTreeSet<InternedString>,
     uses_default_features: bool,
+    license: Option<String>,
+    description: Option<String>,
+    repository: Option<String>,
+    /// Only reachable through build-dependencies of other crates.
+    build_only: bool,
+    rust_version: Option<String>,
+    /// The features requested by the crate's playground metadata.
+    metadata_features: BTreeSet<InternedString>,
+    /// Whether the crate's playground metadata sets `all-features`.
+    all_features: bool,
 }
 
 fn exact_version<S>(version: &Version, serializer: S) -> Result<S::Ok, S::Error>
@@ -103,429 +1063,6232 @@ fn is_true(b: &bool) -> bool {
     *b
 }
 
-impl Modifications {
-    fn excluded(&self, name: &str) -> bool {
-        self.exclusions.iter().any(|n| n == name)
-    }
+fn regexes<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| {
+                serde::de::Error::custom(format!("invalid pattern `{}`: {}", pattern, e))
+            })
+        })
+        .collect()
 }
 
-fn simple_get(url: &str) -> reqwest::Result<reqwest::blocking::Response> {
-    reqwest::blocking::ClientBuilder::new()
-        .user_agent("Rust Playground - Top Crates Utility")
-        .build()?
-        .get(url)
-        .send()
+fn additions<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<InternedString, Option<VersionReq>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let additions = Vec::<String>::deserialize(deserializer)?;
+    additions
+        .iter()
+        .map(|addition| parse_addition(addition).map_err(serde::de::Error::custom))
+        .collect()
 }
 
-impl TopCrates {
-    /// List top 100 crates by number of downloads on crates.io.
-    fn download() -> TopCrates {
-        let resp = simple_get("https://crates.io/api/v1/crates?page=1&per_page=100&sort=downloads")
-            .expect("Could not fetch top crates");
-        assert!(
-            resp.status().is_success(),
-            "Could not download top crates; HTTP status was {}",
-            resp.status(),
-        );
+/// The names to expose crates as, keyed by crate name, with the
+/// version requirement each name is for.
+pub type Renames = BTreeMap<InternedString, Vec<(VersionReq, String)>>;
+
+fn renames<'de, D>(deserializer: D) -> Result<Renames, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let renames = BTreeMap::<InternedString, BTreeMap<String, String>>::deserialize(deserializer)?;
+    renames
+        .into_iter()
+        .map(|(krate, renames)| {
+            let renames = renames
+                .into_iter()
+                .map(|(requirement, name)| {
+                    let requirement = requirement.parse().map_err(|e| {
+                        serde::de::Error::custom(format!(
+                            "invalid version requirement `{}` in the renames of {}: {}",
+                            requirement, krate, e
+                        ))
+                    })?;
+                    if rust_identifier(&name).is_some() {
+                        return Err(serde::de::Error::custom(format!(
+                            "`{}` in the renames of {} is not a valid identifier",
+                            name, krate
+                        )));
+                    }
+                    Ok((requirement, name))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((krate, renames))
+        })
+        .collect()
+}
 
-        serde_json::from_reader(resp).expect("Invalid JSON")
+/// Splits an addition written as `name` or `name@version`.
+fn parse_addition(addition: &str) -> Result<(InternedString, Option<VersionReq>), String> {
+    let (name, requirement) = match addition.split_once('@') {
+        Some((name, version)) => {
+            let requirement = version.parse().map_err(|e| {
+                format!(
+                    "invalid version requirement in addition `{}`: {}",
+                    addition, e
+                )
+            })?;
+            (name, Some(requirement))
+        }
+        None => (addition, None),
+    };
+    if name.is_empty() {
+        return Err(format!("addition `{}` has no crate name", addition));
     }
+    Ok((InternedString::new(name), requirement))
+}
 
-    fn add_rust_cookbook_crates(&mut self) {
-        let mut resp = simple_get(
-            "https://raw.githubusercontent.com/rust-lang-nursery/rust-cookbook/master/Cargo.toml",
-        )
-        .expect("Could not fetch cookbook manifest");
-        assert!(
-            resp.status().is_success(),
-            "Could not download cookbook; HTTP status was {}",
-            resp.status(),
-        );
+/// One of the `exclusions` of [`Modifications`].
+#[derive(Debug, Clone)]
+pub enum Exclusion {
+    /// A crate name, compared the way cargo compares them.
+    Name(InternedString),
+    /// A glob pattern, such as `windows-sys*`, as it was written and
+    /// translated into a regular expression like those of
+    /// `exclusion-patterns`. Crate names are matched against it as
+    /// they are.
+    Pattern(String, Regex),
+}
 
-        let mut content = String::new();
-        resp.read_to_string(&mut content)
-            .expect("could not read cookbook manifest");
+impl Exclusion {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Exclusion::Name(name) => name,
+            Exclusion::Pattern(glob, _) => glob,
+        }
+    }
 
-        let manifest = content
-            .parse::<toml::Value>()
-            .expect("could not parse cookbook manifest");
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Exclusion::Name(exclusion) => {
+                normalize_crate_name(exclusion) == normalize_crate_name(name)
+            }
+            Exclusion::Pattern(_, regex) => regex.is_match(name),
+        }
+    }
+}
 
-        let dependencies = manifest["dependencies"]
-            .as_table()
-            .expect("no dependencies found for cookbook manifest");
-        self.crates.extend({
-            dependencies.iter().map(|(name, _)| Crate {
-                name: InternedString::new(name),
-            })
-        })
+impl PartialEq for Exclusion {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
     }
+}
 
-    /// Add crates that have been hand-picked
-    fn add_curated_crates(&mut self, modifications: &Modifications) {
-        self.crates.extend({
-            modifications
-                .additions
-                .iter()
-                .copied()
-                .map(|name| Crate { name })
-        });
+impl PartialEq<InternedString> for Exclusion {
+    fn eq(&self, other: &InternedString) -> bool {
+        self.as_str() == other.as_str()
     }
 }
 
-/// Finds the features specified by the custom metadata of `pkg`.
-///
-/// Our custom metadata format looks like:
-///
-///     [package.metadata.playground]
-///     default-features = true
-///     features = ["std", "extra-traits"]
-///     all-features = false
-///
-/// All fields are optional.
-fn playground_metadata_features(pkg: &Package) -> Option<(BTreeSet<InternedString>, bool)> {
-    let custom_metadata = pkg.manifest().custom_metadata()?;
-    let playground_metadata = custom_metadata.get("playground")?;
+impl std::str::FromStr for Exclusion {
+    type Err = regex::Error;
 
-    #[derive(Deserialize)]
-    #[serde(default, rename_all = "kebab-case")]
-    struct Metadata {
-        features: BTreeSet<InternedString>,
-        default_features: bool,
-        all_features: bool,
+    fn from_str(exclusion: &str) -> Result<Self, Self::Err> {
+        if !exclusion.contains(['*', '?', '[']) {
+            return Ok(Exclusion::Name(InternedString::new(exclusion)));
+        }
+        let regex = Regex::new(&glob_regex(exclusion))?;
+        Ok(Exclusion::Pattern(exclusion.to_owned(), regex))
     }
+}
 
-    impl Default for Metadata {
-        fn default() -> Self {
-            Metadata {
-                features: BTreeSet::new(),
-                default_features: true,
-                all_features: false,
-            }
-        }
+impl<'de> Deserialize<'de> for Exclusion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let exclusion = String::deserialize(deserializer)?;
+        exclusion.parse().map_err(|e| {
+            serde::de::Error::custom(format!("invalid pattern `{}`: {}", exclusion, e))
+        })
     }
+}
 
-    let metadata = match playground_metadata.clone().try_into::<Metadata>() {
-        Ok(metadata) => metadata,
-        Err(err) => {
-            eprintln!(
-                "Failed to parse custom metadata for {} {}: {}",
-                pkg.name(),
-                pkg.version(),
-                err
-            );
-            return None;
+/// The regular expression matching the same names as `glob`, where
+/// `*` matches any run of characters, `?` any one character, and
+/// `[...]` (or `[!...]`) any one character of (or not of) a set.
+fn glob_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let rest = chars.as_str();
+                let (negated, set) = match rest.strip_prefix('!') {
+                    Some(set) => (true, set),
+                    None => (false, rest),
+                };
+                // A `]` right at the start is one of the set's characters.
+                let end = set.char_indices().skip(1).find(|&(_, c)| c == ']');
+                let end = match end {
+                    Some((end, _)) => end,
+                    None => {
+                        // Left unclosed, so the pattern is rejected.
+                        regex.push('[');
+                        continue;
+                    }
+                };
+                regex.push('[');
+                if negated {
+                    regex.push('^');
+                }
+                for c in set[..end].chars() {
+                    if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+                chars = set[end + 1..].chars();
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
         }
-    };
-
-    // If `all-features` is set then we ignore `features`.
-    let summary = pkg.summary();
-    let enabled_features: BTreeSet<InternedString> = if metadata.all_features {
-        summary.features().keys().copied().collect()
-    } else {
-        metadata.features
-    };
+    }
+    regex.push('$');
+    regex
+}
 
-    Some((enabled_features, metadata.default_features))
+/// Cargo considers crate names that only differ by case or by `-`
+/// and `_` to be the same, so they are compared in this form.
+fn normalize_crate_name(name: &str) -> String {
+    name.replace('-', "_").to_lowercase()
 }
 
-fn make_global_state<'cfg>(
-    config: &'cfg Config,
-    modifications: &'cfg Modifications,
-) -> GlobalState<'cfg> {
-    // Information about the playground's target platform.
-    let compile_target =
-        CompileTarget::new(PLAYGROUND_TARGET_PLATFORM).expect("Unable to create a CompileTarget");
-    let compile_kind = CompileKind::Target(compile_target);
-    let rustc = config
-        .load_global_rustc(None)
-        .expect("Unable to load the global rustc");
-    let target_info = TargetInfo::new(config, &[compile_kind], &rustc, compile_kind)
-        .expect("Unable to create a TargetInfo");
+/// The groups of distinct `names` that are the same once normalized.
+fn similar_names(names: impl IntoIterator<Item = InternedString>) -> Vec<Vec<InternedString>> {
+    names
+        .into_iter()
+        .unique()
+        .into_group_map_by(|name| normalize_crate_name(name))
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|names| names.into_iter().sorted().collect_vec())
+        .sorted()
+        .collect()
+}
 
-    // Registry of known packages.
-    let mut registry = PackageRegistry::new(config).expect("Unable to create package registry");
-    registry.lock_patches();
+impl Modifications {
+    fn excluded(&self, name: &str) -> bool {
+        self.exclusions.iter().any(|e| e.matches(name))
+            || self.exclusion_patterns.iter().any(|p| p.is_match(name))
+    }
 
-    // Source for obtaining packages from the crates.io registry.
-    let crates_io = SourceId::crates_io(config).expect("Unable to create crates.io source ID");
-    let yanked_whitelist = HashSet::new();
-    let mut source = RegistrySource::remote(crates_io, &yanked_whitelist, config)
-        .expect("Unable to create registry source");
-    source.invalidate_cache();
-    source
-        .block_until_ready()
-        .expect("Unable to wait for registry to be ready");
+    /// Whether the top-level crate `name` is left out for only being a
+    /// procedural macro, given the library it would be used through.
+    fn excludes_proc_macro(&self, name: InternedString, library: &Target) -> bool {
+        let proc_macro = match library.kind() {
+            TargetKind::Lib(crate_types) => crate_types.iter().all(|t| *t == CrateType::ProcMacro),
+            _ => false,
+        };
+        self.exclude_proc_macros && proc_macro && !self.additions.contains_key(&name)
+    }
 
-    GlobalState {
-        config,
-        target_info,
-        registry,
-        crates_io,
-        source,
-        modifications,
+    /// Whether `krate` is known to be downloaded too rarely to be
+    /// included. Additions are hand-picked, so they always are.
+    fn too_few_downloads(&self, krate: &Crate) -> bool {
+        match (self.min_downloads, krate.downloads) {
+            (Some(min), Some(downloads)) => {
+                downloads < min && !self.additions.contains_key(&krate.name)
+            }
+            _ => false,
+        }
+    }
+
+    /// The requirement that the chosen version of `name` must meet,
+    /// either as a pin or as part of its addition.
+    fn pin(&self, name: InternedString) -> Option<&VersionReq> {
+        self.pinned
+            .get(&name)
+            .or_else(|| self.additions.get(&name)?.as_ref())
     }
 }
 
-fn bulk_download(global: &mut GlobalState<'_>, package_ids: &[PackageId]) -> Vec<Package> {
-    let mut sources = SourceMap::new();
-    sources.insert(Box::new(&mut global.source));
+/// A duration given in seconds by the environment variable `name`, or
+/// `default` when it isn't set or isn't a number.
+fn seconds_from_env(name: &str, default: Duration, diagnostics: &mut Diagnostics) -> Duration {
+    match env::var(name) {
+        Ok(seconds) => match seconds.parse() {
+            Ok(seconds) => Duration::from_secs(seconds),
+            Err(_) => {
+                diagnostics.warn(format!(
+                    "Ignoring {}, which is not a number of seconds: `{}`",
+                    name, seconds
+                ));
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
 
-    let package_set = PackageSet::new(package_ids, sources, global.config)
-        .expect("Unable to create a PackageSet");
+/// Makes HTTP requests, retrying those that fail transiently.
+struct Http {
+    client: reqwest::blocking::Client,
+    backoff: Backoff,
+}
 
-    package_set
-        .get_many(package_set.package_ids())
-        .expect("Unable to download packages")
-        .into_iter()
-        .map(Package::clone)
-        .collect()
+impl Http {
+    /// The client goes through the proxies named by `HTTP_PROXY`,
+    /// `HTTPS_PROXY` and `NO_PROXY`, if set.
+    fn new(backoff: Backoff, diagnostics: &mut Diagnostics) -> Result<Self, TopCratesError> {
+        let user_agent = env::var(USER_AGENT_ENV).unwrap_or_else(|_| DEFAULT_USER_AGENT.to_owned());
+        let connect_timeout =
+            seconds_from_env(CONNECT_TIMEOUT_ENV, DEFAULT_CONNECT_TIMEOUT, diagnostics);
+        let timeout = seconds_from_env(TIMEOUT_ENV, DEFAULT_TIMEOUT, diagnostics);
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(connect_timeout)
+            .timeout(timeout)
+            .build()
+            .map_err(TopCratesError::network("Unable to create an HTTP client"))?;
+        Ok(Http { client, backoff })
+    }
+
+    /// Requests `url`, failing with the status and body of any
+    /// unsuccessful response so that rejections can be diagnosed.
+    fn fetch(
+        &self,
+        url: &str,
+        what: &str,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<reqwest::blocking::Response, TopCratesError> {
+        self.backoff.run_with_requested_delays(
+            || self.fetch_once(url, what),
+            TopCratesError::is_transient,
+            TopCratesError::retry_after,
+            |attempt, e| {
+                diagnostics.note(format!(
+                    "Retrying {} after attempt {}: {}",
+                    what, attempt, e
+                ))
+            },
+        )
+    }
+
+    fn fetch_once(
+        &self,
+        url: &str,
+        what: &str,
+    ) -> Result<reqwest::blocking::Response, TopCratesError> {
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .map_err(TopCratesError::network(format!("Could not fetch {}", what)))?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let retry_after = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(TopCratesError::HttpStatus {
+                context: format!("Could not download {}", what),
+                status,
+                body: resp.text().unwrap_or_default(),
+                retry_after,
+            });
+        }
+        Ok(resp)
+    }
 }
 
-fn populate_initial_direct_dependencies(
-    global: &mut GlobalState<'_>,
-) -> BTreeMap<PackageId, ResolvedDep> {
-    let mut top = TopCrates::download();
-    top.add_rust_cookbook_crates();
-    top.add_curated_crates(global.modifications);
-
-    // Find the newest (non-prerelease, non-yanked) versions of all
-    // the interesting crates.
-    let mut package_ids = Vec::new();
-    for Crate { name } in top.crates {
-        if global.modifications.excluded(&name) {
-            continue;
+/// Reads a `Retry-After` header given in seconds. Dates, which
+/// crates.io doesn't send, are left to the usual backoff.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Requests pages (starting at 1) until `count` distinct crates have
+/// been seen or a page comes back empty.
+///
+/// A crate can move between pages while they are being requested, so
+/// only its first appearance is kept.
+fn collect_pages<E>(
+    count: usize,
+    mut fetch_page: impl FnMut(u32) -> Result<Vec<Crate>, E>,
+) -> Result<Vec<Crate>, E> {
+    let mut seen = HashSet::new();
+    let mut crates = Vec::new();
+
+    for page in 1.. {
+        if crates.len() >= count {
+            break;
         }
+        let page_crates = fetch_page(page)?;
+        if page_crates.is_empty() {
+            break;
+        }
+        crates.extend(
+            page_crates
+                .into_iter()
+                .filter(|krate| seen.insert(krate.name)),
+        );
+    }
 
-        // Query the registry for a summary of this crate.
-        // Usefully, this doesn't seem to include yanked versions
-        let version = None;
-        let dep = Dependency::parse(name, version, global.crates_io)
-            .unwrap_or_else(|e| panic!("Unable to parse dependency for {}: {}", name, e));
+    crates.truncate(count);
+    Ok(crates)
+}
 
-        let matches = match global.source.query_vec(&dep, QueryKind::Exact) {
-            Poll::Ready(Ok(v)) => v,
-            Poll::Ready(Err(e)) => panic!("Unable to query registry for {}: {}", name, e),
-            Poll::Pending => panic!("Registry not ready to query"),
-        };
+/// Lists `count` crates from the registry whose web API is at `api`,
+/// `query` choosing which ones and their order.
+fn list_crates(
+    http: &Http,
+    diagnostics: &mut Diagnostics,
+    api: &str,
+    query: &str,
+    what: &str,
+    count: usize,
+    page_size: u32,
+) -> Result<Vec<Crate>, TopCratesError> {
+    let page_size = page_size.clamp(1, CRATES_IO_MAX_PAGE_SIZE);
+    let api = api.trim_end_matches('/');
+    collect_pages(count, |page| {
+        let url = format!(
+            "{}/api/v1/crates?page={}&per_page={}&{}",
+            api, page, page_size, query,
+        );
+        let resp = http.fetch(&url, what, diagnostics)?;
+        let top: TopCrates = serde_json::from_reader(resp)
+            .map_err(TopCratesError::json(format!("Invalid JSON in {}", what)))?;
+        Ok(top.crates)
+    })
+}
 
-        // Find the newest non-prelease version
-        let summary = matches
-            .into_iter()
-            .filter(|summary| !summary.version().is_prerelease())
-            .max_by_key(|summary| summary.version().clone())
-            .unwrap_or_else(|| panic!("Registry has no viable versions of {}", name));
+/// The query listing the most downloaded crates whose `field` is
+/// `value`, which is encoded so that a keyword such as `c++` keeps its
+/// `+`.
+fn most_downloaded_with(field: &str, value: &str) -> String {
+    let value = form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+    format!("{}={}&sort=downloads", field, value)
+}
 
-        let package_id = PackageId::pure(name, summary.version().clone(), global.crates_io);
-        package_ids.push(package_id);
+impl TopCrates {
+    /// List the `count` top crates by `sort` on the registry whose
+    /// web API is at `api`, in order.
+    fn download(
+        http: &Http,
+        diagnostics: &mut Diagnostics,
+        api: &str,
+        count: usize,
+        sort: TopCratesSort,
+        page_size: u32,
+    ) -> Result<TopCrates, TopCratesError> {
+        let query = format!("sort={}", sort.as_str());
+        let crates = list_crates(
+            http,
+            diagnostics,
+            api,
+            &query,
+            "top crates",
+            count,
+            page_size,
+        )?;
+        Ok(TopCrates { crates })
     }
 
-    let packages = bulk_download(global, &package_ids);
+    /// Add the `count` most downloaded crates in each of `categories`.
+    ///
+    /// Each category is listed separately, so this makes at least one
+    /// more request to the registry's web API per category.
+    fn add_category_crates(
+        &mut self,
+        http: &Http,
+        diagnostics: &mut Diagnostics,
+        api: &str,
+        categories: &[String],
+        count: usize,
+        page_size: u32,
+    ) -> Result<(), TopCratesError> {
+        for category in categories {
+            let query = most_downloaded_with("category", category);
+            let what = format!("the top crates in category {}", category);
+            let crates = list_crates(http, diagnostics, api, &query, &what, count, page_size)?;
+            for krate in crates {
+                self.add(krate);
+            }
+        }
+        Ok(())
+    }
 
-    let mut initial_direct_dependencies = BTreeMap::new();
-    for download in packages {
-        let id = download.package_id();
-        let lib_target = download
-            .library()
-            .unwrap_or_else(|| panic!("{} did not have a library", id))
-            .clone();
-        let mut dep = ResolvedDep {
-            summary: download.summary().clone(),
-            lib_target,
-            features: BTreeSet::new(),
-            uses_default_features: true,
-        };
-        if let Some((features, default_features)) = playground_metadata_features(&download) {
-            dep.features = features;
-            dep.uses_default_features = default_features;
+    /// Add the `count` most downloaded crates tagged with each of
+    /// `keywords`.
+    ///
+    /// Each keyword is listed separately, so this makes at least one
+    /// more request to the registry's web API per keyword.
+    fn add_keyword_crates(
+        &mut self,
+        http: &Http,
+        diagnostics: &mut Diagnostics,
+        api: &str,
+        keywords: &[String],
+        count: usize,
+        page_size: u32,
+    ) -> Result<(), TopCratesError> {
+        for keyword in keywords {
+            let query = most_downloaded_with("keyword", keyword);
+            let what = format!("the top crates with keyword {}", keyword);
+            let crates = list_crates(http, diagnostics, api, &query, &what, count, page_size)?;
+            for krate in crates {
+                self.add(krate);
+            }
         }
-        initial_direct_dependencies.insert(id, dep);
+        Ok(())
     }
 
-    initial_direct_dependencies
-}
+    /// The crates named by the lines of a crate list, as described by
+    /// [`GenerateOptions::crate_list`].
+    fn from_list(lines: &[String]) -> Result<TopCrates, TopCratesError> {
+        let mut top = TopCrates { crates: Vec::new() };
+        for (index, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, requirement) =
+                parse_addition(line).map_err(|message| TopCratesError::InvalidCrateList {
+                    line: index + 1,
+                    message,
+                })?;
+            top.add(Crate {
+                requirement,
+                ..Crate::named(name)
+            });
+        }
+        Ok(top)
+    }
 
-fn extend_direct_dependencies(
-    global: &mut GlobalState<'_>,
-    crates: &mut BTreeMap<PackageId, ResolvedDep>,
-) {
-    // Add a direct dependency on each starting crate.
-    let mut summaries = Vec::new();
-    let mut valid_for_our_platform = BTreeSet::new();
-    for dep in mem::take(crates).into_values() {
-        valid_for_our_platform.insert(dep.summary.package_id());
-        summaries.push((
-            dep.summary,
-            ResolveOpts {
-                dev_deps: false,
-                features: RequestedFeatures::DepFeatures {
-                    features: Rc::new(dep.features),
-                    uses_default_features: dep.uses_default_features,
-                },
-            },
-        ));
+    /// Load a crates.io response that was saved to disk earlier.
+    fn from_snapshot(path: &Path) -> Result<TopCrates, TopCratesError> {
+        let f = File::open(path).map_err(TopCratesError::io(format!(
+            "Unable to open {}",
+            path.display()
+        )))?;
+        serde_json::from_reader(BufReader::new(f)).map_err(TopCratesError::json(format!(
+            "Invalid JSON in {}",
+            path.display()
+        )))
     }
 
-    // Resolve transitive dependencies.
-    let replacements = [];
-    let version_prefs = VersionPreferences::default();
-    let warnings = None;
-    let check_public_visible_dependencies = true;
-    let resolve = resolver::resolve(
-        &summaries,
-        &replacements,
-        &mut global.registry,
-        &version_prefs,
-        warnings,
-        check_public_visible_dependencies,
-    )
-    .expect("Unable to resolve dependencies");
+    fn add_rust_cookbook_crates(
+        &mut self,
+        http: &Http,
+        diagnostics: &mut Diagnostics,
+        url: &str,
+    ) -> Result<(), TopCratesError> {
+        let content = http
+            .fetch(url, "cookbook manifest", diagnostics)?
+            .text()
+            .map_err(TopCratesError::network("Could not read cookbook manifest"))?;
 
-    // Find transitive deps compatible with the playground's platform.
-    let mut to_visit = valid_for_our_platform.clone();
-    while !to_visit.is_empty() {
-        let mut visit_next = BTreeSet::new();
+        #[derive(Deserialize)]
+        struct Manifest {
+            dependencies: toml::value::Table,
+        }
 
-        for package_id in to_visit {
-            for (dep_pkg, deps) in resolve.deps(package_id) {
-                let for_this_platform = deps.iter().any(|dep| {
-                    dep.platform().map_or(true, |platform| {
-                        platform.matches(PLAYGROUND_TARGET_PLATFORM, global.target_info.cfg())
-                    })
-                });
+        let manifest: Manifest = toml::from_str(&content).map_err(TopCratesError::toml_parse(
+            "Could not parse cookbook manifest",
+        ))?;
 
-                if for_this_platform {
-                    valid_for_our_platform.insert(dep_pkg);
-                    visit_next.insert(dep_pkg);
-                }
-            }
+        for name in manifest.dependencies.keys() {
+            self.add(Crate::named(InternedString::new(name)));
         }
-
-        to_visit = visit_next;
+        Ok(())
     }
 
-    // Remove invalid and excluded packages that have been added due to resolution
-    let package_ids = resolve
-        .iter()
-        .filter(|pkg| valid_for_our_platform.contains(pkg))
-        .filter(|pkg| !global.modifications.excluded(pkg.name().as_str()))
-        .collect_vec();
+    /// Add crates that have been hand-picked
+    fn add_curated_crates(&mut self, modifications: &Modifications) {
+        for (&name, requirement) in &modifications.additions {
+            self.add(Crate {
+                requirement: requirement.clone(),
+                curated: true,
+                ..Crate::named(name)
+            });
+        }
+    }
 
-    let packages = bulk_download(global, &package_ids);
+    /// Keeps only the crates called one of `names`, adding those that
+    /// aren't listed.
+    fn restrict_to(&mut self, names: &[String]) {
+        let wanted = names
+            .iter()
+            .map(|name| normalize_crate_name(name))
+            .collect::<BTreeSet<_>>();
+        self.crates
+            .retain(|krate| wanted.contains(&normalize_crate_name(&krate.name)));
+        for name in names {
+            let normalized = normalize_crate_name(name);
+            if !self
+                .crates
+                .iter()
+                .any(|krate| normalize_crate_name(&krate.name) == normalized)
+            {
+                self.crates.push(Crate::named(InternedString::new(name)));
+            }
+        }
+    }
 
-    for download in packages {
-        let id = download.package_id();
-        let lib_target = download
-            .library()
-            .unwrap_or_else(|| panic!("{} did not have a library", id))
-            .clone();
-        let mut dep = ResolvedDep {
-            summary: download.summary().clone(),
-            lib_target,
-            features: resolve.features(id).iter().copied().collect(),
-            // If enabled, all default features are already included in
-            // `features` by the resolver.
-            uses_default_features: false,
-        };
-        if let Some((features, _default_features)) = playground_metadata_features(&download) {
-            dep.features.extend(features);
+    /// Adds `krate` unless a crate of the same name is already listed,
+    /// in which case only its version requirement is kept, so that
+    /// each crate is resolved once.
+    fn add(&mut self, krate: Crate) {
+        match self.crates.iter_mut().find(|c| c.name == krate.name) {
+            Some(existing) => {
+                if existing.requirement.is_none() {
+                    existing.requirement = krate.requirement;
+                }
+                existing.curated |= krate.curated;
+            }
+            None => self.crates.push(krate),
         }
-        crates.insert(id, dep);
     }
 }
 
-pub fn generate_info(
-    modifications: &Modifications,
-) -> (BTreeMap<String, DependencySpec>, Vec<CrateInformation>) {
-    // Setup to interact with cargo.
-    let config = Config::default().expect("Unable to create default Cargo config");
-    let _lock = config.acquire_package_cache_lock();
-    let mut global = make_global_state(&config, modifications);
+/// Finds the features specified by the custom metadata of `pkg`, and
+/// whether it sets `all-features`.
+///
+/// Our custom metadata format looks like:
+///
+/// ```toml
+/// [package.metadata.playground]
+/// default-features = true
+/// features = ["std", "extra-traits"]
+/// all-features = false
+/// all-features-except = ["backend-a"]
+/// blacklist-features = ["nightly"]
+/// enable-optional-deps = false
+/// ```
+///
+/// All fields are optional. `all-features-except` only matters when
+/// `all-features` is set, leaving out features that can't be enabled
+/// alongside the rest. `enable-optional-deps` adds the features of
+/// [`optional_dependency_features`] to `features`; it changes nothing
+/// when `all-features` is set, which already enables them. The
+/// blacklisted features, along with those in `blacklist`, are left
+/// out even when `all-features` is set or the default features would
+/// enable them.
+fn playground_metadata_features(
+    pkg: &Package,
+    blacklist: &BTreeSet<InternedString>,
+    diagnostics: &mut Diagnostics,
+) -> Result<Option<(BTreeSet<InternedString>, bool, bool)>, MetadataError> {
+    let playground_metadata = match pkg
+        .manifest()
+        .custom_metadata()
+        .and_then(|custom_metadata| custom_metadata.get("playground"))
+    {
+        Some(playground_metadata) => playground_metadata,
+        None => return Ok(None),
+    };
 
-    let mut resolved_crates = populate_initial_direct_dependencies(&mut global);
+    #[derive(Deserialize)]
+    #[serde(default, rename_all = "kebab-case")]
+    struct Metadata {
+        features: BTreeSet<InternedString>,
+        default_features: bool,
+        all_features: bool,
+        all_features_except: BTreeSet<InternedString>,
+        blacklist_features: BTreeSet<InternedString>,
+        enable_optional_deps: bool,
+    }
 
-    loop {
-        let num_crates_before = resolved_crates.len();
-        extend_direct_dependencies(&mut global, &mut resolved_crates);
-        if num_crates_before == resolved_crates.len() {
-            break;
+    impl Default for Metadata {
+        fn default() -> Self {
+            Metadata {
+                features: BTreeSet::new(),
+                default_features: true,
+                all_features: false,
+                all_features_except: BTreeSet::new(),
+                blacklist_features: BTreeSet::new(),
+                enable_optional_deps: false,
+            }
         }
     }
 
-    let dependencies = generate_dependency_specs(&resolved_crates);
-    let infos = generate_crate_information(&dependencies);
-    (dependencies, infos)
-}
+    let metadata = playground_metadata
+        .clone()
+        .try_into::<Metadata>()
+        .map_err(|source| MetadataError {
+            name: pkg.name().to_string(),
+            version: pkg.version().clone(),
+            metadata: playground_metadata.to_string(),
+            source: source.into(),
+        })?;
 
-fn generate_dependency_specs(
-    crates: &BTreeMap<PackageId, ResolvedDep>,
-) -> BTreeMap<String, DependencySpec> {
-    // Sort all packages by name then version (descending), so that
-    // when we group them we know we get all the same crates together
-    // and the newest version first.
-    let mut crates = crates.values().collect_vec();
-    crates.sort_by(|a, b| {
-        let name_cmp = a.summary.name().as_str().cmp(b.summary.name().as_str());
-        let version_cmp = a.summary.version().cmp(b.summary.version());
-        name_cmp.then(version_cmp.reverse())
-    });
+    // If `all-features` is set then we ignore `features`.
+    let summary = pkg.summary();
+    let mut enabled_features: BTreeSet<InternedString> = if metadata.all_features {
+        if summary.features().is_empty() {
+            // Harmless, but it usually means the metadata has outlived
+            // the features it was written for.
+            diagnostics.note_for(
+                pkg.package_id(),
+                "`all-features` is set but the crate declares no features",
+            );
+        }
+        summary
+            .features()
+            .keys()
+            .filter(|feature| !metadata.all_features_except.contains(*feature))
+            .copied()
+            .collect()
+    } else {
+        let mut features =
+            known_features(pkg, metadata.features, "Playground metadata", diagnostics);
+        if metadata.enable_optional_deps {
+            features.extend(optional_dependency_features(summary));
+        }
+        features
+    };
 
-    let mut dependencies = BTreeMap::new();
-    for (name, pkgs) in &crates.iter().group_by(|dep| dep.summary.name()) {
-        let mut first = true;
+    let blacklist = blacklist
+        .union(&metadata.blacklist_features)
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let mut default_features = metadata.default_features;
 
-        for dep in pkgs {
-            let summary = &dep.summary;
-            let version = summary.version();
+    // When the default features would bring in a blacklisted feature,
+    // turn them off and ask for the rest of them explicitly instead.
+    if default_features || enabled_features.contains("default") {
+        let defaults = summary
+            .features()
+            .get("default")
+            .map(|values| &values[..])
+            .unwrap_or_default();
+        let blocked = |value: &&FeatureValue| enables_blacklisted(value, &blacklist);
+        if blacklist.contains("default") || defaults.iter().any(|value| blocked(&value)) {
+            default_features = false;
+            enabled_features.remove("default");
+            for value in defaults.iter().filter(|value| !blocked(value)) {
+                match requested_feature(summary, InternedString::new(&value.to_string())) {
+                    Ok(feature) => {
+                        enabled_features.insert(feature);
+                    }
+                    Err(reason) => diagnostics.note_for(
+                        pkg.package_id(),
+                        format!(
+                            "Default feature `{}` is left out with the default features, {}",
+                            value, reason
+                        ),
+                    ),
+                }
+            }
+        }
+    }
 
-            // We see the newest version first. Any subsequent
-            // versions will have their version appended so that they
-            // are uniquely named
-            let crate_name = dep.lib_target.crate_name();
-            let exposed_name = if first {
-                crate_name
-            } else {
-                format!(
-                    "{}_{}_{}_{}",
-                    crate_name, version.major, version.minor, version.patch
-                )
-            };
+    enabled_features.retain(|feature| !blacklist.contains(feature));
 
-            let mut features = dep.features.clone();
-            let mut default_features = dep.uses_default_features;
-            if features.contains("default") || summary.features().get("default").is_none() {
-                features.remove("default");
-                default_features = true;
+    // Even with no features left, the metadata is returned so that a
+    // crate opting out of its default features keeps doing so.
+    Ok(Some((
+        enabled_features,
+        default_features,
+        metadata.all_features,
+    )))
+}
+
+/// Whether enabling `value` would turn on any feature in `blacklist`.
+///
+/// Besides a feature itself, `dep:name` and `name/feature` turn on the
+/// optional dependency's feature of the same name, while the weak
+/// `name?/feature` only turns on the feature of the dependency.
+fn enables_blacklisted(value: &FeatureValue, blacklist: &BTreeSet<InternedString>) -> bool {
+    match value {
+        FeatureValue::Feature(feature) => blacklist.contains(feature),
+        FeatureValue::Dep { dep_name } => blacklist.contains(dep_name),
+        FeatureValue::DepFeature { dep_name, weak, .. } => {
+            blacklist.contains(value.to_string().as_str())
+                || (!weak && blacklist.contains(dep_name))
+        }
+    }
+}
+
+/// How `feature` can be asked for of the crate described by
+/// `summary`, or why it can't be.
+///
+/// As well as the crate's own features, `name/feature` and
+/// `name?/feature` may enable a feature of one of its dependencies,
+/// which are passed through as they are. `dep:name` can only be asked
+/// for when the optional dependency is also a feature of the same
+/// name, as dependents can't use that syntax.
+fn requested_feature(summary: &Summary, feature: InternedString) -> Result<InternedString, String> {
+    let dependency = |name: InternedString| {
+        summary
+            .dependencies()
+            .iter()
+            .find(|dep| dep.name_in_toml() == name)
+    };
+    match FeatureValue::new(feature) {
+        FeatureValue::Feature(name) if summary.features().contains_key(&name) => Ok(name),
+        FeatureValue::Feature(_) => Err("which the crate does not have".to_owned()),
+        FeatureValue::Dep { dep_name } => match dependency(dep_name) {
+            Some(dep) if dep.is_optional() && summary.features().contains_key(&dep_name) => {
+                Ok(dep_name)
             }
+            Some(dep) if dep.is_optional() => Err(format!(
+                "but the optional dependency `{}` is only enabled through the crate's features",
+                dep_name
+            )),
+            _ => Err(format!(
+                "but the crate has no optional dependency `{}`",
+                dep_name
+            )),
+        },
+        FeatureValue::DepFeature { dep_name, .. } => match dependency(dep_name) {
+            Some(_) => Ok(feature),
+            None => Err(format!("but the crate has no dependency `{}`", dep_name)),
+        },
+    }
+}
 
-            dependencies.insert(
-                exposed_name,
-                DependencySpec {
-                    package: name.to_string(),
-                    version: version.clone(),
-                    features,
-                    default_features,
-                },
-            );
+/// The features named after an optional dependency that enable it,
+/// whether they are implicit or written with `dep:`.
+fn optional_dependency_features(summary: &Summary) -> BTreeSet<InternedString> {
+    summary
+        .features()
+        .iter()
+        .filter(|(feature, values)| {
+            values.iter().any(
+                |value| matches!(value, FeatureValue::Dep { dep_name } if dep_name == *feature),
+            )
+        })
+        .map(|(feature, _)| *feature)
+        .collect()
+}
 
-            first = false;
+/// The `features` that `pkg` has, warning about the rest.
+///
+/// Asking for a feature that doesn't exist would fail the whole
+/// resolve, so only the ones that do are kept. See
+/// [`requested_feature`] for the syntax that is understood.
+fn known_features(
+    pkg: &Package,
+    features: BTreeSet<InternedString>,
+    origin: &str,
+    diagnostics: &mut Diagnostics,
+) -> BTreeSet<InternedString> {
+    let mut known = BTreeSet::new();
+    for feature in features {
+        match requested_feature(pkg.summary(), feature) {
+            Ok(feature) => {
+                known.insert(feature);
+            }
+            Err(reason) => diagnostics.warn_for(
+                pkg.package_id(),
+                format!("{} enables feature `{}`, {}", origin, feature, reason),
+            ),
         }
     }
-
-    dependencies
+    known
 }
 
-fn generate_crate_information(
-    dependencies: &BTreeMap<String, DependencySpec>,
-) -> Vec<CrateInformation> {
-    let mut infos = Vec::new();
+/// Like [`playground_metadata_features`], but remembers any metadata
+/// that could not be parsed instead of failing, and adds the features
+/// that `crate-modifications.toml` asks for.
+///
+/// Whether the metadata sets `all-features` is returned alongside.
+fn metadata_features(
+    global: &mut GlobalState<'_>,
+    pkg: &Package,
+) -> (Option<(BTreeSet<InternedString>, bool)>, bool) {
+    if global.options.minimal_features {
+        return (Some((BTreeSet::new(), false)), false);
+    }
+    let modifications = global.modifications;
+    let blacklist = &modifications.blacklist_features;
+    let (features, all_features) =
+        match playground_metadata_features(pkg, blacklist, global.diagnostics) {
+            Ok(Some((features, default_features, all_features))) => {
+                (Some((features, default_features)), all_features)
+            }
+            Ok(None) => (None, false),
+            Err(e) => {
+                global.metadata_errors.insert(pkg.package_id(), e);
+                (None, false)
+            }
+        };
+    let features = with_curated_features(pkg, features, modifications, global.diagnostics);
+    (features, all_features)
+}
 
-    for (exposed_name, dependency_spec) in dependencies {
-        infos.push(CrateInformation {
-            name: dependency_spec.package.clone(),
-            version: dependency_spec.version.clone(),
-            id: exposed_name.clone(),
-        });
+/// Adds the features that `modifications` enables for `pkg` to those
+/// chosen from its metadata. Crates without metadata keep their
+/// default features.
+fn with_curated_features(
+    pkg: &Package,
+    mut features: Option<(BTreeSet<InternedString>, bool)>,
+    modifications: &Modifications,
+    diagnostics: &mut Diagnostics,
+) -> Option<(BTreeSet<InternedString>, bool)> {
+    if let Some(extra) = modifications.features.get(&pkg.name()) {
+        let extra = known_features(pkg, extra.clone(), "crate-modifications.toml", diagnostics);
+        let (enabled, _default_features) = features.get_or_insert_with(|| (BTreeSet::new(), true));
+        enabled.extend(extra);
+    }
+    if modifications.enable_optional_deps.contains(&pkg.name()) {
+        let optional = optional_dependency_features(pkg.summary())
+            .into_iter()
+            .filter(|feature| !modifications.blacklist_features.contains(feature));
+        let (enabled, _default_features) = features.get_or_insert_with(|| (BTreeSet::new(), true));
+        enabled.extend(optional);
     }
+    features
+}
 
+/// The registry that crates are chosen from.
+fn registry_source_id(
+    config: &Config,
+    options: &GenerateOptions,
+) -> Result<SourceId, TopCratesError> {
+    let registry_url = options
+        .registry
+        .clone()
+        .or_else(|| env::var(REGISTRY_ENV).ok());
+    match registry_url {
+        Some(url) => {
+            let url = parse_registry_url(&url).map_err(|e| TopCratesError::Registry {
+                context: "Invalid registry".to_owned(),
+                source: anyhow::anyhow!(e),
+            })?;
+            let url = url.into_url().map_err(TopCratesError::registry(format!(
+                "Invalid registry URL `{}`",
+                url
+            )))?;
+            SourceId::for_registry(&url)
+        }
+        None => SourceId::crates_io(config),
+    }
+    .map_err(TopCratesError::registry(
+        "Unable to create registry source ID",
+    ))
+}
+
+fn make_global_state<'cfg>(
+    config: &'cfg Config,
+    modifications: &'cfg Modifications,
+    options: &'cfg GenerateOptions,
+    diagnostics: &'cfg mut Diagnostics,
+) -> Result<GlobalState<'cfg>, TopCratesError> {
+    // Information about the playground's target platform.
+    let compile_target = CompileTarget::new(PLAYGROUND_TARGET_PLATFORM)
+        .map_err(TopCratesError::registry("Unable to create a CompileTarget"))?;
+    let compile_kind = CompileKind::Target(compile_target);
+    let rustc = config
+        .load_global_rustc(None)
+        .map_err(TopCratesError::registry("Unable to load the global rustc"))?;
+    let target_info = TargetInfo::new(config, &[compile_kind], &rustc, compile_kind)
+        .map_err(TopCratesError::registry("Unable to create a TargetInfo"))?;
+
+    // Registry of known packages.
+    let mut registry = PackageRegistry::new(config).map_err(TopCratesError::registry(
+        "Unable to create package registry",
+    ))?;
+    registry.lock_patches();
+
+    // Source for obtaining packages from the registry.
+    let registry_id = registry_source_id(config, options)?;
+    let yanked_whitelist = HashSet::new();
+    let mut source = RegistrySource::remote(registry_id, &yanked_whitelist, config)
+        .map_err(TopCratesError::registry("Unable to create registry source"))?;
+    let update = !options.offline && !options.skip_update && options.index_rev.is_none();
+    if update {
+        info!("Updating the registry index");
+        source.invalidate_cache();
+    } else {
+        info!("Using the local registry index without updating it");
+    }
+    let updating = Instant::now();
+    source
+        .block_until_ready()
+        .map_err(TopCratesError::registry(
+            "Unable to wait for registry to be ready",
+        ))?;
+    if update {
+        info!(
+            "Updated the registry index in {:.1}s",
+            updating.elapsed().as_secs_f64()
+        );
+    }
+    let mut timings = Timings::new();
+    timings.since("update registry", updating);
+
+    let api = if registry_id.is_crates_io() {
+        env::var(CRATES_IO_API_ENV)
+            .map(|base| base.trim_end_matches('/').to_owned())
+            .unwrap_or_else(|_| CRATES_IO_API.to_owned())
+    } else {
+        registry_api(&mut source)?.ok_or_else(|| TopCratesError::Registry {
+            context: format!("Unable to list the top crates of {}", registry_id.url()),
+            source: anyhow::anyhow!("the registry does not have a web API"),
+        })?
+    };
+
+    let query_cache = options.query_cache.as_ref().and_then(|dir| {
+        let revision = match index_revision(config, registry_id) {
+            Some(revision) => revision,
+            None => {
+                diagnostics.warn("Unable to determine the registry index revision; not caching");
+                return None;
+            }
+        };
+        // Each registry has its own entries, as a mirror may not have
+        // the same versions of a crate as crates.io.
+        match QueryCache::new(dir.join(registry_directory(registry_id)), revision) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                diagnostics.warn(format!(
+                    "Unable to create the query cache in {}: {}",
+                    dir.display(),
+                    e
+                ));
+                None
+            }
+        }
+    });
+
+    let backoff = Backoff::new(options.retries, options.max_retry_after);
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| match env::var(CONCURRENCY_ENV) {
+            Ok(jobs) => jobs.parse().unwrap_or_else(|_| {
+                diagnostics.warn(format!(
+                    "Ignoring {}, which is not a number: `{}`",
+                    CONCURRENCY_ENV, jobs
+                ));
+                DEFAULT_CONCURRENCY
+            }),
+            Err(_) => DEFAULT_CONCURRENCY,
+        });
+
+    let http = Http::new(backoff.clone(), diagnostics)?;
+
+    let locked = match &options.locked_from {
+        Some(path) => locked_packages(path, registry_id)?,
+        None => BTreeSet::new(),
+    };
+
+    Ok(GlobalState {
+        config,
+        target_info,
+        registry,
+        registry_id,
+        api,
+        source,
+        modifications,
+        options,
+        diagnostics,
+        metadata_errors: BTreeMap::new(),
+        query_cache,
+        http,
+        backoff,
+        jobs: jobs.max(1),
+        listings: BTreeMap::new(),
+        redundant_additions: BTreeSet::new(),
+        listed: 0,
+        owners: BTreeMap::new(),
+        locked,
+        failed_downloads: BTreeSet::new(),
+        timings,
+    })
+}
+
+/// The web API the registry's `config.json` points to, if any.
+fn registry_api(source: &mut RegistrySource<'_>) -> Result<Option<String>, TopCratesError> {
+    let context = "Unable to read the registry configuration";
+    loop {
+        match source.config() {
+            Poll::Ready(config) => {
+                let config = config.map_err(TopCratesError::registry(context))?;
+                return Ok(config.and_then(|config| config.api));
+            }
+            Poll::Pending => source
+                .block_until_ready()
+                .map_err(TopCratesError::registry(context))?,
+        }
+    }
+}
+
+/// Checks that `s` looks like the index URL of a registry cargo can
+/// use, such as `https://github.com/rust-lang/crates.io-index` or
+/// `sparse+https://index.crates.io/`.
+pub fn parse_registry_url(s: &str) -> Result<String, String> {
+    let (scheme, rest) = s
+        .split_once("://")
+        .ok_or_else(|| format!("`{}` is not a URL", s))?;
+    let scheme = scheme.strip_prefix("sparse+").unwrap_or(scheme);
+    if !matches!(scheme, "http" | "https" | "file") {
+        return Err(format!(
+            "`{}` is not a registry URL; expected http, https, or file",
+            s
+        ));
+    }
+    if rest.is_empty() {
+        return Err(format!("`{}` has no host or path", s));
+    }
+    Ok(s.to_owned())
+}
+
+/// Where to download each of `package_ids` from.
+fn package_sources<'src, 'cfg: 'src>(
+    source: &'src mut RegistrySource<'cfg>,
+    config: &'cfg Config,
+    registry_id: SourceId,
+    package_ids: &[PackageId],
+) -> Result<SourceMap<'src>, TopCratesError> {
+    let mut sources = SourceMap::new();
+    sources.insert(Box::new(source));
+    // A registry's crates may depend on those of another registry, and
+    // the same name may be in both, so each package is downloaded from
+    // the source it was resolved from.
+    let yanked_whitelist = HashSet::new();
+    for source_id in other_sources(package_ids, registry_id) {
+        let source =
+            source_id
+                .load(config, &yanked_whitelist)
+                .map_err(TopCratesError::registry(format!(
+                    "Unable to create a source for {}",
+                    source_id
+                )))?;
+        sources.insert(source);
+    }
+    Ok(sources)
+}
+
+fn bulk_download(
+    global: &mut GlobalState<'_>,
+    package_ids: &[PackageId],
+) -> Result<Vec<Package>, TopCratesError> {
+    let sources = package_sources(
+        &mut global.source,
+        global.config,
+        global.registry_id,
+        package_ids,
+    )?;
+    let package_set = PackageSet::new(package_ids, sources, global.config)
+        .map_err(TopCratesError::registry("Unable to create a PackageSet"))?;
+
+    let limit = global.options.download_concurrency.max(1);
+    info!("Downloading {} packages", package_ids.len());
+    let downloading = Instant::now();
+    let mut packages = Vec::new();
+    let mut progress = Progress::new("Downloading packages", package_ids.len());
+    // One failure stops the rest from being started, so whatever is
+    // left is then downloaded one at a time to find out which fail.
+    if let Err(e) = download_all(&package_set, limit, &mut packages, &mut progress) {
+        debug!("Downloading the remaining packages one at a time: {:#}", e);
+    }
+    let downloaded = packages
+        .iter()
+        .map(Package::package_id)
+        .collect::<HashSet<_>>();
+    let mut failed = Vec::new();
+    for id in package_set.package_ids() {
+        if downloaded.contains(&id) {
+            continue;
+        }
+        progress.working_on(id);
+        let diagnostics = &mut *global.diagnostics;
+        let package = global.backoff.run(
+            || {
+                package_set
+                    .get_one(id)
+                    .cloned()
+                    .map_err(TopCratesError::registry(format!(
+                        "Unable to download {}",
+                        id
+                    )))
+            },
+            TopCratesError::is_transient,
+            |attempt, e| {
+                diagnostics.note_for(
+                    id,
+                    format!("Retrying the download after attempt {}: {}", attempt, e),
+                )
+            },
+        );
+        match package {
+            Ok(package) => packages.push(package),
+            Err(e) => failed.push((id, e)),
+        }
+        progress.done();
+    }
+    drop(progress);
+
+    if !failed.is_empty() && !global.options.skip_failed_downloads {
+        let (_, e) = failed.swap_remove(0);
+        return Err(e);
+    }
+    for (id, e) in failed {
+        global
+            .diagnostics
+            .warn_for(id, format!("Left out as it could not be downloaded: {}", e));
+        global.failed_downloads.insert(id);
+    }
+
+    global.timings.since("download packages", downloading);
+
+    // Downloads finish in whatever order they happen to.
+    packages.sort_by_key(Package::package_id);
+    verify_checksums(global.config, &packages)?;
+    Ok(packages)
+}
+
+/// Downloads every package of `package_set` with only `limit` in
+/// flight at once, starting the next as soon as any of them finishes,
+/// until one of them fails.
+fn download_all(
+    package_set: &PackageSet<'_>,
+    limit: usize,
+    packages: &mut Vec<Package>,
+    progress: &mut Progress,
+) -> Result<(), TopCratesError> {
+    let context = "Unable to download packages";
+    let mut downloads = package_set
+        .enable_download()
+        .map_err(TopCratesError::registry(context))?;
+    for id in package_set.package_ids() {
+        debug!("Downloading {}", id);
+        progress.working_on(id);
+        while downloads.remaining() >= limit {
+            let package = downloads
+                .wait()
+                .map_err(TopCratesError::registry(context))?;
+            packages.push(package.clone());
+            progress.done();
+        }
+        let package = downloads
+            .start(id)
+            .map_err(TopCratesError::registry(context))?;
+        if let Some(package) = package {
+            packages.push(package.clone());
+            progress.done();
+        }
+    }
+    while downloads.remaining() > 0 {
+        let package = downloads
+            .wait()
+            .map_err(TopCratesError::registry(context))?;
+        packages.push(package.clone());
+        progress.done();
+    }
+    Ok(())
+}
+
+/// The sources of `package_ids` other than the registry's.
+fn other_sources(package_ids: &[PackageId], registry_id: SourceId) -> BTreeSet<SourceId> {
+    package_ids
+        .iter()
+        .map(|id| id.source_id())
+        .filter(|&source_id| source_id != registry_id)
+        .collect()
+}
+
+/// Checks that each downloaded `.crate` file is the one the registry
+/// index describes.
+///
+/// Cargo only checks a `.crate` file as it downloads it, and trusts
+/// one that is already in its cache, so this catches files that were
+/// changed or copied into the cache afterwards, such as by restoring
+/// a CI cache made with another registry.
+///
+/// Packages whose registry doesn't provide a checksum aren't checked,
+/// but a `.crate` file that can't be read is an error, as it can't be
+/// told apart from one that was tampered with.
+fn verify_checksums(config: &Config, packages: &[Package]) -> Result<(), TopCratesError> {
+    for package in packages {
+        let expected = match package.summary().checksum() {
+            Some(expected) => expected,
+            None => continue,
+        };
+        let id = package.package_id();
+        let path = crate_file(config, id);
+        let actual = File::open(&path)
+            .and_then(|file| Sha256::new().update_file(&file).map(Sha256::finish_hex))
+            .map_err(TopCratesError::io(format!(
+                "Unable to verify the checksum of {}",
+                path.display()
+            )))?;
+        if actual != expected {
+            return Err(TopCratesError::ChecksumMismatch {
+                id: id.to_string(),
+                expected: expected.to_owned(),
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Where cargo keeps the downloaded `.crate` file of `id`, which is
+/// in a directory of its own for each registry.
+fn crate_file(config: &Config, id: PackageId) -> PathBuf {
+    config
+        .registry_cache_path()
+        .join(registry_directory(id.source_id()))
+        .into_path_unlocked()
+        .join(format!("{}-{}.crate", id.name(), id.version()))
+}
+
+/// The details of a published version of a crate that are needed to
+/// decide whether to use it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Candidate {
+    version: Version,
+    checksum: Option<String>,
+}
+
+impl From<&Summary> for Candidate {
+    fn from(summary: &Summary) -> Self {
+        Candidate {
+            version: summary.version().clone(),
+            checksum: summary.checksum().map(str::to_owned),
+        }
+    }
+}
+
+/// Query the registry for the published versions of each crate.
+///
+/// Usefully, this doesn't seem to include yanked versions.
+///
+/// Every crate is asked about before waiting for any of the answers,
+/// so a remote registry can fetch their index entries concurrently.
+///
+/// A crate whose entry in the local index is damaged is queried once
+/// more with the entry read afresh, and is left out with a warning if
+/// that fails too, rather than stopping the run.
+fn query_candidates(
+    global: &mut GlobalState<'_>,
+    names: impl IntoIterator<Item = InternedString>,
+) -> Result<BTreeMap<InternedString, Vec<Candidate>>, TopCratesError> {
+    let mut found = BTreeMap::new();
+    let mut pending = BTreeMap::new();
+    for name in names {
+        if found.contains_key(&name) || pending.contains_key(&name) {
+            continue;
+        }
+        if let Some(candidates) = global.query_cache.as_ref().and_then(|c| c.get(&name)) {
+            debug!("Using the remembered registry query for {}", name);
+            found.insert(name, candidates);
+            continue;
+        }
+
+        let version = None;
+        let dep = Dependency::parse(name, version, global.registry_id).map_err(
+            TopCratesError::registry(format!("Unable to parse dependency for {}", name)),
+        )?;
+        pending.insert(name, dep);
+    }
+
+    info!(
+        "Querying the registry for {} crates",
+        found.len() + pending.len()
+    );
+    for name in pending.keys() {
+        debug!("Querying the registry for {}", name);
+    }
+
+    let querying = Instant::now();
+    let mut queried = BTreeMap::new();
+    let mut corrupt = BTreeMap::new();
+    let mut progress = Progress::new("Querying the registry", pending.len());
+    let source = &mut global.source;
+    let diagnostics = &mut *global.diagnostics;
+    let jobs = global.jobs;
+    global.backoff.run(
+        || loop {
+            let mut failure = None;
+            // Only the first `jobs` queries are polled, so no more than
+            // that are in flight; each answered one makes room for the
+            // next.
+            let mut polled = 0;
+            pending.retain(|&name, dep| {
+                if polled == jobs {
+                    return true;
+                }
+                polled += 1;
+                match source.query_vec(dep, QueryKind::Exact) {
+                    Poll::Ready(Ok(matches)) => {
+                        queried.insert(name, matches.iter().map(Candidate::from).collect_vec());
+                        progress.working_on(name);
+                        progress.done();
+                        false
+                    }
+                    Poll::Ready(Err(e)) => {
+                        let context = format!("Unable to query registry for {}", name);
+                        let e = TopCratesError::registry(context)(e);
+                        if e.is_index_corruption() {
+                            progress.done();
+                            corrupt.insert(name, (dep.clone(), e));
+                            return false;
+                        }
+                        failure.get_or_insert(e);
+                        true
+                    }
+                    Poll::Pending => true,
+                }
+            });
+            if let Some(e) = failure {
+                return Err(e);
+            }
+            if pending.is_empty() {
+                return Ok(());
+            }
+            source
+                .block_until_ready()
+                .map_err(TopCratesError::registry("Unable to wait for the registry"))?;
+        },
+        TopCratesError::is_transient,
+        |attempt, e| diagnostics.note(format!("Retrying queries after attempt {}: {}", attempt, e)),
+    )?;
+    drop(progress);
+
+    // Damaged entries in the local index are read afresh once, as that
+    // is often enough, before their crates are left out.
+    if !corrupt.is_empty() {
+        forget_index_entries(global, corrupt.keys().copied());
+    }
+    for (name, (dep, e)) in corrupt {
+        global.diagnostics.note_for(
+            name,
+            format!("Reading its registry index entry again: {}", e),
+        );
+        match requery(global, &dep) {
+            Ok(candidates) => {
+                queried.insert(name, candidates);
+            }
+            Err(e) => global.diagnostics.warn_for(
+                name,
+                format!(
+                    "Left out because its registry index entry could not be read: {}",
+                    e
+                ),
+            ),
+        }
+    }
+    global.timings.since("query registry", querying);
+
+    if let Some(cache) = &global.query_cache {
+        for (name, candidates) in &queried {
+            if let Err(e) = cache.put(name, candidates) {
+                global
+                    .diagnostics
+                    .warn_for(name, format!("Unable to cache the registry query: {}", e));
+            }
+        }
+    }
+
+    found.append(&mut queried);
+    Ok(found)
+}
+
+/// Throws away cargo's cached copies of the index entries of `names`,
+/// so that they are read from the index itself when they are queried
+/// again. The index is refreshed too, once for all of them, unless it
+/// is being kept as it is.
+fn forget_index_entries(
+    global: &mut GlobalState<'_>,
+    names: impl IntoIterator<Item = InternedString>,
+) {
+    let cache = global
+        .config
+        .registry_index_path()
+        .join(registry_directory(global.registry_id))
+        .join(".cache")
+        .into_path_unlocked();
+    for name in names {
+        // There may well be no cached copy.
+        let _ = fs::remove_file(cache.join(index_entry_path(&name)));
+    }
+
+    let options = global.options;
+    if !options.offline && !options.skip_update && options.index_rev.is_none() {
+        global.source.invalidate_cache();
+    }
+}
+
+/// Queries the registry for `dep` again, after its index entry has been
+/// forgotten by [`forget_index_entries`].
+fn requery(
+    global: &mut GlobalState<'_>,
+    dep: &Dependency,
+) -> Result<Vec<Candidate>, TopCratesError> {
+    loop {
+        match global.source.query_vec(dep, QueryKind::Exact) {
+            Poll::Ready(matches) => {
+                let context = format!("Unable to query registry for {}", dep.package_name());
+                let matches = matches.map_err(TopCratesError::registry(context))?;
+                return Ok(matches.iter().map(Candidate::from).collect());
+            }
+            Poll::Pending => global
+                .source
+                .block_until_ready()
+                .map_err(TopCratesError::registry("Unable to wait for the registry"))?,
+        }
+    }
+}
+
+/// Where cargo keeps the index entry of the crate `name`, relative to
+/// the root of the index.
+fn index_entry_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/// Find the versions worth using, newest first, only considering those
+/// that satisfy `pin` if there is one.
+///
+/// Prereleases are skipped unless `allow_prerelease` is set or `pin`
+/// names them explicitly (such as `=1.0.0-beta.2`), so a crate that
+/// has only ever published prereleases otherwise has no viable
+/// versions at all.
+///
+/// A registry may return the same version more than once, so ties are
+/// broken by checksum to always put the same candidate first.
+fn viable_candidates(
+    candidates: Vec<Candidate>,
+    pin: Option<&VersionReq>,
+    allow_prerelease: bool,
+) -> Vec<Candidate> {
+    let mut candidates = candidates
+        .into_iter()
+        .filter(|candidate| {
+            // Requirements only ever match prereleases that they name.
+            let pinned = pin.is_some_and(|pin| pin.matches(&candidate.version));
+            allow_prerelease || pinned || !candidate.version.is_prerelease()
+        })
+        .filter(|candidate| pin.is_none_or(|pin| pin.matches(&candidate.version)))
+        .collect_vec();
+    candidates.sort_by(|a, b| {
+        b.version
+            .cmp(&a.version)
+            .then_with(|| b.checksum.cmp(&a.checksum))
+    });
+    candidates
+}
+
+/// The name cargo gives the directories it keeps each registry's index
+/// and downloads in.
+fn registry_directory(source_id: SourceId) -> String {
+    let host = source_id.url().host_str().unwrap_or("");
+    format!("{}-{}", host, short_hash(&source_id))
+}
+
+/// The revision of the registry index that queries are answered from.
+fn index_revision(config: &Config, source_id: SourceId) -> Option<String> {
+    let path = config
+        .registry_index_path()
+        .join(registry_directory(source_id));
+
+    let repo = git2::Repository::open(path.into_path_unlocked()).ok()?;
+    let head = repo.refname_to_id(INDEX_HEAD).ok()?;
+    Some(head.to_string())
+}
+
+/// The ref of a git registry index that cargo reads the index from.
+const INDEX_HEAD: &str = "refs/remotes/origin/HEAD";
+
+/// Makes a cargo home of its own whose git index of `source_id` is at
+/// `rev`, updating the shared index first if it doesn't have that
+/// revision yet. A sparse index has no revisions, so it never has `rev`.
+///
+/// The shared index is only read, as everything else that uses it
+/// expects it to stay at the newest revision. The private index borrows
+/// its objects, and the downloaded crates and the configuration are
+/// shared with the rest of cargo.
+fn index_home_at(
+    config: &Config,
+    source_id: SourceId,
+    rev: &str,
+    offline: bool,
+) -> Result<tempfile::TempDir, TopCratesError> {
+    let unavailable = |reason: &str| TopCratesError::Registry {
+        context: format!("Revision {} of the registry index is not available", rev),
+        source: anyhow::anyhow!("{}", reason),
+    };
+    let unusable = |e: anyhow::Error| TopCratesError::Registry {
+        context: format!("Unable to check out revision {} of the registry index", rev),
+        source: e,
+    };
+
+    let index = registry_directory(source_id);
+    let path = config
+        .registry_index_path()
+        .join(&index)
+        .into_path_unlocked();
+    let find = || {
+        let repo = git2::Repository::open(&path).ok()?;
+        let commit = repo.revparse_single(rev).ok()?.peel_to_commit().ok()?.id();
+        Some((repo, commit))
+    };
+
+    let (shared, commit) = match find() {
+        Some(found) => found,
+        None if offline => {
+            return Err(unavailable(
+                "it is not in the local index, which can't be updated offline",
+            ))
+        }
+        None => {
+            info!("Updating the registry index to find revision {}", rev);
+            let yanked_whitelist = HashSet::new();
+            let mut source = RegistrySource::remote(source_id, &yanked_whitelist, config)
+                .map_err(TopCratesError::registry("Unable to create registry source"))?;
+            source.invalidate_cache();
+            source
+                .block_until_ready()
+                .map_err(TopCratesError::registry(
+                    "Unable to update the registry index",
+                ))?;
+            find().ok_or_else(|| unavailable("the registry index does not have it"))?
+        }
+    };
+
+    let home = tempfile::tempdir().map_err(TopCratesError::io(
+        "Unable to create a cargo home for the registry index revision",
+    ))?;
+    let repo = git2::Repository::init_bare(home.path().join("registry/index").join(&index))
+        .map_err(anyhow::Error::from)
+        .map_err(unusable)?;
+    let alternates = repo.path().join("objects/info/alternates");
+    fs::write(
+        &alternates,
+        format!("{}\n", shared.path().join("objects").display()),
+    )
+    .map_err(TopCratesError::io(format!(
+        "Unable to write {}",
+        alternates.display()
+    )))?;
+    repo.reference(
+        INDEX_HEAD,
+        commit,
+        true,
+        &format!("check out revision {}", rev),
+    )
+    .map_err(anyhow::Error::from)
+    .map_err(unusable)?;
+
+    let shared_home = config.home().as_path_unlocked();
+    for file in ["config", "config.toml", "credentials", "credentials.toml"] {
+        let original = shared_home.join(file);
+        if original.exists() {
+            fs::copy(&original, home.path().join(file)).map_err(TopCratesError::io(format!(
+                "Unable to copy {}",
+                original.display()
+            )))?;
+        }
+    }
+    for dir in ["registry/cache", "registry/src"] {
+        let original = shared_home.join(dir);
+        fs::create_dir_all(&original)
+            .and_then(|()| symlink_dir(&original, &home.path().join(dir)))
+            .map_err(TopCratesError::io(format!(
+                "Unable to share {}",
+                original.display()
+            )))?;
+    }
+
+    info!("Using revision {} of the registry index", commit);
+    Ok(home)
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+/// Downloads the top crates, unless they were downloaded recently
+/// enough to use the remembered list.
+fn cached_top_crates(global: &mut GlobalState<'_>) -> Result<TopCrates, TopCratesError> {
+    let options = global.options;
+    // Any list is better than none when it can't be downloaded again.
+    let ttl = if options.offline {
+        Duration::MAX
+    } else {
+        options.top_crates_ttl
+    };
+    let cache = options
+        .top_crates_cache
+        .as_ref()
+        .map(|path| TopCratesCache::new(path, &global.api, ttl));
+
+    if let Some(cache) = &cache {
+        if !options.refresh_top_crates {
+            if let Some(top) = cache.get(options.count, options.sort) {
+                global.diagnostics.note(format!(
+                    "Using the top crates remembered in {}",
+                    cache.path().display()
+                ));
+                return Ok(top);
+            }
+        }
+    }
+
+    if options.offline {
+        let what = match &cache {
+            Some(cache) => format!("The top crates remembered in {}", cache.path().display()),
+            None => "The list of top crates".to_owned(),
+        };
+        return Err(TopCratesError::Offline { what });
+    }
+
+    info!(
+        "Downloading the top {} crates by {}",
+        options.count,
+        options.sort.as_str()
+    );
+    let downloading = Instant::now();
+    let top = TopCrates::download(
+        &global.http,
+        global.diagnostics,
+        &global.api,
+        options.count,
+        options.sort,
+        options.page_size,
+    )?;
+    global.timings.since("download top crates", downloading);
+    check_top_crates_count(
+        top.crates.len(),
+        options.count,
+        options.allow_empty,
+        global.diagnostics,
+    )?;
+
+    if let Some(cache) = &cache {
+        if let Err(e) = cache.put(options.count, options.sort, &top) {
+            global.diagnostics.warn(format!(
+                "Unable to remember the top crates in {}: {}",
+                cache.path().display(),
+                e
+            ));
+        }
+    }
+
+    Ok(top)
+}
+
+/// Refuses to carry on with fewer than half of the `count` top crates,
+/// such as when the registry answers with an empty list, unless
+/// `allow_empty` is set.
+fn check_top_crates_count(
+    fetched: usize,
+    count: usize,
+    allow_empty: bool,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), TopCratesError> {
+    if fetched * 2 >= count {
+        return Ok(());
+    }
+    if !allow_empty {
+        return Err(TopCratesError::TooFewTopCrates { fetched, count });
+    }
+    diagnostics.warn(format!(
+        "Only {} of the top {} crates were listed by the registry",
+        fetched, count
+    ));
+    Ok(())
+}
+
+/// Refuses to carry on without `what`, which can't be had offline,
+/// unless `allow_empty` is set, in which case `warning` is given.
+fn unavailable_offline(
+    global: &mut GlobalState<'_>,
+    what: &str,
+    warning: &str,
+) -> Result<(), TopCratesError> {
+    if !global.options.allow_empty {
+        return Err(TopCratesError::Offline {
+            what: what.to_owned(),
+        });
+    }
+    global.diagnostics.warn(warning);
+    Ok(())
+}
+
+/// The crates listed by crates.io (or the list used instead of it),
+/// the Rust Cookbook, and the categories and keywords asked for.
+fn listed_crates(global: &mut GlobalState<'_>) -> Result<TopCrates, TopCratesError> {
+    let mut top = match (
+        &global.options.crate_list,
+        &global.options.top_crates_snapshot,
+    ) {
+        (Some(lines), _) => TopCrates::from_list(lines)?,
+        (None, Some(path)) => TopCrates::from_snapshot(path)?,
+        (None, None) => cached_top_crates(global)?,
+    };
+    if global.options.offline {
+        unavailable_offline(
+            global,
+            "The Rust Cookbook's crates",
+            "Not adding the Rust Cookbook's crates as they can't be downloaded offline",
+        )?;
+    } else {
+        let url = &global.options.rust_cookbook_manifest;
+        top.add_rust_cookbook_crates(&global.http, global.diagnostics, url)?;
+    }
+    let modifications = global.modifications;
+    if !modifications.categories.is_empty() {
+        if global.options.offline {
+            unavailable_offline(
+                global,
+                "The top crates of categories",
+                "Not adding the top crates of categories as they can't be listed offline",
+            )?;
+        } else {
+            let listing = Instant::now();
+            top.add_category_crates(
+                &global.http,
+                global.diagnostics,
+                &global.api,
+                &modifications.categories,
+                modifications
+                    .category_count
+                    .unwrap_or(DEFAULT_CATEGORY_COUNT),
+                global.options.page_size,
+            )?;
+            global.timings.since("list category crates", listing);
+        }
+    }
+    if !modifications.keywords.is_empty() {
+        if global.options.offline {
+            unavailable_offline(
+                global,
+                "The top crates of keywords",
+                "Not adding the top crates of keywords as they can't be listed offline",
+            )?;
+        } else {
+            let listing = Instant::now();
+            top.add_keyword_crates(
+                &global.http,
+                global.diagnostics,
+                &global.api,
+                &modifications.keywords,
+                modifications.keyword_count.unwrap_or(DEFAULT_KEYWORD_COUNT),
+                global.options.page_size,
+            )?;
+            global.timings.since("list keyword crates", listing);
+        }
+    }
+    Ok(top)
+}
+
+/// The curated additions that `listed` already has. Those with a
+/// version requirement aren't redundant, as the requirement is still
+/// needed.
+fn redundant_additions(
+    listed: &TopCrates,
+    modifications: &Modifications,
+) -> BTreeSet<InternedString> {
+    modifications
+        .additions
+        .iter()
+        .filter(|(_, requirement)| requirement.is_none())
+        .map(|(&name, _)| name)
+        .filter(|name| listed.crates.iter().any(|krate| krate.name == *name))
+        .collect()
+}
+
+/// `content`, a crate modifications file, without the entries of its
+/// top-level `additions` list that are one of `names`. Everything
+/// else, comments and the additions of each channel included, is left
+/// as it was.
+pub fn without_additions(
+    content: &str,
+    names: &BTreeSet<String>,
+) -> Result<String, TopCratesError> {
+    let mut document =
+        content
+            .parse::<toml_edit::Document>()
+            .map_err(|e| TopCratesError::TomlParse {
+                context: "Unable to parse the crate modifications".to_owned(),
+                source: serde::de::Error::custom(e),
+            })?;
+    let additions = match document.get_mut("additions").and_then(|a| a.as_array_mut()) {
+        Some(additions) => additions,
+        None => return Ok(content.to_owned()),
+    };
+    for i in (0..additions.len()).rev() {
+        if !additions
+            .get(i)
+            .and_then(|a| a.as_str())
+            .is_some_and(|a| names.contains(a))
+        {
+            continue;
+        }
+        let removed = additions.remove(i);
+        // What follows the entry on its line, such as a comment, is
+        // kept in front of the next entry, so that goes too.
+        if let Some(next) = additions.get_mut(i) {
+            let prefix = removed.decor().prefix().unwrap_or_default().to_owned();
+            next.decor_mut().set_prefix(prefix);
+        }
+    }
+    Ok(document.to_string())
+}
+
+fn populate_initial_direct_dependencies(
+    global: &mut GlobalState<'_>,
+) -> Result<BTreeMap<PackageId, ResolvedDep>, TopCratesError> {
+    let modifications = global.modifications;
+    // Only the curated additions are used with `include_only`.
+    let mut top = if global.options.include_only {
+        if modifications.additions.is_empty() {
+            global
+                .diagnostics
+                .warn("Only additions are included, but there are none");
+        }
+        TopCrates { crates: Vec::new() }
+    } else {
+        let top = listed_crates(global)?;
+        global.redundant_additions = redundant_additions(&top, modifications);
+        for &name in &global.redundant_additions {
+            global.diagnostics.note_for(
+                name,
+                "Already listed, so it no longer needs to be an addition",
+            );
+        }
+        top
+    };
+    top.add_curated_crates(modifications);
+    if !global.options.only.is_empty() {
+        top.restrict_to(&global.options.only);
+    }
+
+    if !modifications.blacklist_owners.is_empty() && global.options.offline {
+        unavailable_offline(
+            global,
+            "The owners of crates",
+            "Not checking the owners of crates as they can't be looked up offline",
+        )?;
+    }
+
+    let mut names = Vec::new();
+    let mut requirements = BTreeMap::new();
+    global.listed = top.crates.len();
+    for krate in top.crates {
+        let name = krate.name;
+        if global.modifications.excluded(&name) {
+            continue;
+        }
+        if global.modifications.too_few_downloads(&krate) {
+            global.diagnostics.note_for(
+                name,
+                format!(
+                    "Left out after only {} downloads",
+                    krate.downloads.unwrap_or_default()
+                ),
+            );
+            continue;
+        }
+        if let Some(owner) = blacklisted_owner(global, name)? {
+            global
+                .diagnostics
+                .note_for(name, format!("Left out because {} owns it", owner));
+            continue;
+        }
+        if let Some(requirement) = &krate.requirement {
+            requirements.insert(name, requirement.clone());
+        }
+        global.listings.entry(name).or_insert(krate);
+        names.push(name);
+    }
+
+    let queried = query_candidates(global, names)?;
+
+    // Report every misspelled addition at once, rather than only the
+    // first one to be looked at.
+    let unknown = unknown_additions(global.modifications, &queried);
+    if !unknown.is_empty() {
+        return Err(TopCratesError::UnknownAdditions { names: unknown });
+    }
+
+    let choices = choose_candidates(
+        queried,
+        global.modifications,
+        &requirements,
+        global.options.allow_prerelease,
+        global.diagnostics,
+    );
+
+    let packages = download_choices(global, &choices)?;
+
+    let mut initial_direct_dependencies = BTreeMap::new();
+    for download in packages {
+        let id = download.package_id();
+        let lib_target = match top_level_library(&download, global.diagnostics) {
+            Some(lib_target) => lib_target,
+            None => continue,
+        };
+        if global
+            .modifications
+            .excludes_proc_macro(download.name(), &lib_target)
+        {
+            global.diagnostics.note_for(
+                id,
+                "Left out because its only library is a procedural macro",
+            );
+            continue;
+        }
+        let mut dep = ResolvedDep {
+            summary: download.summary().clone(),
+            lib_target,
+            features: BTreeSet::new(),
+            uses_default_features: true,
+            license: download.manifest().metadata().license.clone(),
+            description: download.manifest().metadata().description.clone(),
+            repository: repository_url(download.manifest().metadata().repository.as_deref()),
+            build_only: false,
+            rust_version: download.rust_version().map(str::to_owned),
+            metadata_features: BTreeSet::new(),
+            all_features: false,
+        };
+        let (features, all_features) = metadata_features(global, &download);
+        dep.all_features = all_features;
+        if let Some((features, default_features)) = features {
+            dep.metadata_features = features.clone();
+            dep.features = features;
+            dep.uses_default_features = default_features;
+        }
+        initial_direct_dependencies.insert(id, dep);
+    }
+
+    Ok(initial_direct_dependencies)
+}
+
+/// The first of the owners of `name` whose crates are blacklisted, if
+/// any of them is. Owners can't be looked up offline, so no crate is
+/// left out then, which only happens with `allow_empty`.
+fn blacklisted_owner(
+    global: &mut GlobalState<'_>,
+    name: InternedString,
+) -> Result<Option<String>, TopCratesError> {
+    let modifications = global.modifications;
+    let blacklist = &modifications.blacklist_owners;
+    if blacklist.is_empty() || global.options.offline {
+        return Ok(None);
+    }
+    let owners = crate_owners(global, name)?;
+    Ok(owners
+        .iter()
+        .find(|&owner| blacklist.contains(owner))
+        .cloned())
+}
+
+/// Asks the registry's web API for the logins of the owners of `name`,
+/// remembering them for the rest of the run.
+fn crate_owners(
+    global: &mut GlobalState<'_>,
+    name: InternedString,
+) -> Result<Vec<String>, TopCratesError> {
+    #[derive(Deserialize)]
+    struct Owners {
+        users: Vec<Owner>,
+    }
+
+    #[derive(Deserialize)]
+    struct Owner {
+        login: String,
+    }
+
+    if !global.owners.contains_key(&name) {
+        let url = format!(
+            "{}/api/v1/crates/{}/owners",
+            global.api.trim_end_matches('/'),
+            name
+        );
+        let what = format!("the owners of {}", name);
+        let resp = global.http.fetch(&url, &what, global.diagnostics)?;
+        let owners: Owners = serde_json::from_reader(resp)
+            .map_err(TopCratesError::json(format!("Invalid JSON in {}", what)))?;
+        let logins = owners.users.into_iter().map(|owner| owner.login).collect();
+        global.owners.insert(name, logins);
+    }
+    Ok(global.owners[&name].clone())
+}
+
+/// Find the newest (non-prerelease, non-yanked) versions of all the
+/// interesting crates. A pin takes precedence over the version an
+/// addition asks for.
+///
+/// A crate without any viable version is left out with a warning, so
+/// that one bad addition or pin doesn't stop everything else from
+/// being generated.
+fn choose_candidates(
+    queried: BTreeMap<InternedString, Vec<Candidate>>,
+    modifications: &Modifications,
+    requirements: &BTreeMap<InternedString, VersionReq>,
+    allow_all_prereleases: bool,
+    diagnostics: &mut Diagnostics,
+) -> BTreeMap<InternedString, Vec<Candidate>> {
+    let mut choices = BTreeMap::new();
+    for (name, candidates) in queried {
+        let pin = modifications
+            .pinned
+            .get(&name)
+            .or_else(|| requirements.get(&name));
+        let allow_prerelease =
+            allow_all_prereleases || modifications.allow_prerelease.contains(&name);
+        let mut candidates = viable_candidates(candidates, pin, allow_prerelease);
+        if let Some(blocked) = modifications.blocked_versions.get(&name) {
+            let unblocked = candidates
+                .iter()
+                .filter(|c| !blocked.iter().any(|req| req.matches(&c.version)))
+                .cloned()
+                .collect_vec();
+            match (unblocked.first(), candidates.first()) {
+                (None, Some(newest)) => diagnostics.warn_for(
+                    name,
+                    format!(
+                        "Every viable version is blocked, so {} is used anyway",
+                        newest.version
+                    ),
+                ),
+                (Some(chosen), Some(newest)) if chosen.version != newest.version => {
+                    // Candidates are newest first, so everything before
+                    // the chosen one was blocked.
+                    let skipped = candidates
+                        .iter()
+                        .map(|c| &c.version)
+                        .filter(|&version| *version > chosen.version)
+                        .dedup()
+                        .join(", ");
+                    let plural = if skipped.contains(',') { "s" } else { "" };
+                    diagnostics.note_for(
+                        name,
+                        format!("Skipping blocked version{} {}", plural, skipped),
+                    );
+                    candidates = unblocked;
+                }
+                _ => candidates = unblocked,
+            }
+        }
+        if candidates.is_empty() {
+            let message = match pin {
+                Some(pin) => format!(
+                    "Left out because no viable version satisfies its pin `{}`",
+                    pin
+                ),
+                None => "Left out because the registry has no viable versions \
+                         (prereleases are only used for crates listed in `allow-prerelease`)"
+                    .to_owned(),
+            };
+            diagnostics.warn_for(name, message);
+            continue;
+        }
+        let why = match pin {
+            Some(pin) => format!("the newest satisfying `{}`", pin),
+            None if allow_prerelease => "the newest, prereleases included".to_owned(),
+            None => "the newest non-prerelease".to_owned(),
+        };
+        debug!(
+            "Choosing {} {}, {} of {} viable versions",
+            name,
+            candidates[0].version,
+            why,
+            candidates.len()
+        );
+        choices.insert(name, candidates);
+    }
+    choices
+}
+
+/// The repository a manifest names, unless it is blank.
+fn repository_url(repository: Option<&str>) -> Option<String> {
+    let repository = repository?.trim();
+    (!repository.is_empty()).then(|| repository.to_owned())
+}
+
+/// The library of a top-level crate. Some popular crates are only
+/// binaries, which the playground can't use, so they are left out.
+fn top_level_library(pkg: &Package, diagnostics: &mut Diagnostics) -> Option<Target> {
+    let library = library_target(pkg.targets()).cloned();
+    if library.is_none() {
+        diagnostics.warn_for(
+            pkg.package_id(),
+            "Left out because it has no library for the playground to use",
+        );
+    }
+    library
+}
+
+/// The library target whose name users `use`, preferring a plain
+/// `lib` or `rlib` over other kinds of library, and those over a
+/// procedural macro.
+fn library_target(targets: &[Target]) -> Option<&Target> {
+    let rank = |crate_types: &[CrateType]| {
+        if crate_types
+            .iter()
+            .any(|t| matches!(t, CrateType::Lib | CrateType::Rlib))
+        {
+            0
+        } else if crate_types.iter().any(|t| *t != CrateType::ProcMacro) {
+            1
+        } else {
+            2
+        }
+    };
+    targets
+        .iter()
+        .filter_map(|target| match target.kind() {
+            TargetKind::Lib(crate_types) => Some((rank(crate_types), target)),
+            _ => None,
+        })
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, target)| target)
+}
+
+/// The curated additions that the registry has no versions of at all.
+///
+/// Excluded additions were never queried, so they aren't reported.
+fn unknown_additions(
+    modifications: &Modifications,
+    queried: &BTreeMap<InternedString, Vec<Candidate>>,
+) -> Vec<String> {
+    modifications
+        .additions
+        .keys()
+        .filter(|name| queried.get(*name).is_some_and(Vec::is_empty))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Downloads the newest of each crate's candidates (which are newest
+/// first) that is known to build with the playground's Rust version
+/// and edition.
+///
+/// Older candidates are only downloaded when the newer ones turn out
+/// to need a newer Rust or edition. If none of them can be built, the
+/// newest one is used anyway.
+fn download_choices(
+    global: &mut GlobalState<'_>,
+    choices: &BTreeMap<InternedString, Vec<Candidate>>,
+) -> Result<Vec<Package>, TopCratesError> {
+    let registry_id = global.registry_id;
+    let package_id =
+        |name, candidate: &Candidate| PackageId::pure(name, candidate.version.clone(), registry_id);
+
+    let newest_ids = choices
+        .iter()
+        .map(|(&name, candidates)| package_id(name, &candidates[0]))
+        .collect_vec();
+    let newest = bulk_download(global, &newest_ids)?;
+
+    let msrv = global.options.msrv.clone();
+    let max_edition = global.options.max_edition;
+    let toolchain = match (&msrv, max_edition) {
+        (None, None) => return Ok(newest),
+        (Some(msrv), None) => format!("Rust {}", msrv),
+        (None, Some(edition)) => format!("edition {}", edition),
+        (Some(msrv), Some(edition)) => format!("Rust {} and edition {}", msrv, edition),
+    };
+    let too_new_for_toolchain = |pkg: &Package| {
+        let rust = msrv
+            .as_ref()
+            .is_some_and(|msrv| requires_newer_rust(pkg.rust_version(), msrv));
+        let edition = max_edition.is_some_and(|max| pkg.manifest().edition() > max);
+        rust || edition
+    };
+
+    let mut chosen = BTreeMap::new();
+    let mut too_new = BTreeMap::new();
+    for pkg in newest {
+        if too_new_for_toolchain(&pkg) {
+            too_new.insert(pkg.name(), (0, pkg));
+        } else {
+            chosen.insert(pkg.name(), pkg);
+        }
+    }
+
+    // Step each crate that still needs a newer toolchain back by one
+    // version at a time, downloading all of them together.
+    let mut attempts = too_new.keys().copied().collect_vec();
+    while !attempts.is_empty() {
+        let mut ids = Vec::new();
+        for name in attempts.drain(..) {
+            let (index, _) = too_new.get_mut(&name).expect("Crate was not too new");
+            *index += 1;
+            if let Some(candidate) = choices[&name].get(*index) {
+                ids.push(package_id(name, candidate));
+            }
+        }
+
+        for pkg in bulk_download(global, &ids)? {
+            let name = pkg.name();
+            if too_new_for_toolchain(&pkg) {
+                attempts.push(name);
+            } else {
+                debug!(
+                    "Choosing {} {} instead, as newer versions can't be built with {}",
+                    name,
+                    pkg.version(),
+                    toolchain
+                );
+                too_new.remove(&name);
+                chosen.insert(name, pkg);
+            }
+        }
+    }
+
+    for (name, (_, newest)) in too_new {
+        global.diagnostics.warn_for(
+            newest.package_id(),
+            format!(
+                "No version of {} can be built with {}; using the newest anyway",
+                name, toolchain
+            ),
+        );
+        chosen.insert(name, newest);
+    }
+
+    Ok(chosen.into_values().collect())
+}
+
+/// Walks the resolve from `roots`, returning every package that is
+/// used on the playground's platform.
+///
+/// When `normal_only` is set, build dependencies are not followed.
+fn reachable_packages(
+    resolve: &Resolve,
+    roots: BTreeSet<PackageId>,
+    target_info: &TargetInfo,
+    normal_only: bool,
+) -> BTreeSet<PackageId> {
+    let mut reachable = roots.clone();
+    let mut to_visit = roots;
+    while !to_visit.is_empty() {
+        let mut visit_next = BTreeSet::new();
+
+        for package_id in to_visit {
+            for (dep_pkg, deps) in resolve.deps(package_id) {
+                let followed = deps.iter().any(|dep| {
+                    let for_this_platform = dep.platform().is_none_or(|platform| {
+                        platform.matches(PLAYGROUND_TARGET_PLATFORM, target_info.cfg())
+                    });
+                    let kind_followed = !normal_only || dep.kind() == DepKind::Normal;
+                    for_this_platform && kind_followed
+                });
+
+                if followed && reachable.insert(dep_pkg) {
+                    visit_next.insert(dep_pkg);
+                }
+            }
+        }
+
+        to_visit = visit_next;
+    }
+    reachable
+}
+
+/// The top-level crates to drop so that, going by `resolve`, no more
+/// than `max_crates` of `crates` are left: the least downloaded ones
+/// first, but never the curated additions.
+fn crates_over_cap(
+    resolve: &Resolve,
+    top_level: &[PackageId],
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    max_crates: usize,
+    modifications: &Modifications,
+    listings: &BTreeMap<InternedString, Crate>,
+    target_info: &TargetInfo,
+) -> Vec<PackageId> {
+    let closures = top_level
+        .iter()
+        .map(|&id| {
+            let mut closure = reachable_packages(resolve, BTreeSet::from([id]), target_info, false);
+            closure.retain(|id| crates.contains_key(id));
+            (id, closure)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let remaining = |kept: &BTreeSet<PackageId>| {
+        kept.iter()
+            .flat_map(|id| &closures[id])
+            .collect::<BTreeSet<_>>()
+            .len()
+    };
+
+    let mut kept = top_level.iter().copied().collect::<BTreeSet<_>>();
+    let mut dropped = Vec::new();
+    let candidates = top_level
+        .iter()
+        .copied()
+        .filter(|id| !modifications.additions.contains_key(&id.name()))
+        .sorted_by_key(|id| {
+            let downloads = listings.get(&id.name()).and_then(|krate| krate.downloads);
+            (downloads.unwrap_or_default(), *id)
+        });
+    for id in candidates {
+        if remaining(&kept) <= max_crates {
+            break;
+        }
+        kept.remove(&id);
+        dropped.push(id);
+    }
+    dropped
+}
+
+/// The excluded crates that some of the included `crates` depend on
+/// unconditionally on the playground's platform, along with the crates
+/// that do. Excluding them leaves out their name, but they are built
+/// all the same.
+fn required_exclusions(
+    resolve: &Resolve,
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    modifications: &Modifications,
+    target_info: &TargetInfo,
+) -> BTreeMap<PackageId, BTreeSet<PackageId>> {
+    let mut required = BTreeMap::<_, BTreeSet<_>>::new();
+    for &parent in crates.keys() {
+        for (child, deps) in resolve.deps(parent) {
+            if !modifications.excluded(child.name().as_str()) {
+                continue;
+            }
+            let unconditional = deps.iter().any(|dep| {
+                let for_this_platform = dep.platform().is_none_or(|platform| {
+                    platform.matches(PLAYGROUND_TARGET_PLATFORM, target_info.cfg())
+                });
+                for_this_platform && !dep.is_optional() && dep.kind() != DepKind::Development
+            });
+            if unconditional {
+                required.entry(child).or_default().insert(parent);
+            }
+        }
+    }
+    required
+}
+
+/// The resolved crates that don't meet their pin, along with the
+/// crates that depend on them. A pinned crate is resolved at the
+/// pinned version as a root, so another version is only there because
+/// a dependent needs a version the pin doesn't allow.
+fn unhonored_pins(
+    resolve: &Resolve,
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    modifications: &Modifications,
+) -> BTreeMap<PackageId, BTreeSet<PackageId>> {
+    let mut unhonored = BTreeMap::<_, BTreeSet<_>>::new();
+    for &parent in crates.keys() {
+        for (child, _) in resolve.deps(parent) {
+            let honored = modifications
+                .pin(child.name())
+                .is_none_or(|pin| pin.matches(child.version()));
+            if !honored {
+                unhonored.entry(child).or_default().insert(parent);
+            }
+        }
+    }
+    unhonored
+}
+
+/// The top-level crates that can't be built on the playground without
+/// some of the packages that `failed` to download, along with those
+/// packages.
+fn needs_failed_downloads(
+    resolve: &Resolve,
+    top_level: &[PackageId],
+    failed: &BTreeSet<PackageId>,
+    target_info: &TargetInfo,
+) -> BTreeMap<PackageId, BTreeSet<PackageId>> {
+    if failed.is_empty() {
+        return BTreeMap::new();
+    }
+    top_level
+        .iter()
+        .filter_map(|&id| {
+            let reachable = reachable_packages(resolve, BTreeSet::from([id]), target_info, false);
+            let needed = reachable
+                .intersection(failed)
+                .copied()
+                .collect::<BTreeSet<_>>();
+            (!needed.is_empty()).then_some((id, needed))
+        })
+        .collect()
+}
+
+/// What to resolve `dep` with when it is one of the roots.
+fn resolve_root(modifications: &Modifications, dep: ResolvedDep) -> (Summary, ResolveOpts) {
+    let dev_deps = modifications.with_dev_deps.contains(&dep.summary.name());
+    (
+        dep.summary,
+        ResolveOpts {
+            dev_deps,
+            features: RequestedFeatures::DepFeatures {
+                features: Rc::new(dep.features),
+                uses_default_features: dep.uses_default_features,
+            },
+        },
+    )
+}
+
+/// Resolves the transitive dependencies of `summaries`.
+fn resolve_roots(
+    global: &mut GlobalState<'_>,
+    summaries: &[(Summary, ResolveOpts)],
+) -> Result<Resolve, TopCratesError> {
+    info!("Resolving {} crates", summaries.len());
+    let resolving = Instant::now();
+    let replacements = [];
+    let mut version_prefs = VersionPreferences::default();
+    for &id in &global.locked {
+        version_prefs.prefer_package_id(id);
+    }
+    let warnings = None;
+    let check_public_visible_dependencies = true;
+    // Which versions are chosen doesn't depend on the feature resolver,
+    // and the features of the resolve are unified the way version 1
+    // does; see `FeatureResolver`.
+    let resolve = resolver::resolve(
+        summaries,
+        &replacements,
+        &mut global.registry,
+        &version_prefs,
+        warnings,
+        check_public_visible_dependencies,
+    )
+    .map_err(|source| TopCratesError::Resolution { source })?;
+    global.timings.since("resolve", resolving);
+    Ok(resolve)
+}
+
+/// The features cargo's version 2 feature resolver enables on each of
+/// `dependencies`, keyed by the name they are exposed as, when the
+/// playground depends on them the way its manifest does. `ids` finds
+/// the package of each by name and version.
+///
+/// Unlike version 1, version 2 keeps the features that
+/// build-dependencies and proc macros ask for apart from those of
+/// normal dependencies, so these are the features that code in the
+/// playground can use.
+fn v2_features(
+    global: &mut GlobalState<'_>,
+    dependencies: &BTreeMap<String, DependencySpec>,
+    ids: &BTreeMap<(&str, Version), PackageId>,
+) -> Result<BTreeMap<String, BTreeSet<InternedString>>, TopCratesError> {
+    info!("Resolving features the way version 2 does");
+    let resolving = Instant::now();
+    let config = global.config;
+    let context = "Unable to resolve features the way version 2 does";
+    let registry = || TopCratesError::registry(context);
+
+    // Cargo reads the playground from disk to find out whether it is a
+    // proc macro, so a stand-in for it is written out.
+    let dir = tempfile::tempdir().map_err(TopCratesError::io(context))?;
+    let manifest_path = dir.path().join("Cargo.toml");
+    fs::create_dir(dir.path().join("src"))
+        .and_then(|_| fs::write(dir.path().join("src/lib.rs"), ""))
+        .and_then(|_| {
+            let manifest =
+                "[package]\nname = \"playground\"\nversion = \"0.0.1\"\nresolver = \"2\"\n";
+            fs::write(&manifest_path, manifest)
+        })
+        .map_err(TopCratesError::io(context))?;
+    let path_id = SourceId::for_path(dir.path()).map_err(registry())?;
+    let mut manifest = match read_manifest(&manifest_path, path_id, config) {
+        Ok((EitherManifest::Real(manifest), _)) => manifest,
+        Ok((EitherManifest::Virtual(_), _)) => unreachable!("The stand-in is a package"),
+        Err(e) => return Err(registry()(e.into())),
+    };
+
+    // Its dependencies are given here rather than in its manifest, so
+    // that each comes from the source it was resolved from.
+    let mut deps = Vec::new();
+    for (exposed_name, spec) in dependencies {
+        let id = match ids.get(&(spec.package.as_str(), spec.version.clone())) {
+            Some(&id) => id,
+            None => continue,
+        };
+        let version = format!("={}", id.version());
+        for kind in [DepKind::Normal, DepKind::Build].iter().copied() {
+            let mut dep =
+                Dependency::parse(id.name(), Some(&version), id.source_id()).map_err(registry())?;
+            dep.set_kind(kind)
+                .set_explicit_name_in_toml(exposed_name.as_str())
+                .set_features(spec.features.iter().copied())
+                .set_default_features(spec.default_features);
+            deps.push(dep);
+        }
+    }
+    let summary = Summary::new(
+        config,
+        manifest.summary().package_id(),
+        deps,
+        &BTreeMap::new(),
+        None::<InternedString>,
+    )
+    .map_err(registry())?;
+    *manifest.summary_mut() = summary.clone();
+    let package = Package::new(manifest, &manifest_path);
+    let member = package.package_id();
+    let ws = Workspace::ephemeral(package, config, None, false).map_err(registry())?;
+
+    // Every dependency is exact, so this finds the same versions again.
+    let opts = ResolveOpts {
+        dev_deps: false,
+        features: RequestedFeatures::CliFeatures(CliFeatures::new_all(false)),
+    };
+    let mut version_prefs = VersionPreferences::default();
+    for &id in ids.values() {
+        version_prefs.prefer_package_id(id);
+    }
+    // Like cargo, several versions of a crate may be depended on at
+    // once, which checking public dependencies would reject.
+    let check_public_visible_dependencies = false;
+    let resolve = resolver::resolve(
+        &[(summary, opts)],
+        &[],
+        &mut global.registry,
+        &version_prefs,
+        None,
+        check_public_visible_dependencies,
+    )
+    .map_err(|source| TopCratesError::Resolution { source })?;
+
+    // Cargo expects the packages it looks at to have been downloaded
+    // already, which those for other platforms never are.
+    let reachable = reachable_packages(
+        &resolve,
+        BTreeSet::from([member]),
+        &global.target_info,
+        false,
+    )
+    .into_iter()
+    .collect_vec();
+    let sources = package_sources(&mut global.source, config, global.registry_id, &reachable)?;
+    let package_set = PackageSet::new(&reachable, sources, config).map_err(registry())?;
+    package_set
+        .get_many(reachable.iter().copied())
+        .map_err(registry())?;
+
+    let compile_target = CompileTarget::new(PLAYGROUND_TARGET_PLATFORM).map_err(registry())?;
+    let compile_kind = CompileKind::Target(compile_target);
+    let target_data = RustcTargetData::new(&ws, &[compile_kind]).map_err(registry())?;
+    let resolved = features::FeatureResolver::resolve(
+        &ws,
+        &target_data,
+        &resolve,
+        &package_set,
+        &CliFeatures::new_all(false),
+        &[PackageIdSpec::from_package_id(member)],
+        &[compile_kind],
+        FeatureOpts::new_behavior(ResolveBehavior::V2, HasDevUnits::No),
+    )
+    .map_err(registry())?;
+
+    let mut activated = BTreeMap::new();
+    for (exposed_name, spec) in dependencies {
+        let id = match ids.get(&(spec.package.as_str(), spec.version.clone())) {
+            Some(&id) => id,
+            None => continue,
+        };
+        // Proc macros only have features for the host.
+        let features = resolved
+            .activated_features_unverified(id, FeaturesFor::NormalOrDev)
+            .or_else(|| resolved.activated_features_unverified(id, FeaturesFor::HostDep))
+            .unwrap_or_default();
+        activated.insert(exposed_name.clone(), features.into_iter().collect());
+    }
+    global.timings.since("resolve features", resolving);
+    Ok(activated)
+}
+
+fn extend_direct_dependencies(
+    global: &mut GlobalState<'_>,
+    crates: &mut BTreeMap<PackageId, ResolvedDep>,
+) -> Result<Resolve, TopCratesError> {
+    // Add a direct dependency on each starting crate, in the order of
+    // their ids so that the resolve never depends on the order they
+    // were found in.
+    let mut summaries = Vec::new();
+    let mut roots = BTreeSet::new();
+    let mut normal_roots = BTreeSet::new();
+    for dep in mem::take(crates).into_values() {
+        roots.insert(dep.summary.package_id());
+        if !dep.build_only {
+            normal_roots.insert(dep.summary.package_id());
+        }
+        summaries.push(resolve_root(global.modifications, dep));
+    }
+
+    let resolve = resolve_roots(global, &summaries)?;
+
+    // Find transitive deps compatible with the playground's platform,
+    // noting which are only needed to build other crates.
+    let valid_for_our_platform = reachable_packages(&resolve, roots, &global.target_info, false);
+    let normal_dependencies = reachable_packages(&resolve, normal_roots, &global.target_info, true);
+
+    // Remove invalid and excluded packages that have been added due to resolution
+    let package_ids = resolve
+        .iter()
+        .filter(|pkg| valid_for_our_platform.contains(pkg))
+        .filter(|pkg| !global.modifications.excluded(pkg.name().as_str()))
+        .collect_vec();
+
+    let packages = bulk_download(global, &package_ids)?;
+
+    for download in packages {
+        let id = download.package_id();
+        let lib_target = library_target(download.targets())
+            .ok_or_else(|| TopCratesError::NoLibrary { id: id.to_string() })?
+            .clone();
+        let mut dep = ResolvedDep {
+            summary: download.summary().clone(),
+            lib_target,
+            features: resolve.features(id).iter().copied().collect(),
+            // If enabled, all default features are already included in
+            // `features` by the resolver.
+            uses_default_features: false,
+            license: download.manifest().metadata().license.clone(),
+            description: download.manifest().metadata().description.clone(),
+            repository: repository_url(download.manifest().metadata().repository.as_deref()),
+            build_only: !normal_dependencies.contains(&id),
+            rust_version: download.rust_version().map(str::to_owned),
+            metadata_features: BTreeSet::new(),
+            all_features: false,
+        };
+        let (features, all_features) = metadata_features(global, &download);
+        dep.all_features = all_features;
+        if let Some((features, _default_features)) = features {
+            dep.features.extend(&features);
+            dep.metadata_features = features;
+        }
+        crates.insert(id, dep);
+    }
+
+    Ok(resolve)
+}
+
+/// Everything computed about the crates to include in the playground
+#[derive(Debug)]
+pub struct GeneratedInfo {
+    pub dependencies: BTreeMap<String, DependencySpec>,
+    pub infos: Vec<CrateInformation>,
+    pub resolved_crates: Vec<ResolvedCrate>,
+    /// The final resolve, serialized the same way cargo writes
+    /// `Cargo.lock`.
+    pub cargo_resolve: String,
+    pub trim_suggestion: Option<TrimSuggestion>,
+    pub feature_provenance: Option<Vec<FeatureProvenance>>,
+    pub feature_unification: Option<Vec<FeatureUnification>>,
+    pub resolution_graph: Option<Vec<ResolvedPackage>>,
+    pub explanation: Option<Explanation>,
+    pub summary: ResolutionSummary,
+    pub download_size: DownloadSize,
+    pub feature_counts: FeatureCounts,
+    /// Crates whose playground metadata was ignored because it could
+    /// not be parsed.
+    pub metadata_errors: Vec<MetadataError>,
+    /// Curated additions without a version requirement that were
+    /// listed anyway.
+    pub redundant_additions: Vec<String>,
+    pub timings: Timings,
+}
+
+/// Setup to interact with cargo.
+fn cargo_config(options: &GenerateOptions) -> Result<CargoConfig, TopCratesError> {
+    let shared = cargo_config_in(options, None)?;
+    let index_home = match &options.index_rev {
+        Some(rev) => {
+            let source_id = registry_source_id(&shared, options)?;
+            let _lock = shared
+                .acquire_package_cache_lock()
+                .map_err(TopCratesError::registry("Unable to lock the package cache"))?;
+            Some(index_home_at(&shared, source_id, rev, options.offline)?)
+        }
+        None => None,
+    };
+    let index_rev = index_home
+        .map(|home| Ok((cargo_config_in(options, Some(home.path()))?, home)))
+        .transpose()?;
+    Ok(CargoConfig { shared, index_rev })
+}
+
+fn cargo_config_in(
+    options: &GenerateOptions,
+    home: Option<&Path>,
+) -> Result<Config, TopCratesError> {
+    let mut config = match home {
+        Some(home) => {
+            let cwd = env::current_dir()
+                .map_err(TopCratesError::io("Unable to find the current directory"))?;
+            Config::new(Shell::new(), cwd, home.to_owned())
+        }
+        None => Config::default().map_err(TopCratesError::registry(
+            "Unable to create default Cargo config",
+        ))?,
+    };
+    if options.offline {
+        let target_dir = None;
+        config
+            .configure(0, false, None, false, false, true, &target_dir, &[], &[])
+            .map_err(TopCratesError::registry(
+                "Unable to configure cargo to work offline",
+            ))?;
+    }
+    Ok(config)
+}
+
+/// Cargo's configuration, which has a cargo home of its own with
+/// `GenerateOptions::index_rev`.
+struct CargoConfig {
+    shared: Config,
+    /// Dropped before the cargo home it refers to.
+    index_rev: Option<(Config, tempfile::TempDir)>,
+}
+
+impl CargoConfig {
+    fn get(&self) -> &Config {
+        match &self.index_rev {
+            Some((config, _)) => config,
+            None => &self.shared,
+        }
+    }
+
+    /// Locks the shared package cache too, as the private cargo home
+    /// downloads crates into it.
+    fn lock(&self) -> Result<Vec<PackageCacheLock<'_>>, TopCratesError> {
+        let mut configs = vec![&self.shared];
+        configs.extend(self.index_rev.as_ref().map(|(config, _)| config));
+        configs
+            .into_iter()
+            .map(|config| {
+                config
+                    .acquire_package_cache_lock()
+                    .map_err(TopCratesError::registry("Unable to lock the package cache"))
+            })
+            .collect()
+    }
+}
+
+pub fn generate_info(
+    modifications: &Modifications,
+    options: &GenerateOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<GeneratedInfo, TopCratesError> {
+    let config = cargo_config(options)?;
+    let _lock = config.lock()?;
+    let mut global = make_global_state(config.get(), modifications, options, diagnostics)?;
+
+    let initial_crates = populate_initial_direct_dependencies(&mut global)?;
+    let mut top_level = initial_crates.keys().copied().collect_vec();
+
+    // With `max-crates`, the least downloaded top-level crates are
+    // dropped and everything resolved again until few enough are left.
+    let (resolve, resolved_crates) = loop {
+        let mut resolved_crates = initial_crates
+            .iter()
+            .filter(|(id, _)| top_level.contains(id))
+            .map(|(&id, dep)| (id, dep.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let resolve = loop {
+            let num_crates_before = resolved_crates.len();
+            let resolve = extend_direct_dependencies(&mut global, &mut resolved_crates)?;
+            if num_crates_before == resolved_crates.len() {
+                break resolve;
+            }
+        };
+
+        let undownloadable = needs_failed_downloads(
+            &resolve,
+            &top_level,
+            &global.failed_downloads,
+            &global.target_info,
+        );
+        if !undownloadable.is_empty() {
+            for (&id, failed) in &undownloadable {
+                global.diagnostics.warn_for(
+                    id,
+                    format!(
+                        "Left out as {} could not be downloaded",
+                        failed.iter().join(", ")
+                    ),
+                );
+            }
+            top_level.retain(|id| !undownloadable.contains_key(id));
+            continue;
+        }
+
+        let max_crates = match modifications.max_crates {
+            Some(max_crates) if resolved_crates.len() > max_crates => max_crates,
+            _ => break (resolve, resolved_crates),
+        };
+        let dropped = crates_over_cap(
+            &resolve,
+            &top_level,
+            &resolved_crates,
+            max_crates,
+            modifications,
+            &global.listings,
+            &global.target_info,
+        );
+        if dropped.is_empty() {
+            global.diagnostics.warn(format!(
+                "{} crates are resolved, more than the {} of max-crates, \
+                 but only curated additions are left to drop",
+                resolved_crates.len(),
+                max_crates
+            ));
+            break (resolve, resolved_crates);
+        }
+        for &id in &dropped {
+            global.diagnostics.note_for(
+                id,
+                format!("Left out to keep to at most {} crates", max_crates),
+            );
+        }
+        top_level.retain(|id| !dropped.contains(id));
+    };
+
+    for (excluded, dependents) in required_exclusions(
+        &resolve,
+        &resolved_crates,
+        modifications,
+        &global.target_info,
+    ) {
+        global.diagnostics.warn_for(
+            excluded,
+            format!(
+                "Excluded, but {} cannot be built without it, so it is still built \
+                 as their dependency; the exclusion only keeps it from being exposed",
+                dependents.iter().map(|id| id.to_string()).join(", ")
+            ),
+        );
+    }
+
+    for (unpinned, dependents) in unhonored_pins(&resolve, &resolved_crates, modifications) {
+        let pin = modifications
+            .pin(unpinned.name())
+            .expect("Only pinned crates are unhonored");
+        global.diagnostics.warn_for(
+            unpinned,
+            format!(
+                "Pinned to `{}`, but {} also needs {}, so that version is built too",
+                pin,
+                dependents.iter().map(|id| id.to_string()).join(", "),
+                unpinned.version()
+            ),
+        );
+    }
+
+    let top_level_names = top_level
+        .iter()
+        .map(|id| id.name())
+        .collect::<BTreeSet<_>>();
+    let changed = changed_versions(&global.locked, resolved_crates.keys().copied());
+    for (name, (was, now)) in changed {
+        if top_level_names.contains(&name) {
+            continue;
+        }
+        global.diagnostics.warn_for(
+            name,
+            format!(
+                "Locked at {}, but had to change to {}",
+                was.iter().join(", "),
+                now.iter().join(", ")
+            ),
+        );
+    }
+
+    for names in similar_names(top_level.iter().map(|id| id.name())) {
+        global.diagnostics.warn(format!(
+            "The top-level crates {} have names that only differ by `-`, `_`, or case; \
+             consider excluding all but one of them in crate-modifications.toml",
+            names.join(", ")
+        ));
+    }
+
+    let resolved_names = resolved_crates
+        .keys()
+        .map(|id| id.name())
+        .collect::<BTreeSet<_>>();
+    for &name in modifications.profile_overrides.keys() {
+        if !resolved_names.contains(&name) {
+            global.diagnostics.warn_for(
+                name,
+                "Has profile overrides, but is not among the resolved crates",
+            );
+        }
+    }
+
+    if let Some(allowlist) = &modifications.license_allowlist {
+        check_licenses(&resolved_crates, allowlist, global.diagnostics);
+    }
+
+    if let Some(advisory_db) = &modifications.advisory_db {
+        check_advisories(
+            &resolved_crates,
+            advisory_db,
+            options.deny_advisories,
+            global.diagnostics,
+        )?;
+    }
+
+    let trim_suggestion = options.trim_to.map(|target| {
+        let closures = top_level
+            .iter()
+            .map(|&id| {
+                let roots = BTreeSet::from([id]);
+                let mut closure = reachable_packages(&resolve, roots, &global.target_info, false);
+                closure.retain(|id| resolved_crates.contains_key(id));
+                (id.name().to_string(), closure)
+            })
+            .collect();
+        trim::suggest(&closures, target)
+    });
+
+    let feature_provenance = options
+        .feature_provenance
+        .then(|| explain_features(&resolve, &resolved_crates));
+
+    let feature_unification = options
+        .feature_unification
+        .then(|| unified_features(&resolve, &resolved_crates));
+
+    // Crates that only build scripts need can be left out of what is
+    // reported about the resolve.
+    let unreported = resolved_crates
+        .iter()
+        .filter(|(_, dep)| dep.build_only && options.exclude_build_dependencies)
+        .map(|(&id, _)| id)
+        .collect::<BTreeSet<_>>();
+
+    let resolution_graph = options
+        .resolution_graph
+        .then(|| resolution::graph(&resolve, &unreported));
+
+    let explanation = options
+        .explain
+        .as_deref()
+        .map(|name| explain_inclusion(&resolve, &top_level, &resolved_crates, name));
+
+    let dependencies = generate_dependency_specs(
+        &resolved_crates,
+        options,
+        &modifications.renames,
+        global.diagnostics,
+    );
+    let mut infos = generate_crate_information(
+        &dependencies,
+        &resolved_crates,
+        &global.listings,
+        options.version_format,
+    );
+    sanitize_crate_information(&mut infos, global.diagnostics);
+    // The features in the manifest are unified the way version 1 does,
+    // which version 2 may enable fewer of.
+    let resolver = FeatureResolver::written(&modifications.package, options.cargo_compat.as_ref());
+    if resolver == Some(FeatureResolver::V2) {
+        let ids = resolved_crates
+            .keys()
+            .map(|&id| {
+                let version = options.version_format.normalize(id.version());
+                ((id.name().as_str(), version), id)
+            })
+            .collect();
+        let activated = v2_features(&mut global, &dependencies, &ids)?;
+        for info in &mut infos {
+            if let Some(features) = activated.get(&info.id) {
+                info.features = features
+                    .iter()
+                    .filter(|&&feature| feature != "default")
+                    .map(|feature| feature.to_string())
+                    .collect();
+            }
+        }
+    }
+    check_consistency(&dependencies, &infos)?;
+    let cargo_resolve = serialize_resolve(&resolve)?;
+    let feature_counts = FeatureCounts::new(
+        dependencies
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.features.len(), spec.default_features)),
+    );
+    let reported = resolved_crates.keys().filter(|id| !unreported.contains(id));
+    let download_size = DownloadSize::new(reported.map(|&id| {
+        let size = fs::metadata(crate_file(config.get(), id))
+            .ok()
+            .map(|metadata| metadata.len());
+        (id.name().to_string(), id.version().clone(), size)
+    }));
+    let resolved_crates = resolved_crates
+        .values()
+        .map(|dep| ResolvedCrate {
+            name: dep.summary.name().to_string(),
+            version: dep.summary.version().clone(),
+            build_only: dep.build_only,
+            rust_version: dep.rust_version.clone(),
+        })
+        .collect_vec();
+    let summary = ResolutionSummary::new(
+        global.listed,
+        top_level.len(),
+        resolve.iter().count(),
+        &resolved_crates,
+    );
+
+    Ok(GeneratedInfo {
+        dependencies,
+        infos,
+        resolved_crates,
+        cargo_resolve,
+        trim_suggestion,
+        feature_provenance,
+        feature_unification,
+        resolution_graph,
+        explanation,
+        summary,
+        download_size,
+        feature_counts,
+        metadata_errors: global.metadata_errors.into_values().collect(),
+        redundant_additions: global
+            .redundant_additions
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        timings: global.timings,
+    })
+}
+
+/// The shortest chain of dependencies leading to each resolved
+/// version of the crate called `name`.
+fn explain_inclusion(
+    resolve: &Resolve,
+    top_level: &[PackageId],
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    name: &str,
+) -> Explanation {
+    let normalized = normalize_crate_name(name);
+    let paths = crates
+        .keys()
+        .filter(|id| normalize_crate_name(&id.name()) == normalized)
+        .filter_map(|&target| {
+            // Only the crates built for the playground's platform are
+            // followed, which are the ones that were kept.
+            let dependencies = |id| {
+                resolve
+                    .deps(id)
+                    .map(|(dep, _)| dep)
+                    .filter(|dep| crates.contains_key(dep))
+            };
+            dependency_path::shortest(top_level.iter().copied(), target, dependencies)
+        })
+        .map(|path| {
+            path.into_iter()
+                .map(|id| (id.name().to_string(), id.version().clone()))
+                .collect()
+        })
+        .collect();
+
+    Explanation {
+        name: name.to_owned(),
+        paths,
+    }
+}
+
+/// What [`generate`] works out: everything in [`GeneratedInfo`], with
+/// the dependencies already made into the playground's manifest.
+#[derive(Debug)]
+pub struct GeneratedOutput {
+    pub manifest: TomlManifest,
+    pub infos: Vec<CrateInformation>,
+    pub resolved_crates: Vec<ResolvedCrate>,
+    /// The final resolve, serialized the same way cargo writes
+    /// `Cargo.lock`.
+    pub cargo_resolve: String,
+    pub trim_suggestion: Option<TrimSuggestion>,
+    pub feature_provenance: Option<Vec<FeatureProvenance>>,
+    pub feature_unification: Option<Vec<FeatureUnification>>,
+    pub resolution_graph: Option<Vec<ResolvedPackage>>,
+    pub explanation: Option<Explanation>,
+    pub summary: ResolutionSummary,
+    pub download_size: DownloadSize,
+    pub feature_counts: FeatureCounts,
+    pub metadata_errors: Vec<MetadataError>,
+    pub redundant_additions: Vec<String>,
+    pub timings: Timings,
+}
+
+/// Works out the playground's manifest and everything else about its
+/// crates, without writing anything to disk.
+pub fn generate(
+    modifications: &Modifications,
+    options: &GenerateOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<GeneratedOutput, TopCratesError> {
+    let GeneratedInfo {
+        dependencies,
+        infos,
+        resolved_crates,
+        cargo_resolve,
+        trim_suggestion,
+        feature_provenance,
+        feature_unification,
+        resolution_graph,
+        explanation,
+        summary,
+        download_size,
+        feature_counts,
+        metadata_errors,
+        redundant_additions,
+        timings,
+    } = generate_info(modifications, options, diagnostics)?;
+
+    let profile = options.profile.clone().or(&modifications.profile);
+    let mut manifest = TomlManifest::playground(
+        dependencies,
+        &modifications.package,
+        &modifications.playground_features,
+        &profile,
+        &modifications.patches,
+        &modifications.target_deps,
+        options.cargo_compat.as_ref(),
+    );
+    match &options.cargo_compat {
+        Some(compat) if *compat < Version::new(1, 41, 0) => {
+            if !modifications.profile_overrides.is_empty() {
+                diagnostics.warn(format!(
+                    "Not writing the profile overrides, which cargo {} does not understand",
+                    compat
+                ));
+            }
+        }
+        _ => manifest.override_profiles(&modifications.profile_overrides),
+    }
+
+    Ok(GeneratedOutput {
+        manifest,
+        infos,
+        resolved_crates,
+        cargo_resolve,
+        trim_suggestion,
+        feature_provenance,
+        feature_unification,
+        resolution_graph,
+        explanation,
+        summary,
+        download_size,
+        feature_counts,
+        metadata_errors,
+        redundant_additions,
+        timings,
+    })
+}
+
+/// A crate that has a newer version in the registry than the one a
+/// manifest uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedCrate {
+    pub name: String,
+    pub current: Version,
+    pub latest: Version,
+}
+
+/// Looks up the newest version of each of the `current` crates, keyed
+/// by package name, and lists those that are behind it. When a crate is
+/// used at several versions, the older ones are only compared with the
+/// releases they are semver compatible with. Prereleases are never
+/// counted as newer.
+pub fn outdated(
+    current: &BTreeMap<String, Vec<Version>>,
+    modifications: &Modifications,
+    options: &GenerateOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<OutdatedCrate>, TopCratesError> {
+    let config = cargo_config(options)?;
+    let _lock = config.lock()?;
+    let mut global = make_global_state(config.get(), modifications, options, diagnostics)?;
+
+    let names = current.keys().map(|name| InternedString::new(name));
+    let candidates = query_candidates(&mut global, names)?;
+    for name in current.keys() {
+        let published = candidates.get(name.as_str()).is_some_and(|c| !c.is_empty());
+        if !published {
+            global
+                .diagnostics
+                .warn_for(name, "Unable to find the crate in the registry");
+        }
+    }
+
+    Ok(newer_versions(current, candidates))
+}
+
+/// How much one top-level crate that isn't in the baseline adds to
+/// its resolve
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Growth {
+    pub name: String,
+    pub version: Version,
+    /// The crates it brings in that the baseline doesn't have, as
+    /// `name version`, itself included.
+    pub added: Vec<String>,
+}
+
+/// Finds which of the top-level crates that aren't among the
+/// `baseline` ones, such as those of an existing Cargo.toml, make the
+/// resolve grow the most, most first.
+///
+/// The baseline crates are resolved on their own, and then with each
+/// of the others in turn, so this resolves once more than there are
+/// new crates. Only the crates used on the playground's platform are
+/// counted.
+pub fn bisect_growth(
+    baseline: &BTreeSet<String>,
+    modifications: &Modifications,
+    options: &GenerateOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<Growth>, TopCratesError> {
+    let config = cargo_config(options)?;
+    let _lock = config.lock()?;
+    let mut global = make_global_state(config.get(), modifications, options, diagnostics)?;
+
+    let (known, new): (Vec<_>, Vec<_>) = populate_initial_direct_dependencies(&mut global)?
+        .into_values()
+        .partition(|dep| baseline.contains(dep.summary.name().as_str()));
+    info!(
+        "Measuring {} crates against {} in the baseline",
+        new.len(),
+        known.len()
+    );
+
+    let roots = known
+        .into_iter()
+        .map(|dep| resolve_root(modifications, dep))
+        .collect_vec();
+    fn reachable(
+        global: &mut GlobalState<'_>,
+        summaries: &[(Summary, ResolveOpts)],
+    ) -> Result<BTreeSet<PackageId>, TopCratesError> {
+        let resolve = resolve_roots(global, summaries)?;
+        let ids = summaries.iter().map(|(s, _)| s.package_id()).collect();
+        Ok(reachable_packages(
+            &resolve,
+            ids,
+            &global.target_info,
+            false,
+        ))
+    }
+    let before = reachable(&mut global, &roots)?;
+
+    let mut growth = Vec::new();
+    for dep in new {
+        let (name, version) = (dep.summary.name(), dep.summary.version().clone());
+        let mut summaries = roots.clone();
+        summaries.push(resolve_root(modifications, dep));
+        let after = reachable(&mut global, &summaries)?;
+        growth.push(Growth {
+            name: name.to_string(),
+            version,
+            added: after
+                .difference(&before)
+                .map(|id| format!("{} {}", id.name(), id.version()))
+                .collect(),
+        });
+    }
+    growth.sort_by(|a, b| {
+        b.added
+            .len()
+            .cmp(&a.added.len())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(growth)
+}
+
+fn newer_versions(
+    current: &BTreeMap<String, Vec<Version>>,
+    mut candidates: BTreeMap<InternedString, Vec<Candidate>>,
+) -> Vec<OutdatedCrate> {
+    let mut outdated = Vec::new();
+    for (name, versions) in current {
+        let candidates = match candidates.remove(name.as_str()) {
+            Some(candidates) => candidates,
+            None => continue,
+        };
+        let newest = versions.iter().max();
+        for version in versions {
+            // Older versions kept alongside a newer one are held back
+            // on purpose, so only releases compatible with them count.
+            let compatible = if Some(version) == newest {
+                None
+            } else {
+                VersionReq::parse(&format!("^{}", version)).ok()
+            };
+            let latest = viable_candidates(candidates.clone(), compatible.as_ref(), false)
+                .into_iter()
+                .next();
+            if let Some(latest) = latest.filter(|latest| latest.version > *version) {
+                outdated.push(OutdatedCrate {
+                    name: name.clone(),
+                    current: version.clone(),
+                    latest: latest.version,
+                });
+            }
+        }
+    }
+    outdated
+}
+
+/// The features each crate asks of each of its dependencies, either
+/// directly or through one of its own enabled features, keyed by the
+/// dependency and then the name of the crate asking.
+fn requested_features(
+    resolve: &Resolve,
+) -> BTreeMap<PackageId, BTreeMap<String, BTreeSet<InternedString>>> {
+    let mut requested: BTreeMap<PackageId, BTreeMap<String, BTreeSet<InternedString>>> =
+        BTreeMap::new();
+    for parent in resolve.iter() {
+        let declared = resolve.summary(parent).features();
+        let enabled = resolve.features(parent);
+
+        for (child, deps) in resolve.deps(parent) {
+            let features = requested
+                .entry(child)
+                .or_default()
+                .entry(parent.name().to_string())
+                .or_default();
+
+            for dep in deps {
+                features.extend(dep.features());
+                let through_features = enabled
+                    .iter()
+                    .flat_map(|feature| declared.get(feature).into_iter().flatten());
+                for value in through_features {
+                    if let FeatureValue::DepFeature {
+                        dep_name,
+                        dep_feature,
+                        ..
+                    } = value
+                    {
+                        if *dep_name == dep.name_in_toml() {
+                            features.insert(*dep_feature);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    requested
+}
+
+fn explain_features(
+    resolve: &Resolve,
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+) -> Vec<FeatureProvenance> {
+    let requested = requested_features(resolve);
+    let no_dependents = BTreeMap::new();
+    crates
+        .values()
+        .map(|dep| {
+            let id = dep.summary.package_id();
+            let dependents = requested.get(&id).unwrap_or(&no_dependents);
+            FeatureProvenance {
+                name: id.name().to_string(),
+                version: id.version().clone(),
+                features: provenance::explain(
+                    dep.summary.features(),
+                    &dep.features,
+                    &dep.metadata_features,
+                    dependents,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// The unified features of every crate that other crates depend on and
+/// that is only included at one version, as those are where the
+/// requests of different crates meet.
+fn unified_features(
+    resolve: &Resolve,
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+) -> Vec<FeatureUnification> {
+    let requested = requested_features(resolve);
+    let versions = crates.keys().map(|id| id.name()).counts();
+    let expands_all_features = crates
+        .values()
+        .filter(|dep| dep.all_features)
+        .map(|dep| dep.summary.name().to_string())
+        .collect::<BTreeSet<_>>();
+
+    crates
+        .values()
+        .filter(|dep| versions[&dep.summary.name()] == 1)
+        .filter_map(|dep| {
+            let id = dep.summary.package_id();
+            let dependents = requested.get(&id).filter(|d| !d.is_empty())?;
+            Some(FeatureUnification {
+                name: id.name().to_string(),
+                version: id.version().clone(),
+                features: dep.features.clone(),
+                requested_by: dependents.clone(),
+                from_all_features: provenance::from_all_features(
+                    &dep.features,
+                    &dep.metadata_features,
+                    dep.all_features,
+                    dependents,
+                    |dependent| expands_all_features.contains(dependent),
+                ),
+            })
+        })
+        .collect()
+}
+
+/// The packages of the lockfile at `path` that come from a registry,
+/// taken to be the one at `registry_id`.
+fn locked_packages(
+    path: &Path,
+    registry_id: SourceId,
+) -> Result<BTreeSet<PackageId>, TopCratesError> {
+    #[derive(Deserialize)]
+    struct Lockfile {
+        #[serde(default)]
+        package: Vec<LockedPackage>,
+    }
+
+    #[derive(Deserialize)]
+    struct LockedPackage {
+        name: String,
+        version: Version,
+        source: Option<String>,
+    }
+
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let lockfile: Lockfile = toml::from_str(&content).map_err(TopCratesError::toml_parse(
+        format!("Unable to parse {}", path.display()),
+    ))?;
+    lockfile
+        .package
+        .into_iter()
+        .filter(|package| {
+            package.source.as_deref().is_some_and(|source| {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            })
+        })
+        .map(|package| {
+            PackageId::new(package.name.as_str(), package.version, registry_id).map_err(
+                TopCratesError::registry(format!("Invalid package in {}", path.display())),
+            )
+        })
+        .collect()
+}
+
+/// The crates that were `locked` at versions none of which were
+/// resolved, along with the versions they were resolved to instead.
+fn changed_versions(
+    locked: &BTreeSet<PackageId>,
+    resolved: impl IntoIterator<Item = PackageId>,
+) -> BTreeMap<InternedString, (Vec<Version>, Vec<Version>)> {
+    fn by_name(ids: impl IntoIterator<Item = PackageId>) -> BTreeMap<InternedString, Vec<Version>> {
+        let mut versions = BTreeMap::<_, Vec<_>>::new();
+        for id in ids {
+            versions
+                .entry(id.name())
+                .or_default()
+                .push(id.version().clone());
+        }
+        versions
+    }
+
+    let mut before = by_name(locked.iter().copied());
+    by_name(resolved)
+        .into_iter()
+        .filter_map(|(name, mut now)| {
+            let mut was = before.remove(&name)?;
+            if now.iter().any(|version| was.contains(version)) {
+                return None;
+            }
+            was.sort();
+            now.sort();
+            Some((name, (was, now)))
+        })
+        .collect()
+}
+
+/// Uses cargo's own encoding of a resolve, which is what it writes to
+/// `Cargo.lock`.
+fn serialize_resolve(resolve: &Resolve) -> Result<String, TopCratesError> {
+    // Converting to a `Value` first ensures that plain values are
+    // emitted before any tables.
+    let encoded = toml::Value::try_from(resolve).map_err(TopCratesError::toml_serialize(
+        "Unable to encode the resolve",
+    ))?;
+    toml::to_string(&encoded).map_err(TopCratesError::toml_serialize(
+        "Unable to serialize the resolve",
+    ))
+}
+
+/// Builds a `Cargo.lock` for a package named `name` that depends on
+/// exactly `dependencies`, locking everything to `cargo_resolve`.
+pub fn playground_lockfile(
+    cargo_resolve: &str,
+    name: &str,
+    version: &str,
+    dependencies: &BTreeMap<String, DependencySpec>,
+) -> Result<String, TopCratesError> {
+    let mut lockfile: toml::value::Table = toml::from_str(cargo_resolve)
+        .map_err(TopCratesError::toml_parse("Unable to parse the resolve"))?;
+    let mut packages = match lockfile.remove("package") {
+        Some(toml::Value::Array(packages)) => packages,
+        _ => vec![],
+    };
+
+    let locked_versions = |package: &str| {
+        packages
+            .iter()
+            .filter(|p| p.get("name").and_then(toml::Value::as_str) == Some(package))
+            .filter_map(|p| p.get("version")?.as_str()?.parse::<Version>().ok())
+            .collect_vec()
+    };
+
+    // Like cargo, only mention the version of a dependency when more
+    // than one version of it is locked.
+    let root_dependencies = dependencies
+        .iter()
+        .map(|(exposed_name, spec)| {
+            let package = if spec.package.is_empty() {
+                exposed_name
+            } else {
+                &spec.package
+            };
+            let versions = locked_versions(package);
+            if versions.len() <= 1 {
+                return package.to_owned();
+            }
+            // The written version may have lost its build metadata.
+            let version = versions
+                .iter()
+                .find(|v| {
+                    let normalize = |v| VersionFormat::Semver3.normalize(v);
+                    normalize(v) == normalize(&spec.version)
+                })
+                .unwrap_or(&spec.version);
+            format!("{} {}", package, version)
+        })
+        .sorted()
+        .dedup()
+        .map(toml::Value::String)
+        .collect();
+
+    let mut root = toml::value::Table::new();
+    root.insert("name".to_owned(), toml::Value::String(name.to_owned()));
+    root.insert(
+        "version".to_owned(),
+        toml::Value::String(version.to_owned()),
+    );
+    root.insert(
+        "dependencies".to_owned(),
+        toml::Value::Array(root_dependencies),
+    );
+    packages.push(toml::Value::Table(root));
+
+    let sort_key = |p: &toml::Value| {
+        let field = |f| p.get(f).and_then(toml::Value::as_str).map(str::to_owned);
+        let version = field("version").and_then(|v| v.parse::<Version>().ok());
+        (field("name"), version)
+    };
+    packages.sort_by_cached_key(sort_key);
+    lockfile.insert("package".to_owned(), toml::Value::Array(packages));
+
+    let content = toml::to_string(&toml::Value::Table(lockfile)).map_err(
+        TopCratesError::toml_serialize("Unable to serialize Cargo.lock"),
+    )?;
+    Ok(format!(
+        "# This file is automatically @generated by Cargo.\n\
+         # It is not intended for manual editing.\n\
+         {}",
+        content
+    ))
+}
+
+/// Reports the crates that an advisory in the database at
+/// `advisory_db` affects. Vulnerabilities are errors when `deny` is
+/// set, while informational advisories, such as a crate being
+/// unmaintained, are only ever warnings.
+fn check_advisories(
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    advisory_db: &Path,
+    deny: bool,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), TopCratesError> {
+    let (advisories, errors) = advisories::load(advisory_db)?;
+    for error in errors {
+        diagnostics.warn(error);
+    }
+
+    for id in crates.keys() {
+        let affecting = advisories
+            .get(id.name().as_str())
+            .into_iter()
+            .flatten()
+            .filter(|advisory| advisory.affects(id.version()));
+        for advisory in affecting {
+            let title = advisory.title.as_deref().unwrap_or("no title");
+            match &advisory.informational {
+                Some(kind) => {
+                    diagnostics.warn_for(id, format!("{} ({}): {}", advisory.id, kind, title))
+                }
+                None => {
+                    let message = format!("{}: {}", advisory.id, title);
+                    if deny {
+                        diagnostics.error_for(id, message);
+                    } else {
+                        diagnostics.warn_for(id, message);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_licenses(
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    allowlist: &[String],
+    diagnostics: &mut Diagnostics,
+) {
+    let allowed = |id: &str| allowlist.iter().any(|a| a == id);
+
+    for (id, dep) in crates {
+        let license = match &dep.license {
+            Some(license) => license,
+            None => {
+                diagnostics.error_for(id, "No SPDX license is declared");
+                continue;
+            }
+        };
+
+        match license::Expression::parse(license) {
+            Ok(expression) if expression.satisfied_by(&allowed) => {}
+            Ok(_) => diagnostics.error_for(
+                id,
+                format!("License `{}` is not covered by the allowlist", license),
+            ),
+            Err(e) => diagnostics.error_for(id, e.to_string()),
+        }
+    }
+}
+
+/// The components of `version` up to and including the first non-zero
+/// one, which cargo needs to differ before it will use both versions.
+fn compatibility_suffix(version: &Version) -> String {
+    match (version.major, version.minor) {
+        (0, 0) => format!("0_0_{}", version.patch),
+        (0, minor) => format!("0_{}", minor),
+        (major, _) => major.to_string(),
+    }
+}
+
+/// Turns `name` into something that can be written in a `use`
+/// statement, or returns `None` when it already can be.
+fn rust_identifier(name: &str) -> Option<String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let starts_with_digit = name.starts_with(|c: char| c.is_ascii_digit());
+    if !name.is_empty() && !starts_with_digit && name.chars().all(valid) {
+        return None;
+    }
+
+    let mut identifier = String::with_capacity(name.len() + 1);
+    if name.is_empty() || starts_with_digit {
+        identifier.push('_');
+    }
+    identifier.extend(name.chars().map(|c| if valid(c) { c } else { '_' }));
+    Some(identifier)
+}
+
+/// Works out the name each crate is exposed as and the features it is
+/// depended on with. Crates are named after their library, with the
+/// versions besides the newest one getting a suffix, unless `renames`
+/// names them explicitly.
+fn generate_dependency_specs(
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    options: &GenerateOptions,
+    renames: &Renames,
+    diagnostics: &mut Diagnostics,
+) -> BTreeMap<String, DependencySpec> {
+    // Sort all packages by name then version (descending), so that
+    // when we group them we know we get all the same crates together
+    // and the newest version first.
+    let mut crates = crates.values().collect_vec();
+    crates.sort_by(|a, b| {
+        let name_cmp = a.summary.name().as_str().cmp(b.summary.name().as_str());
+        let version_cmp = a.summary.version().cmp(b.summary.version());
+        name_cmp.then(version_cmp.reverse())
+    });
+
+    let mut dependencies = BTreeMap::new();
+    for (name, pkgs) in &crates.iter().group_by(|dep| dep.summary.name()) {
+        let pkgs = pkgs.collect_vec();
+        let mut first = true;
+
+        // Every version of the crate should get all of the features
+        // requested for any version of it.
+        let unified_features = if options.unify_duplicate_features && pkgs.len() > 1 {
+            Some(
+                pkgs.iter()
+                    .flat_map(|dep| &dep.features)
+                    .copied()
+                    .collect::<BTreeSet<_>>(),
+            )
+        } else {
+            None
+        };
+
+        for dep in pkgs {
+            let summary = &dep.summary;
+            let version = summary.version();
+
+            // We see the newest version first. Any subsequent
+            // versions will have the semver-compatible part of their
+            // version appended so that they are uniquely named
+            let crate_name = dep.lib_target.crate_name();
+            let renamed = renames
+                .get(&name)
+                .and_then(|renames| renames.iter().find(|(req, _)| req.matches(version)));
+            let exposed_name = if let Some((_, rename)) = renamed {
+                rename.clone()
+            } else if first {
+                crate_name
+            } else {
+                format!("{}_{}", crate_name, compatibility_suffix(version))
+            };
+            let exposed_name = match rust_identifier(&exposed_name) {
+                Some(identifier) => {
+                    diagnostics.warn_for(
+                        summary.package_id(),
+                        format!(
+                            "`{}` is not a valid identifier; exposing it as `{}`",
+                            exposed_name, identifier
+                        ),
+                    );
+                    identifier
+                }
+                None => exposed_name,
+            };
+
+            let mut features = dep.features.clone();
+            for &feature in unified_features.iter().flatten() {
+                if let Ok(feature) = requested_feature(summary, feature) {
+                    features.insert(feature);
+                } else {
+                    diagnostics.warn_for(
+                        summary.package_id(),
+                        format!(
+                            "Feature `{}` is enabled on another version but does not exist here",
+                            feature,
+                        ),
+                    );
+                }
+            }
+
+            let mut default_features = dep.uses_default_features;
+            let fold_default = !options.keep_default_feature
+                && (features.contains("default") || summary.features().get("default").is_none());
+            if options.minimal_features {
+                features.clear();
+                default_features = false;
+            } else if fold_default {
+                features.remove("default");
+                default_features = true;
+            }
+
+            debug!(
+                "Exposing {} {} as `{}` with features [{}]{}",
+                name,
+                version,
+                exposed_name,
+                features.iter().join(", "),
+                if default_features {
+                    ""
+                } else {
+                    " and no default features"
+                }
+            );
+            let spec = DependencySpec {
+                package: name.to_string(),
+                version: options.version_format.normalize(version),
+                features,
+                default_features,
+            };
+            match dependencies.entry(exposed_name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(spec);
+                }
+                Entry::Occupied(entry) => {
+                    let existing = entry.get();
+                    diagnostics.error_for(
+                        summary.package_id(),
+                        format!(
+                            "Would be exposed as `{}`, the same as {} {}; \
+                             exclude one of them in crate-modifications.toml",
+                            entry.key(),
+                            existing.package,
+                            existing.version,
+                        ),
+                    );
+                }
+            }
+
+            first = false;
+        }
+    }
+
+    dependencies
+}
+
+/// Makes sure that every crate exposed in `dependencies` has the same
+/// version in `infos`, and the other way around.
+fn check_consistency(
+    dependencies: &BTreeMap<String, DependencySpec>,
+    infos: &[CrateInformation],
+) -> Result<(), TopCratesError> {
+    let mut mismatches = Vec::new();
+    for info in infos {
+        match dependencies.get(&info.id) {
+            None => mismatches.push(format!("`{}` is only in crate-information.json", info.id)),
+            Some(spec) if spec.package != info.name || spec.version != info.version => mismatches
+                .push(format!(
+                    "`{}` is {} {} in Cargo.toml but {} {} in crate-information.json",
+                    info.id, spec.package, spec.version, info.name, info.version
+                )),
+            Some(_) => {}
+        }
+    }
+    let ids = infos
+        .iter()
+        .map(|info| info.id.as_str())
+        .collect::<BTreeSet<_>>();
+    for exposed_name in dependencies.keys() {
+        if !ids.contains(exposed_name.as_str()) {
+            mismatches.push(format!("`{}` is only in Cargo.toml", exposed_name));
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(TopCratesError::Inconsistent { mismatches })
+    }
+}
+
+fn generate_crate_information(
+    dependencies: &BTreeMap<String, DependencySpec>,
+    crates: &BTreeMap<PackageId, ResolvedDep>,
+    listings: &BTreeMap<InternedString, Crate>,
+    version_format: VersionFormat,
+) -> Vec<CrateInformation> {
+    // The dependencies only know their versions as they are written
+    // out, so the crates are found the same way.
+    let crates = crates
+        .values()
+        .map(|dep| {
+            let summary = &dep.summary;
+            let version = version_format.normalize(summary.version());
+            ((summary.name().as_str(), version), dep)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut infos = Vec::new();
+
+    for (exposed_name, dependency_spec) in dependencies {
+        let listing = listings.get(dependency_spec.package.as_str());
+        let key = (
+            dependency_spec.package.as_str(),
+            dependency_spec.version.clone(),
+        );
+        let dep = crates.get(&key);
+        infos.push(CrateInformation {
+            name: dependency_spec.package.clone(),
+            version: dependency_spec.version.clone(),
+            id: exposed_name.clone(),
+            origin: listing.map(|l| {
+                if l.curated {
+                    CrateOrigin::Curated
+                } else {
+                    CrateOrigin::Top
+                }
+            }),
+            created_at: listing.and_then(|l| l.created_at.clone()),
+            updated_at: listing.and_then(|l| l.updated_at.clone()),
+            description: dep.and_then(|dep| dep.description.clone()),
+            repository: dep.and_then(|dep| dep.repository.clone()),
+            license: dep.and_then(|dep| dep.license.clone()),
+            edition: dep.map(|dep| dep.lib_target.edition().to_string()),
+            features: dependency_spec
+                .features
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect(),
+            default_features: dependency_spec.default_features,
+        });
+    }
+
+    // Exposed names don't sort the same way as crate names do, so the
+    // file is ordered by crate name, newest version first, to keep the
+    // differences between regenerations small.
+    infos.sort_by(|a, b| a.name.cmp(&b.name).then(b.version.cmp(&a.version)));
     infos
 }
+
+/// Puts the text taken from crates' metadata on a single line, with
+/// runs of whitespace collapsed and other control characters removed,
+/// warning about each field that needed more than trimming. The name,
+/// version, and id are left alone.
+fn sanitize_crate_information(infos: &mut [CrateInformation], diagnostics: &mut Diagnostics) {
+    for info in infos {
+        let fields = [
+            ("description", &mut info.description),
+            ("license", &mut info.license),
+            ("repository", &mut info.repository),
+        ];
+        for (field, value) in fields {
+            let text = match value {
+                Some(text) => text,
+                None => continue,
+            };
+            let clean = single_line(text);
+            if clean != text.trim() {
+                diagnostics.warn_for(
+                    format!("{} {}", info.name, info.version),
+                    format!(
+                        "The {} contains line breaks or control characters, which were removed",
+                        field
+                    ),
+                );
+            }
+            *text = clean;
+        }
+    }
+}
+
+/// `text` on one line, with runs of whitespace replaced by a single
+/// space and other control characters removed.
+fn single_line(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_whitespace() || !c.is_control())
+        .collect::<String>()
+        .split_whitespace()
+        .join(" ")
+}
+
+/// The number of features enabled on each dependency, largest first.
+///
+/// Because the dependencies are built from the resolve, this counts
+/// the unified feature set and not only the features requested by
+/// the playground metadata.
+pub fn feature_counts(dependencies: &BTreeMap<String, DependencySpec>) -> Vec<(&str, usize)> {
+    let mut counts = dependencies
+        .iter()
+        .map(|(exposed_name, spec)| (exposed_name.as_str(), spec.features.len()))
+        .collect_vec();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+}
+
+/// Checks `infos` against [`CRATE_INFORMATION_SCHEMA`] as they would
+/// be written to `crate-information.json`, describing each mismatch.
+pub fn validate_crate_information(infos: &[CrateInformation]) -> Vec<String> {
+    let value = serde_json::to_value(infos).expect("Unable to serialize crate information");
+    schema::validate(CRATE_INFORMATION_SCHEMA, &value)
+}
+
+/// Checks an existing `crate-information.json`, such as one that was
+/// edited by hand or merged, describing every problem found: where it
+/// does not match [`CRATE_INFORMATION_SCHEMA`], ids that are used more
+/// than once or could not be written in a `use` statement, and
+/// versions that aren't semver.
+pub fn check_crate_information(value: &serde_json::Value) -> Vec<String> {
+    let mut errors = schema::validate(CRATE_INFORMATION_SCHEMA, value);
+    let entries = value.as_array().map(Vec::as_slice).unwrap_or_default();
+
+    let mut ids = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let name = entry["name"].as_str().unwrap_or("?");
+        if let Some(id) = entry["id"].as_str() {
+            match ids.entry(id) {
+                Entry::Occupied(first) => errors.push(format!(
+                    "/{} ({}): id `{}` is also used by /{}",
+                    i,
+                    name,
+                    id,
+                    first.get()
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert(i);
+                }
+            }
+            if let Some(valid) = rust_identifier(id) {
+                errors.push(format!(
+                    "/{} ({}): id `{}` is not a Rust identifier; `{}` would be",
+                    i, name, id, valid
+                ));
+            }
+        }
+        if let Some(version) = entry["version"].as_str() {
+            if let Err(e) = Version::parse(version) {
+                errors.push(format!(
+                    "/{} ({}): version `{}` is not semver: {}",
+                    i, name, version, e
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Parses a Rust version as written in `rust-version`, where the
+/// patch (and minor) parts may be left out.
+pub fn parse_rust_version(s: &str) -> Result<Version, String> {
+    let mut parts = s.split('.').map(str::parse::<u64>);
+    let mut next = |required: bool| match parts.next() {
+        Some(Ok(part)) => Ok(part),
+        None if !required => Ok(0),
+        _ => Err(format!("invalid Rust version `{}`", s)),
+    };
+    let version = Version::new(next(true)?, next(false)?, next(false)?);
+    match parts.next() {
+        None => Ok(version),
+        Some(_) => Err(format!("invalid Rust version `{}`", s)),
+    }
+}
+
+/// The resolved crates that declare a `rust-version` newer than
+/// `rust_version` and so could not be built with it.
+///
+/// Crates without a (valid) `rust-version` are assumed to build with
+/// any version.
+pub fn msrv_exclusions<'a>(
+    crates: &'a [ResolvedCrate],
+    rust_version: &Version,
+) -> Vec<&'a ResolvedCrate> {
+    crates
+        .iter()
+        .filter(|krate| requires_newer_rust(krate.rust_version.as_deref(), rust_version))
+        .collect()
+}
+
+/// Whether a crate declaring `required` as its `rust-version` needs a
+/// newer Rust than `available`. A missing or invalid `rust-version`
+/// is assumed to be satisfied by any version.
+fn requires_newer_rust(required: Option<&str>, available: &Version) -> bool {
+    required
+        .and_then(|v| parse_rust_version(v).ok())
+        .is_some_and(|required| required > *available)
+}
+
+/// Checks that every curated addition made it into the generated
+/// dependencies, reporting why any of them are missing.
+///
+/// Missing additions are warnings unless `required` is set, in which
+/// case they are errors.
+pub fn verify_additions(
+    modifications: &Modifications,
+    dependencies: &BTreeMap<String, DependencySpec>,
+    required: bool,
+    diagnostics: &mut Diagnostics,
+) {
+    let included: BTreeSet<&str> = dependencies
+        .values()
+        .map(|spec| spec.package.as_str())
+        .collect();
+
+    for &name in modifications.additions.keys() {
+        if included.contains(name.as_str()) {
+            continue;
+        }
+
+        let reason = if modifications.excluded(&name) {
+            "it is excluded"
+        } else {
+            "it was not part of the resolved crates"
+        };
+        let message = format!("Curated addition is missing from the output because {reason}");
+
+        if required {
+            diagnostics.error_for(name, message);
+        } else {
+            diagnostics.warn_for(name, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resolved_dep(
+        name: &str,
+        version: &str,
+        features: &[&str],
+        declared_features: &[&str],
+    ) -> ResolvedDep {
+        let config = Config::default().unwrap();
+        let crates_io = SourceId::crates_io(&config).unwrap();
+        let id = PackageId::new(name, version, crates_io).unwrap();
+        let declared_features = declared_features
+            .iter()
+            .map(|&f| (f.into(), vec![]))
+            .collect();
+        let summary = Summary::new(&config, id, vec![], &declared_features, None::<&str>).unwrap();
+        let lib_target = Target::lib_target(
+            name,
+            vec![CrateType::Lib],
+            "/src/lib.rs".into(),
+            Edition::Edition2018,
+        );
+
+        ResolvedDep {
+            summary,
+            lib_target,
+            features: features.iter().map(|&f| f.into()).collect(),
+            uses_default_features: false,
+            license: None,
+            description: None,
+            repository: None,
+            build_only: false,
+            rust_version: None,
+            metadata_features: BTreeSet::new(),
+            all_features: false,
+        }
+    }
+
+    fn newest_viable(
+        candidates: Vec<Candidate>,
+        pin: Option<&VersionReq>,
+        allow_prerelease: bool,
+    ) -> Option<Candidate> {
+        viable_candidates(candidates, pin, allow_prerelease)
+            .into_iter()
+            .next()
+    }
+
+    fn resolved_crates(deps: Vec<ResolvedDep>) -> BTreeMap<PackageId, ResolvedDep> {
+        deps.into_iter()
+            .map(|dep| (dep.summary.package_id(), dep))
+            .collect()
+    }
+
+    fn spec(package: &str, version: &str) -> DependencySpec {
+        DependencySpec {
+            package: package.to_owned(),
+            version: version.parse().unwrap(),
+            features: BTreeSet::new(),
+            default_features: true,
+        }
+    }
+
+    fn package(manifest: &str) -> Package {
+        let config = Config::default().unwrap();
+        let crates_io = SourceId::crates_io(&config).unwrap();
+        let path = Path::new("/krate/Cargo.toml");
+        let (manifest, _) =
+            cargo::util::toml::read_manifest_from_str(manifest, path, crates_io, &config).unwrap();
+        match manifest {
+            cargo::core::EitherManifest::Real(manifest) => Package::new(manifest, path),
+            cargo::core::EitherManifest::Virtual(_) => panic!("not a package manifest"),
+        }
+    }
+
+    #[test]
+    fn binaries_are_left_out() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "ripgrep"
+            version = "13.0.0"
+
+            [[bin]]
+            name = "rg"
+            path = "main.rs"
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        assert!(top_level_library(&pkg, &mut diagnostics).is_none());
+        assert_eq!(diagnostics.count(Severity::Warning), 1);
+
+        let pkg = package(
+            r#"
+            [package]
+            name = "regex"
+            version = "1.7.0"
+
+            [lib]
+            path = "lib.rs"
+            "#,
+        );
+        assert!(top_level_library(&pkg, &mut diagnostics).is_some());
+        assert_eq!(diagnostics.count(Severity::Warning), 1);
+    }
+
+    #[test]
+    fn libraries_are_preferred_to_proc_macros() {
+        let lib = |name: &str, crate_types| {
+            Target::lib_target(
+                name,
+                crate_types,
+                "/src/lib.rs".into(),
+                Edition::Edition2018,
+            )
+        };
+        let targets = [
+            lib("foo_derive", vec![CrateType::ProcMacro]),
+            lib("foo", vec![CrateType::Rlib]),
+            lib("foo_ffi", vec![CrateType::Cdylib]),
+        ];
+        assert_eq!(library_target(&targets).unwrap().crate_name(), "foo");
+        assert_eq!(
+            library_target(&targets[..1]).unwrap().crate_name(),
+            "foo_derive"
+        );
+        assert_eq!(
+            library_target(&[targets[0].clone(), targets[2].clone()])
+                .unwrap()
+                .crate_name(),
+            "foo_ffi"
+        );
+        assert!(library_target(&[]).is_none());
+    }
+
+    #[test]
+    fn proc_macro_exclusion() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["async-trait"]
+            exclude-proc-macros = true
+            "#,
+        )
+        .unwrap();
+        let lib = |name: &str, crate_types| {
+            Target::lib_target(
+                name,
+                crate_types,
+                "/src/lib.rs".into(),
+                Edition::Edition2018,
+            )
+        };
+        let excluded = |name: &str, crate_types| {
+            let name = InternedString::new(name);
+            modifications.excludes_proc_macro(name, &lib(&name, crate_types))
+        };
+
+        assert!(excluded("serde_derive", vec![CrateType::ProcMacro]));
+        assert!(!excluded("serde", vec![CrateType::Lib]));
+        assert!(!excluded("async-trait", vec![CrateType::ProcMacro]));
+        assert!(!Modifications::default().excludes_proc_macro(
+            InternedString::new("serde_derive"),
+            &lib("serde_derive", vec![CrateType::ProcMacro])
+        ));
+    }
+
+    #[test]
+    fn all_features_without_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "featureless"
+            version = "1.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [package.metadata.playground]
+            all-features = true
+            default-features = false
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let (features, default_features, all_features) =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        assert!(features.is_empty());
+        assert!(!default_features);
+        assert!(all_features);
+        assert_eq!(diagnostics.count(Severity::Note), 1);
+        assert!(!diagnostics.has_warnings());
+    }
+
+    #[test]
+    fn default_features_opt_out_without_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "no-std"
+            version = "1.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [features]
+            default = ["std"]
+            std = []
+
+            [package.metadata.playground]
+            default-features = false
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let features = playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics);
+        assert_eq!(features.unwrap(), Some((BTreeSet::new(), false, false)));
+        assert_eq!(diagnostics.count(Severity::Note), 0);
+    }
+
+    #[test]
+    fn all_features_except() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "backends"
+            version = "1.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [features]
+            std = []
+            backend-a = []
+            backend-b = []
+
+            [package.metadata.playground]
+            all-features = true
+            all-features-except = ["backend-b"]
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let (features, _, _) =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            features,
+            BTreeSet::from([InternedString::new("backend-a"), InternedString::new("std")])
+        );
+    }
+
+    #[test]
+    fn unknown_metadata_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "renamed"
+            version = "2.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [features]
+            std = []
+            serde1 = []
+
+            [package.metadata.playground]
+            features = ["std", "serde"]
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let (features, _, _) =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        assert_eq!(features, BTreeSet::from([InternedString::new("std")]));
+
+        let warnings = diagnostics
+            .iter()
+            .map(|d| &d.message[..])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            ["Playground metadata enables feature `serde`, which the crate does not have"]
+        );
+    }
+
+    #[test]
+    fn curated_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "serde"
+            version = "1.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [features]
+            default = ["std"]
+            std = []
+            derive = []
+            rc = []
+
+            [package.metadata.playground]
+            features = ["rc"]
+            blacklist-features = ["derive"]
+            "#,
+        );
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [features]
+            serde = ["derive", "unstable"]
+            "#,
+        )
+        .unwrap();
+
+        let mut diagnostics = Diagnostics::new();
+        let features = playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+            .unwrap()
+            .map(|(features, default_features, _)| (features, default_features));
+        let (features, default_features) =
+            with_curated_features(&pkg, features, &modifications, &mut diagnostics).unwrap();
+        assert_eq!(
+            features,
+            BTreeSet::from([InternedString::new("derive"), InternedString::new("rc")])
+        );
+        assert!(default_features);
+
+        let warnings = diagnostics
+            .iter()
+            .map(|d| &d.message[..])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            ["crate-modifications.toml enables feature `unstable`, which the crate does not have"]
+        );
+
+        let (features, default_features) =
+            with_curated_features(&pkg, None, &modifications, &mut Diagnostics::new()).unwrap();
+        assert_eq!(features, BTreeSet::from([InternedString::new("derive")]));
+        assert!(default_features);
+    }
+
+    #[test]
+    fn blacklisted_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "unstable"
+            version = "1.0.0"
+
+            [lib]
+            path = "lib.rs"
+
+            [features]
+            default = ["std", "nightly"]
+            std = []
+            nightly = []
+            simd = []
+            serde = []
+
+            [package.metadata.playground]
+            all-features = true
+            blacklist-features = ["simd"]
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let blacklist = BTreeSet::from([InternedString::new("nightly")]);
+        let (features, default_features, _) =
+            playground_metadata_features(&pkg, &blacklist, &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        let expected = ["serde", "std"].map(InternedString::new);
+        assert_eq!(features, BTreeSet::from(expected));
+        assert!(!default_features);
+    }
+
+    #[test]
+    fn namespaced_features() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "chrono"
+            version = "0.4.23"
+
+            [lib]
+            path = "lib.rs"
+
+            [dependencies]
+            serde = { version = "1", optional = true }
+            rkyv = { version = "0.7", optional = true }
+            time = "0.1"
+
+            [features]
+            default = ["clock", "time/std"]
+            clock = []
+            serde = ["dep:serde"]
+            archive = ["dep:rkyv"]
+            unstable = ["serde?/unstable"]
+
+            [package.metadata.playground]
+            features = ["serde", "dep:serde", "serde?/derive", "time/std", "dep:rkyv", "missing/std"]
+            blacklist-features = ["clock"]
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let (features, default_features, _) =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        let expected = ["serde", "serde?/derive", "time/std"].map(InternedString::new);
+        assert_eq!(features, BTreeSet::from(expected));
+        assert!(!default_features);
+
+        let warnings = diagnostics
+            .iter()
+            .map(|d| &d.message[..])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                "Playground metadata enables feature `dep:rkyv`, but the optional dependency \
+                 `rkyv` is only enabled through the crate's features",
+                "Playground metadata enables feature `missing/std`, but the crate has no \
+                 dependency `missing`",
+            ]
+        );
+
+        let blacklist = BTreeSet::from([InternedString::new("serde")]);
+        assert!(enables_blacklisted(
+            &FeatureValue::new("dep:serde".into()),
+            &blacklist
+        ));
+        assert!(enables_blacklisted(
+            &FeatureValue::new("serde/derive".into()),
+            &blacklist
+        ));
+        assert!(!enables_blacklisted(
+            &FeatureValue::new("serde?/derive".into()),
+            &blacklist
+        ));
+    }
+
+    #[test]
+    fn optional_dependencies() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "chrono"
+            version = "0.4.23"
+
+            [lib]
+            path = "lib.rs"
+
+            [dependencies]
+            serde = { version = "1", optional = true }
+            rkyv = { version = "0.7", optional = true }
+            pure-rust-locales = { version = "0.5", optional = true }
+            time = "0.1"
+
+            [features]
+            std = []
+            serde = ["dep:serde", "std"]
+            unstable-locales = ["dep:pure-rust-locales"]
+
+            [package.metadata.playground]
+            features = ["std"]
+            enable-optional-deps = true
+            "#,
+        );
+
+        let expected = ["rkyv", "serde"].map(InternedString::new);
+        assert_eq!(
+            optional_dependency_features(pkg.summary()),
+            BTreeSet::from(expected)
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let (features, _, _) =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics)
+                .unwrap()
+                .unwrap();
+        let expected = ["rkyv", "serde", "std"].map(InternedString::new);
+        assert_eq!(features, BTreeSet::from(expected));
+
+        let modifications: Modifications = toml::from_str(
+            r#"
+            enable-optional-deps = ["chrono"]
+            blacklist-features = ["rkyv"]
+            "#,
+        )
+        .unwrap();
+        let (features, default_features) =
+            with_curated_features(&pkg, None, &modifications, &mut diagnostics).unwrap();
+        assert_eq!(features, BTreeSet::from([InternedString::new("serde")]));
+        assert!(default_features);
+    }
+
+    #[test]
+    fn unparseable_metadata() {
+        let pkg = package(
+            r#"
+            [package]
+            name = "typo"
+            version = "0.3.1"
+
+            [lib]
+            path = "lib.rs"
+
+            [package.metadata.playground]
+            features = "std"
+            "#,
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let error =
+            playground_metadata_features(&pkg, &BTreeSet::new(), &mut diagnostics).unwrap_err();
+        assert_eq!(error.name, "typo");
+        assert_eq!(error.version, Version::new(0, 3, 1));
+        assert_eq!(error.metadata.trim(), r#"features = "std""#);
+        assert!(error
+            .to_string()
+            .starts_with("Unable to parse the playground metadata of typo 0.3.1: "));
+    }
+
+    #[test]
+    fn rust_versions() {
+        assert_eq!(parse_rust_version("1.65"), Ok(Version::new(1, 65, 0)));
+        assert_eq!(parse_rust_version("1.70.1"), Ok(Version::new(1, 70, 1)));
+        assert_eq!(parse_rust_version("2"), Ok(Version::new(2, 0, 0)));
+        assert!(parse_rust_version("1.65-nightly").is_err());
+        assert!(parse_rust_version("1.2.3.4").is_err());
+        assert!(parse_rust_version("").is_err());
+    }
+
+    #[test]
+    fn msrv_exclusions_by_version() {
+        let krate = |name: &str, rust_version: Option<&str>| ResolvedCrate {
+            name: name.to_owned(),
+            version: Version::new(1, 0, 0),
+            build_only: false,
+            rust_version: rust_version.map(str::to_owned),
+        };
+        let crates = [
+            krate("old", Some("1.56")),
+            krate("new", Some("1.70")),
+            krate("unknown", None),
+        ];
+
+        let excluded = |rust_version| {
+            msrv_exclusions(&crates, &parse_rust_version(rust_version).unwrap())
+                .into_iter()
+                .map(|krate| krate.name.as_str())
+                .collect_vec()
+        };
+        assert_eq!(excluded("1.56"), ["new"]);
+        assert_eq!(excluded("1.70"), Vec::<&str>::new());
+        assert_eq!(excluded("1.50"), ["old", "new"]);
+    }
+
+    #[test]
+    fn pages_are_collected_until_enough_crates() {
+        let pages = [vec!["a", "b", "c"], vec!["c", "d", "e"], vec!["f"]];
+        let collect = |count| {
+            let mut requested = Vec::new();
+            let crates = collect_pages(count, |page| {
+                requested.push(page);
+                let crates = pages
+                    .get(page as usize - 1)
+                    .into_iter()
+                    .flatten()
+                    .map(|&name| Crate::named(name.into()))
+                    .collect();
+                Ok::<_, ()>(crates)
+            })
+            .unwrap();
+            let names = crates.iter().map(|krate| krate.name.as_str()).collect_vec();
+            (names.join(" "), requested)
+        };
+
+        assert_eq!(collect(2), ("a b".to_owned(), vec![1]));
+        assert_eq!(collect(4), ("a b c d".to_owned(), vec![1, 2]));
+        assert_eq!(collect(100), ("a b c d e f".to_owned(), vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn lockfile_has_a_root_package() {
+        let cargo_resolve = r#"
+            version = 3
+
+            [[package]]
+            name = "rand"
+            version = "0.7.3"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "aaaa"
+
+            [[package]]
+            name = "rand"
+            version = "0.8.5"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "bbbb"
+            dependencies = ["libc"]
+
+            [[package]]
+            name = "libc"
+            version = "0.2.139"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "cccc"
+        "#;
+        let dependencies = BTreeMap::from([
+            ("libc".to_owned(), spec("", "0.2.139")),
+            ("rand".to_owned(), spec("", "0.8.5")),
+            ("rand_0_7".to_owned(), spec("rand", "0.7.3")),
+        ]);
+
+        let lockfile =
+            playground_lockfile(cargo_resolve, "playground", "0.0.1", &dependencies).unwrap();
+        assert!(lockfile.starts_with("# This file is automatically @generated by Cargo.\n"));
+
+        let lockfile: toml::Value = toml::from_str(&lockfile).unwrap();
+        let packages = lockfile["package"].as_array().unwrap();
+        let names = packages
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect_vec();
+        assert_eq!(names, ["libc", "playground", "rand", "rand"]);
+
+        let root = &packages[1];
+        assert_eq!(root["version"].as_str(), Some("0.0.1"));
+        assert!(root.get("source").is_none());
+        let root_dependencies = root["dependencies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d.as_str().unwrap())
+            .collect_vec();
+        assert_eq!(root_dependencies, ["libc", "rand 0.7.3", "rand 0.8.5"]);
+    }
+
+    #[test]
+    fn missing_additions_are_reported() {
+        let modifications = Modifications {
+            exclusions: vec![Exclusion::Name("excluded".into())],
+            additions: ["present", "excluded", "dropped"]
+                .iter()
+                .map(|&n| (n.into(), None))
+                .collect(),
+            ..Modifications::default()
+        };
+        let dependencies = BTreeMap::from([("present".to_owned(), spec("present", "1.0.0"))]);
+
+        let mut diagnostics = Diagnostics::new();
+        verify_additions(&modifications, &dependencies, false, &mut diagnostics);
+
+        let krates = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.krate.as_deref().unwrap()))
+            .collect_vec();
+        assert_eq!(
+            krates,
+            [
+                (Severity::Warning, "dropped"),
+                (Severity::Warning, "excluded")
+            ]
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        verify_additions(&modifications, &dependencies, true, &mut diagnostics);
+        assert_eq!(diagnostics.count(Severity::Error), 2);
+    }
+
+    #[test]
+    fn duplicate_versions_can_share_features() {
+        let crates = resolved_crates(vec![
+            resolved_dep("foo", "2.0.0", &["a"], &["a", "b"]),
+            resolved_dep("foo", "1.0.0", &["b", "c"], &["b", "c"]),
+        ]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(dependencies["foo"].features, ["a".into()].into());
+        assert_eq!(
+            dependencies["foo_1"].features,
+            ["b".into(), "c".into()].into()
+        );
+
+        let options = GenerateOptions {
+            unify_duplicate_features: true,
+            ..GenerateOptions::default()
+        };
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(
+            dependencies["foo"].features,
+            ["a".into(), "b".into()].into()
+        );
+        assert_eq!(
+            dependencies["foo_1"].features,
+            ["b".into(), "c".into()].into()
+        );
+
+        assert_eq!(diagnostics.count(Severity::Warning), 2);
+    }
+
+    #[test]
+    fn default_feature_can_be_kept() {
+        let crates = resolved_crates(vec![resolved_dep(
+            "foo",
+            "1.0.0",
+            &["default", "std"],
+            &["default", "std"],
+        )]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(dependencies["foo"].features, ["std".into()].into());
+        assert!(dependencies["foo"].default_features);
+
+        let options = GenerateOptions {
+            keep_default_feature: true,
+            ..GenerateOptions::default()
+        };
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(
+            dependencies["foo"].features,
+            ["default".into(), "std".into()].into()
+        );
+        assert!(!dependencies["foo"].default_features);
+    }
+
+    #[test]
+    fn minimal_features() {
+        let crates = resolved_crates(vec![
+            resolved_dep("foo", "1.0.0", &["default", "std"], &["default", "std"]),
+            resolved_dep("bar", "0.1.0", &[], &[]),
+        ]);
+
+        let options = GenerateOptions {
+            minimal_features: true,
+            ..GenerateOptions::default()
+        };
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        for spec in dependencies.values() {
+            assert!(spec.features.is_empty());
+            assert!(!spec.default_features);
+        }
+    }
+
+    #[test]
+    fn equal_versions_are_selected_deterministically() {
+        let candidate = |version: &str, checksum: &str| Candidate {
+            version: version.parse().unwrap(),
+            checksum: Some(checksum.to_owned()),
+        };
+        let candidates = vec![
+            candidate("1.0.0", "aaaa"),
+            candidate("1.1.0", "bbbb"),
+            candidate("1.1.0", "cccc"),
+            candidate("2.0.0-rc.1", "dddd"),
+        ];
+
+        let forward = newest_viable(candidates.clone(), None, false).unwrap();
+        let reversed = newest_viable(candidates.into_iter().rev().collect(), None, false).unwrap();
+
+        assert_eq!(forward, candidate("1.1.0", "cccc"));
+        assert_eq!(reversed, forward);
+    }
+
+    #[test]
+    fn crate_information_from_manifests() {
+        let mut rand = resolved_dep(
+            "rand",
+            "0.8.5",
+            &["std", "small_rng"],
+            &["std", "small_rng"],
+        );
+        rand.description = Some("Random number generators".to_owned());
+        rand.license = Some("MIT OR Apache-2.0".to_owned());
+        let crates = resolved_crates(vec![rand, resolved_dep("rand", "0.7.3", &[], &[])]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        let infos = generate_crate_information(
+            &dependencies,
+            &crates,
+            &BTreeMap::new(),
+            options.version_format,
+        );
+
+        assert_eq!(infos[0].id, "rand");
+        assert_eq!(
+            infos[0].description.as_deref(),
+            Some("Random number generators")
+        );
+        assert_eq!(infos[0].license.as_deref(), Some("MIT OR Apache-2.0"));
+        assert_eq!(infos[0].repository, None);
+        assert_eq!(infos[0].features, ["small_rng", "std"]);
+        assert!(infos[0].default_features);
+        assert_eq!(infos[1].id, "rand_0_7");
+        assert_eq!(infos[1].description, None);
+    }
+
+    #[test]
+    fn manifest_and_information_agree() {
+        let crates = resolved_crates(vec![
+            resolved_dep("rand", "0.8.5", &[], &[]),
+            resolved_dep("regex", "1.7.0", &[], &[]),
+        ]);
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let mut dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        let mut infos = generate_crate_information(
+            &dependencies,
+            &crates,
+            &BTreeMap::new(),
+            options.version_format,
+        );
+        assert!(check_consistency(&dependencies, &infos).is_ok());
+
+        dependencies.remove("regex");
+        infos[0].version = "0.8.4".parse().unwrap();
+        match check_consistency(&dependencies, &infos) {
+            Err(TopCratesError::Inconsistent { mismatches }) => assert_eq!(
+                mismatches,
+                [
+                    "`rand` is rand 0.8.5 in Cargo.toml but rand 0.8.4 in crate-information.json",
+                    "`regex` is only in crate-information.json",
+                ]
+            ),
+            other => panic!("expected the check to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sanitized_crate_information() {
+        let info = |description: &str| CrateInformation {
+            name: "ansi_term".to_owned(),
+            version: Version::new(0, 12, 1),
+            id: "ansi_term".to_owned(),
+            origin: None,
+            created_at: None,
+            updated_at: None,
+            description: Some(description.to_owned()),
+            repository: None,
+            license: Some("MIT".to_owned()),
+            edition: None,
+            features: vec![],
+            default_features: true,
+        };
+        let mut infos = [
+            info("Library for ANSI terminal colours\nand styles (bold,\tunderline)\u{1b}[0m"),
+            info("  Already tidy  \n"),
+        ];
+
+        let mut diagnostics = Diagnostics::new();
+        sanitize_crate_information(&mut infos, &mut diagnostics);
+        assert_eq!(
+            infos[0].description.as_deref(),
+            Some("Library for ANSI terminal colours and styles (bold, underline)[0m")
+        );
+        assert_eq!(infos[1].description.as_deref(), Some("Already tidy"));
+        assert_eq!(infos[0].license.as_deref(), Some("MIT"));
+        assert_eq!(diagnostics.count(Severity::Warning), 1);
+    }
+
+    #[test]
+    fn crate_information_repository() {
+        let mut rand = resolved_dep("rand", "0.8.5", &[], &[]);
+        rand.repository = repository_url(Some(" https://github.com/rust-random/rand "));
+        let mut serde = resolved_dep("serde", "1.0.150", &[], &[]);
+        serde.repository = repository_url(Some(""));
+        let crates = resolved_crates(vec![rand, serde]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        let infos = generate_crate_information(
+            &dependencies,
+            &crates,
+            &BTreeMap::new(),
+            options.version_format,
+        );
+
+        assert_eq!(
+            infos[0].repository.as_deref(),
+            Some("https://github.com/rust-random/rand")
+        );
+        assert_eq!(infos[1].repository, None);
+        let json = serde_json::to_value(&infos).unwrap();
+        assert!(json[1].get("repository").is_none());
+    }
+
+    #[test]
+    fn crate_information_is_stable() {
+        let deps = || {
+            vec![
+                resolved_dep("foo_bar", "1.0.0", &[], &[]),
+                resolved_dep("foo-baz", "1.0.0", &["std"], &["std"]),
+                resolved_dep("rand", "0.7.3", &[], &[]),
+                resolved_dep("rand", "0.8.5", &[], &[]),
+            ]
+        };
+        let generate = |deps: Vec<ResolvedDep>| {
+            let crates = resolved_crates(deps);
+            let options = GenerateOptions::default();
+            let mut diagnostics = Diagnostics::new();
+            let dependencies =
+                generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+            let infos = generate_crate_information(
+                &dependencies,
+                &crates,
+                &BTreeMap::new(),
+                options.version_format,
+            );
+            serde_json::to_string_pretty(&infos).unwrap()
+        };
+
+        let first = generate(deps());
+        let second = generate(deps().into_iter().rev().collect());
+        assert_eq!(first, second);
+
+        let infos: Vec<serde_json::Value> = serde_json::from_str(&first).unwrap();
+        let order = infos
+            .iter()
+            .map(|info| info["id"].as_str().unwrap())
+            .collect_vec();
+        assert_eq!(order, ["foo_baz", "foo_bar", "rand", "rand_0_7"]);
+    }
+
+    #[test]
+    fn crate_information_includes_edition() {
+        let crates = resolved_crates(vec![resolved_dep("rand", "0.8.5", &[], &[])]);
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        let infos = generate_crate_information(
+            &dependencies,
+            &crates,
+            &BTreeMap::new(),
+            options.version_format,
+        );
+
+        let json = serde_json::to_value(&infos).unwrap();
+        assert_eq!(json[0]["edition"], "2018");
+    }
+
+    #[test]
+    fn crate_information_matches_its_schema() {
+        let schema: serde_json::Value = serde_json::from_str(CRATE_INFORMATION_SCHEMA).unwrap();
+        let keys = |value: &serde_json::Value| -> BTreeSet<String> {
+            value.as_object().unwrap().keys().cloned().collect()
+        };
+
+        // Fields that are always written must be required.
+        let bare = CrateInformation {
+            name: "rand".to_owned(),
+            version: Version::new(0, 8, 5),
+            id: "rand".to_owned(),
+            origin: None,
+            created_at: None,
+            updated_at: None,
+            description: None,
+            repository: None,
+            license: None,
+            edition: None,
+            features: vec!["small_rng".to_owned()],
+            default_features: true,
+        };
+        let required: BTreeSet<String> = schema["items"]["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(keys(&serde_json::to_value(&bare).unwrap()), required);
+
+        // Every field is filled in, so that one added without being
+        // described in the schema is noticed.
+        let info = CrateInformation {
+            origin: Some(CrateOrigin::Top),
+            created_at: Some("2015-02-03T06:17:14.169972+00:00".to_owned()),
+            updated_at: Some("2022-02-14T00:26:26.239277+00:00".to_owned()),
+            description: Some("Random number generators".to_owned()),
+            repository: Some("https://github.com/rust-random/rand".to_owned()),
+            license: Some("MIT OR Apache-2.0".to_owned()),
+            edition: Some("2018".to_owned()),
+            ..bare
+        };
+        assert_eq!(
+            keys(&serde_json::to_value(&info).unwrap()),
+            keys(&schema["items"]["properties"])
+        );
+        assert_eq!(validate_crate_information(&[info]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn hand_edited_crate_information() {
+        let value = serde_json::json!([
+            { "name": "rand", "version": "0.8.5", "id": "rand", "features": [], "default_features": true },
+            { "name": "rand", "version": "0.7.3-01", "id": "rand", "features": [], "default_features": true },
+            { "name": "serde-json", "version": "1.0.0", "id": "serde-json", "features": [], "default_features": true },
+            { "name": "log", "id": "log", "features": [], "default_features": true },
+        ]);
+
+        let errors = check_crate_information(&value);
+
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert_eq!(errors[0], "/3: missing required field `version`");
+        assert_eq!(errors[1], "/1 (rand): id `rand` is also used by /0");
+        assert!(errors[2].starts_with("/1 (rand): version `0.7.3-01` is not semver"));
+        assert_eq!(
+            errors[3],
+            "/2 (serde-json): id `serde-json` is not a Rust identifier; `serde_json` would be"
+        );
+    }
+
+    #[test]
+    fn listing_queries_are_encoded() {
+        assert_eq!(
+            most_downloaded_with("keyword", "c++"),
+            "keyword=c%2B%2B&sort=downloads"
+        );
+        assert_eq!(
+            most_downloaded_with("category", "web-programming::http-server"),
+            "category=web-programming%3A%3Ahttp-server&sort=downloads"
+        );
+    }
+
+    #[test]
+    fn index_entry_paths() {
+        assert_eq!(index_entry_path("a"), "1/a");
+        assert_eq!(index_entry_path("cc"), "2/cc");
+        assert_eq!(index_entry_path("syn"), "3/s/syn");
+        assert_eq!(index_entry_path("Serde"), "se/rd/serde");
+    }
+
+    #[test]
+    fn duplicate_names_use_compatible_versions() {
+        let crates = resolved_crates(vec![
+            resolved_dep("rand", "0.8.5", &[], &[]),
+            resolved_dep("rand", "0.7.3", &[], &[]),
+            resolved_dep("rand", "0.0.4", &[], &[]),
+            resolved_dep("syn", "2.0.1", &[], &[]),
+            resolved_dep("syn", "1.0.109", &[], &[]),
+        ]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(
+            dependencies.keys().collect_vec(),
+            ["rand", "rand_0_0_4", "rand_0_7", "syn", "syn_1"]
+        );
+    }
+
+    #[test]
+    fn renamed_versions() {
+        let crates = resolved_crates(vec![
+            resolved_dep("serde", "1.0.150", &[], &[]),
+            resolved_dep("serde", "0.9.15", &[], &[]),
+            resolved_dep("rand", "0.8.5", &[], &[]),
+        ]);
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [renames.serde]
+            "1" = "serde1"
+            "0.9" = "serde0"
+            "#,
+        )
+        .unwrap();
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &modifications.renames, &mut diagnostics);
+        assert_eq!(
+            dependencies.keys().collect_vec(),
+            ["rand", "serde0", "serde1"]
+        );
+        assert_eq!(dependencies["serde0"].version, Version::new(0, 9, 15));
+
+        let invalid = toml::from_str::<Modifications>(
+            r#"
+            [renames.serde]
+            "1" = "serde-1"
+            "#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn colliding_exposed_names() {
+        let crates = resolved_crates(vec![
+            resolved_dep("foo-bar", "1.0.0", &[], &[]),
+            resolved_dep("foo_bar", "2.0.0", &[], &[]),
+        ]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(dependencies["foo_bar"].package, "foo-bar");
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .collect_vec();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .krate
+            .as_ref()
+            .unwrap()
+            .starts_with("foo_bar v2.0.0"));
+        assert!(errors[0].message.contains("the same as foo-bar 1.0.0"));
+    }
+
+    #[test]
+    fn exposed_names_are_identifiers() {
+        let crates = resolved_crates(vec![
+            resolved_dep("3d", "0.2.0", &[], &[]),
+            resolved_dep("3d", "0.1.0", &[], &[]),
+            resolved_dep("serde", "1.0.0", &[], &[]),
+        ]);
+
+        let options = GenerateOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let dependencies =
+            generate_dependency_specs(&crates, &options, &BTreeMap::new(), &mut diagnostics);
+        assert_eq!(
+            dependencies.keys().collect_vec(),
+            ["_3d", "_3d_0_1", "serde"]
+        );
+        assert_eq!(dependencies["_3d"].package, "3d");
+        assert_eq!(diagnostics.count(Severity::Warning), 2);
+
+        assert_eq!(rust_identifier("foo_bar"), None);
+        assert_eq!(rust_identifier("foo-bar").as_deref(), Some("foo_bar"));
+    }
+
+    #[test]
+    fn viable_candidates_newest_first() {
+        let candidates = ["1.0.0", "1.2.0", "0.9.0", "2.0.0-rc.1"]
+            .iter()
+            .map(|version| Candidate {
+                version: version.parse().unwrap(),
+                checksum: None,
+            })
+            .collect_vec();
+
+        let versions = viable_candidates(candidates, None, false)
+            .into_iter()
+            .map(|c| c.version.to_string())
+            .collect_vec();
+        assert_eq!(versions, ["1.2.0", "1.0.0", "0.9.0"]);
+
+        let msrv = Version::new(1, 60, 0);
+        assert!(requires_newer_rust(Some("1.65"), &msrv));
+        assert!(!requires_newer_rust(Some("1.60"), &msrv));
+        assert!(!requires_newer_rust(None, &msrv));
+    }
+
+    #[test]
+    fn outdated_crates() {
+        let candidates = |versions: &[&str]| {
+            versions
+                .iter()
+                .map(|version| Candidate {
+                    version: version.parse().unwrap(),
+                    checksum: None,
+                })
+                .collect_vec()
+        };
+        let current = [
+            ("rand", &["0.8.4"][..]),
+            ("regex", &["1.7.0"]),
+            ("syn", &["1.0.100", "2.0.1"]),
+            ("tokio", &["1.0.0"]),
+        ]
+        .iter()
+        .map(|&(name, versions)| {
+            let versions = versions.iter().map(|v| v.parse().unwrap()).collect();
+            (name.to_owned(), versions)
+        })
+        .collect();
+        let published = BTreeMap::from([
+            ("rand".into(), candidates(&["0.7.3", "0.8.4", "0.8.5"])),
+            ("regex".into(), candidates(&["1.7.0", "2.0.0-alpha.1"])),
+            ("syn".into(), candidates(&["1.0.100", "1.0.109", "2.0.1"])),
+        ]);
+
+        let outdated = newer_versions(&current, published);
+
+        assert_eq!(
+            outdated,
+            [
+                OutdatedCrate {
+                    name: "rand".to_owned(),
+                    current: Version::new(0, 8, 4),
+                    latest: Version::new(0, 8, 5),
+                },
+                OutdatedCrate {
+                    name: "syn".to_owned(),
+                    current: Version::new(1, 0, 100),
+                    latest: Version::new(1, 0, 109),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prereleases_only_when_allowed() {
+        let candidates = |versions: &[&str]| {
+            versions
+                .iter()
+                .map(|version| Candidate {
+                    version: version.parse().unwrap(),
+                    checksum: None,
+                })
+                .collect_vec()
+        };
+        let newest = |versions, allow_prerelease| {
+            newest_viable(candidates(versions), None, allow_prerelease)
+                .map(|c| c.version.to_string())
+        };
+
+        let versions = &["0.9.0", "1.0.0-rc.1"];
+        assert_eq!(newest(versions, false).as_deref(), Some("0.9.0"));
+        assert_eq!(newest(versions, true).as_deref(), Some("1.0.0-rc.1"));
+
+        let versions = &["1.0.0-rc.1"];
+        assert_eq!(newest(versions, false), None);
+        assert_eq!(newest(versions, true).as_deref(), Some("1.0.0-rc.1"));
+    }
+
+    #[test]
+    fn crates_without_viable_versions_are_left_out() {
+        let candidates = |versions: &[&str]| {
+            versions
+                .iter()
+                .map(|version| Candidate {
+                    version: version.parse().unwrap(),
+                    checksum: None,
+                })
+                .collect_vec()
+        };
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [pinned]
+            serde = "=3.0.0"
+            "#,
+        )
+        .unwrap();
+        let queried = BTreeMap::from([
+            ("rand".into(), candidates(&["0.8.5"])),
+            ("serde".into(), candidates(&["1.0.150"])),
+            ("unreleased".into(), candidates(&["0.1.0-alpha.1"])),
+        ]);
+
+        let mut diagnostics = Diagnostics::new();
+        let choices = choose_candidates(
+            queried.clone(),
+            &modifications,
+            &BTreeMap::new(),
+            false,
+            &mut diagnostics,
+        );
+
+        assert_eq!(choices.keys().map(|n| n.as_str()).collect_vec(), ["rand"]);
+        let krates = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.krate.as_deref().unwrap()))
+            .collect_vec();
+        assert_eq!(
+            krates,
+            [
+                (Severity::Warning, "serde"),
+                (Severity::Warning, "unreleased")
+            ]
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let choices = choose_candidates(
+            queried,
+            &modifications,
+            &BTreeMap::new(),
+            true,
+            &mut diagnostics,
+        );
+        assert_eq!(
+            choices["unreleased"][0].version.to_string(),
+            "0.1.0-alpha.1"
+        );
+    }
+
+    #[test]
+    fn blocked_versions_are_skipped() {
+        let candidates = |versions: &[&str]| {
+            versions
+                .iter()
+                .map(|version| Candidate {
+                    version: version.parse().unwrap(),
+                    checksum: None,
+                })
+                .collect_vec()
+        };
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [blocked-versions]
+            rand = ["=0.8.5", ">=0.8.6"]
+            serde = ["1"]
+            "#,
+        )
+        .unwrap();
+        let queried = BTreeMap::from([
+            (
+                "rand".into(),
+                candidates(&["0.8.4", "0.8.6", "0.8.5", "0.7.3"]),
+            ),
+            ("serde".into(), candidates(&["1.0.150", "1.0.151"])),
+        ]);
+
+        let mut diagnostics = Diagnostics::new();
+        let choices = choose_candidates(
+            queried,
+            &modifications,
+            &BTreeMap::new(),
+            false,
+            &mut diagnostics,
+        );
+
+        assert_eq!(choices["rand"][0].version.to_string(), "0.8.4");
+        assert_eq!(choices["serde"][0].version.to_string(), "1.0.151");
+        let krates = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.krate.as_deref().unwrap()))
+            .collect_vec();
+        assert_eq!(
+            krates,
+            [(Severity::Note, "rand"), (Severity::Warning, "serde")]
+        );
+        let skipped = diagnostics.iter().next().unwrap();
+        assert_eq!(skipped.message, "Skipping blocked versions 0.8.6, 0.8.5");
+    }
+
+    #[test]
+    fn pins_hold_back_versions() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [pinned]
+            rand = "~1.0"
+            serde = "=3.0.0"
+            "#,
+        )
+        .unwrap();
+        let candidates = ["1.0.0", "1.0.1", "1.1.0", "2.0.0"]
+            .iter()
+            .map(|version| Candidate {
+                version: version.parse().unwrap(),
+                checksum: None,
+            })
+            .collect_vec();
+
+        let newest = |name: &str| {
+            let pin = modifications.pinned.get(&InternedString::new(name));
+            newest_viable(candidates.clone(), pin, false).map(|c| c.version.to_string())
+        };
+        assert_eq!(newest("rand").as_deref(), Some("1.0.1"));
+        assert_eq!(newest("serde"), None);
+        assert_eq!(newest("libc").as_deref(), Some("2.0.0"));
+    }
+
+    #[test]
+    fn pins_can_choose_prereleases() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [pinned]
+            foo = "=1.0.0-beta.2"
+            bar = "^1.0"
+            "#,
+        )
+        .unwrap();
+        let candidates = [
+            "0.9.0",
+            "1.0.0-beta.1",
+            "1.0.0-beta.2",
+            "1.0.0",
+            "1.1.0-rc.1",
+        ]
+        .iter()
+        .map(|version| Candidate {
+            version: version.parse().unwrap(),
+            checksum: None,
+        })
+        .collect_vec();
+
+        let newest = |name: &str| {
+            let pin = modifications.pinned.get(&InternedString::new(name));
+            newest_viable(candidates.clone(), pin, false).map(|c| c.version.to_string())
+        };
+        assert_eq!(newest("foo").as_deref(), Some("1.0.0-beta.2"));
+        assert_eq!(newest("bar").as_deref(), Some("1.0.0"));
+        assert_eq!(newest("baz").as_deref(), Some("1.0.0"));
+    }
+
+    #[test]
+    fn exclusion_patterns() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            exclusions = ["openssl"]
+            exclusion-patterns = ["^winapi", "-sys$"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(modifications.excluded("openssl"));
+        assert!(modifications.excluded("winapi-util"));
+        assert!(modifications.excluded("libz-sys"));
+        assert!(!modifications.excluded("sysinfo"));
+    }
+
+    #[test]
+    fn glob_exclusions() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            exclusions = ["windows-sys*", "winapi-*", "open?sl", "libz", "[!a-z]*"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(modifications.excluded("windows-sys"));
+        assert!(modifications.excluded("winapi-util"));
+        assert!(!modifications.excluded("winapi"));
+        assert!(modifications.excluded("openssl"));
+        assert!(modifications.excluded("1password"));
+        assert!(!modifications.excluded("libz-sys"));
+
+        // Names are compared the way cargo does, but patterns are
+        // matched against the name as it is.
+        assert!(modifications.excluded("LIBZ"));
+        assert!(!modifications.excluded("winapi_util"));
+
+        let invalid = toml::from_str::<Modifications>(r#"exclusions = ["windows-[sys"]"#);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn misspelled_modifications() {
+        let err = toml::from_str::<Modifications>("exclusion = [\"openssl\"]")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `exclusion`"), "{}", err);
+
+        let err = toml::from_str::<Modifications>("[profile.dev]\ncodegen-unit = 4")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown field `codegen-unit`"), "{}", err);
+
+        let modifications = toml::from_str::<Modifications>("").unwrap();
+        assert!(modifications.exclusions.is_empty());
+        assert_eq!(modifications.max_crates, None);
+    }
+
+    #[test]
+    fn merged_modifications() {
+        let base: Modifications = toml::from_str(
+            r#"
+            exclusions = ["openssl"]
+            additions = ["serde"]
+            blacklist-features = ["nightly"]
+            min-downloads = 1000
+            keywords = ["cli"]
+            [pinned]
+            rand = "0.7"
+            [features]
+            tokio = ["full"]
+            "#,
+        )
+        .unwrap();
+        let overlay: Modifications = toml::from_str(
+            r#"
+            exclusions = ["openssl", "libz-sys"]
+            additions = ["internal-utils"]
+            blacklist-features = ["simd"]
+            keywords = ["async", "cli"]
+            keyword-count = 5
+            [pinned]
+            rand = "0.8"
+            [features]
+            tokio = ["tracing"]
+            "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(overlay);
+
+        assert_eq!(
+            merged.exclusions,
+            [
+                InternedString::new("openssl"),
+                InternedString::new("libz-sys")
+            ]
+        );
+        assert!(merged.additions.contains_key("serde"));
+        assert!(merged.additions.contains_key("internal-utils"));
+        assert_eq!(merged.blacklist_features.len(), 2);
+        assert_eq!(merged.min_downloads, Some(1000));
+        assert_eq!(merged.pinned["rand"].to_string(), "^0.8");
+        assert_eq!(merged.features["tokio"].len(), 2);
+        assert_eq!(merged.keywords, ["cli", "async"]);
+        assert_eq!(merged.keyword_count, Some(5));
+    }
+
+    #[test]
+    fn locked_versions_that_changed() {
+        let config = Config::default().unwrap();
+        let crates_io = SourceId::crates_io(&config).unwrap();
+        let id = |name: &str, version: &str| PackageId::new(name, version, crates_io).unwrap();
+        let locked = BTreeSet::from([
+            id("libc", "0.2.138"),
+            id("syn", "1.0.107"),
+            id("syn", "2.0.0"),
+            id("memchr", "2.5.0"),
+        ]);
+        let resolved = [
+            id("libc", "0.2.139"),
+            id("syn", "2.0.0"),
+            id("memchr", "2.5.0"),
+            id("regex", "1.7.1"),
+        ];
+
+        let changed = changed_versions(&locked, resolved);
+        let libc = (vec![Version::new(0, 2, 138)], vec![Version::new(0, 2, 139)]);
+        assert_eq!(
+            changed,
+            BTreeMap::from([(InternedString::new("libc"), libc)])
+        );
+    }
+
+    #[test]
+    fn similarly_named_crates() {
+        let names = ["foo-bar", "serde", "foo_bar", "foo-bar", "Serde", "rand"];
+        let similar = similar_names(names.map(InternedString::new));
+        assert_eq!(
+            similar,
+            [
+                vec![InternedString::new("Serde"), InternedString::new("serde")],
+                vec![
+                    InternedString::new("foo-bar"),
+                    InternedString::new("foo_bar")
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_modifications() {
+        let modifications = || -> Modifications {
+            toml::from_str(
+                r#"
+                exclusions = ["packed_simd"]
+                additions = ["serde"]
+
+                [pinned]
+                rand = "0.8"
+
+                [channels.nightly]
+                exclusions = ["rustversion"]
+                additions = ["packed_simd_2"]
+
+                [channels.nightly.pinned]
+                rand = "0.9.0-alpha.1"
+
+                [channels.beta]
+                additions = ["tokio"]
+                "#,
+            )
+            .unwrap()
+        };
+
+        let nightly = modifications().for_channel(Some(Channel::Nightly));
+        assert_eq!(
+            nightly.exclusions,
+            ["packed_simd", "rustversion"].map(InternedString::new)
+        );
+        assert!(nightly.additions.contains_key("packed_simd_2"));
+        assert!(!nightly.additions.contains_key("tokio"));
+        assert_eq!(nightly.pinned["rand"].to_string(), "^0.9.0-alpha.1");
+        assert!(nightly.channels.is_empty());
+
+        let stable = modifications().for_channel(Some(Channel::Stable));
+        assert_eq!(stable.exclusions, [InternedString::new("packed_simd")]);
+        assert_eq!(stable.additions.len(), 1);
+        assert_eq!(stable.pinned["rand"].to_string(), "^0.8");
+
+        let unspecified = modifications().for_channel(None);
+        assert!(unspecified.channels.is_empty());
+        assert_eq!(unspecified.additions.len(), 1);
+
+        assert!("canary".parse::<Channel>().is_err());
+    }
+
+    #[test]
+    fn resolution_summary() {
+        let resolved = |name: &str, version: &str| ResolvedCrate {
+            name: name.to_owned(),
+            version: version.parse().unwrap(),
+            build_only: false,
+            rust_version: None,
+        };
+        let resolved_crates = [
+            resolved("rand", "0.7.3"),
+            resolved("rand", "0.8.5"),
+            resolved("regex", "1.7.0"),
+            resolved("syn", "1.0.107"),
+            resolved("syn", "2.0.0"),
+        ];
+
+        let summary = ResolutionSummary::new(3, 2, 6, &resolved_crates);
+        assert_eq!(summary.duplicated, ["rand", "syn"]);
+        assert_eq!(
+            summary.to_string(),
+            "2 top-level crates, 6 packages in total; \
+             2 crates are included at more than one version: rand, syn"
+        );
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn misspelled_additions() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["serde", "sred", "tokoi", "openssl"]
+            exclusions = ["openssl"]
+            "#,
+        )
+        .unwrap();
+        let candidate = Candidate {
+            version: Version::new(1, 0, 0),
+            checksum: None,
+        };
+        let queried = BTreeMap::from([
+            (InternedString::new("serde"), vec![candidate]),
+            (InternedString::new("sred"), vec![]),
+            (InternedString::new("tokoi"), vec![]),
+        ]);
+
+        assert_eq!(
+            unknown_additions(&modifications, &queried),
+            ["sred", "tokoi"]
+        );
+    }
+
+    #[test]
+    fn additions_with_versions() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["serde@1.0.150", "regex", "rand@~0.7"]
+            "#,
+        )
+        .unwrap();
+        let requirement = |name: &str| {
+            modifications.additions[&InternedString::new(name)]
+                .as_ref()
+                .map(VersionReq::to_string)
+        };
+        assert_eq!(requirement("serde").as_deref(), Some("^1.0.150"));
+        assert_eq!(requirement("regex"), None);
+        assert_eq!(requirement("rand").as_deref(), Some("~0.7"));
+
+        let mut top = TopCrates { crates: vec![] };
+        top.add_curated_crates(&modifications);
+        let rand = top.crates.iter().find(|k| k.name == "rand").unwrap();
+        assert_eq!(rand.requirement, modifications.additions[&rand.name]);
+
+        let err = toml::from_str::<Modifications>(r#"additions = ["serde@one"]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("invalid version requirement in addition `serde@one`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn crate_lists() {
+        let lines = [
+            "# from the pipeline",
+            "serde@1.0.150",
+            "",
+            "  rand  ",
+            "serde",
+        ]
+        .iter()
+        .map(|&line| line.to_owned())
+        .collect_vec();
+        let top = TopCrates::from_list(&lines).unwrap();
+        let crates = top
+            .crates
+            .iter()
+            .map(|krate| (krate.name.as_str(), krate.requirement.is_some()))
+            .collect_vec();
+        assert_eq!(crates, [("serde", true), ("rand", false)]);
+
+        let invalid = TopCrates::from_list(&["rand".to_owned(), "serde@one".to_owned()]);
+        assert!(matches!(
+            invalid,
+            Err(TopCratesError::InvalidCrateList { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn additions_already_listed_are_not_repeated() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["serde@1.0.150", "async-trait"]
+            "#,
+        )
+        .unwrap();
+        let mut top = TopCrates {
+            crates: vec![
+                Crate {
+                    downloads: Some(5000),
+                    ..Crate::named("serde".into())
+                },
+                Crate::named("rand".into()),
+            ],
+        };
+        top.add_curated_crates(&modifications);
+
+        let names = top.crates.iter().map(|c| c.name.as_str()).collect_vec();
+        assert_eq!(names, ["serde", "rand", "async-trait"]);
+        assert_eq!(top.crates[0].downloads, Some(5000));
+        assert_eq!(top.crates[0].requirement, Some("1.0.150".parse().unwrap()));
+    }
+
+    #[test]
+    fn redundant_additions_are_pruned() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["serde@1.0.150", "rand", "async-trait"]
+            "#,
+        )
+        .unwrap();
+        let top = TopCrates {
+            crates: vec![Crate::named("serde".into()), Crate::named("rand".into())],
+        };
+        let redundant = redundant_additions(&top, &modifications);
+        assert_eq!(redundant.iter().map(|n| n.as_str()).collect_vec(), ["rand"]);
+
+        let names = BTreeSet::from(["rand".to_owned()]);
+        let content = "\
+# Crates we want
+additions = [
+    \"rand\", # for examples
+    \"async-trait\",
+]
+
+[channels.nightly]
+additions = [\"rand\", \"serde@1\"]
+exclusions = [\"rand\"]
+";
+        assert_eq!(
+            without_additions(content, &names).unwrap(),
+            "\
+# Crates we want
+additions = [
+    \"async-trait\",
+]
+
+[channels.nightly]
+additions = [\"rand\", \"serde@1\"]
+exclusions = [\"rand\"]
+"
+        );
+    }
+
+    #[test]
+    fn restricted_to_some_crates() {
+        let mut top = TopCrates {
+            crates: vec![
+                Crate {
+                    downloads: Some(5000),
+                    ..Crate::named("serde_json".into())
+                },
+                Crate::named("rand".into()),
+                Crate::named("regex".into()),
+            ],
+        };
+        top.restrict_to(&["serde-json".to_owned(), "tokio".to_owned()]);
+
+        let names = top.crates.iter().map(|c| c.name.as_str()).collect_vec();
+        assert_eq!(names, ["serde_json", "tokio"]);
+        assert_eq!(top.crates[0].downloads, Some(5000));
+    }
+
+    #[test]
+    fn download_threshold() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["hand-picked"]
+            min-downloads = 1000
+            "#,
+        )
+        .unwrap();
+        let krate = |name: &str, downloads| Crate {
+            downloads,
+            ..Crate::named(InternedString::new(name))
+        };
+
+        assert!(modifications.too_few_downloads(&krate("obscure", Some(999))));
+        assert!(!modifications.too_few_downloads(&krate("popular", Some(1000))));
+        assert!(!modifications.too_few_downloads(&krate("hand-picked", Some(3))));
+        assert!(!modifications.too_few_downloads(&krate("cookbook", None)));
+    }
+
+    #[test]
+    fn pins_and_addition_requirements() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            additions = ["serde@1.0.150", "rand@0.7", "anyhow"]
+
+            [pinned]
+            rand = "0.8"
+            "#,
+        )
+        .unwrap();
+        let pin = |name| {
+            modifications
+                .pin(InternedString::new(name))
+                .map(|pin| pin.to_string())
+        };
+
+        assert_eq!(pin("serde").as_deref(), Some("^1.0.150"));
+        assert_eq!(pin("rand").as_deref(), Some("^0.8"));
+        assert_eq!(pin("anyhow"), None);
+        assert_eq!(pin("tokio"), None);
+    }
+
+    #[test]
+    fn profile_settings() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [profile.dev]
+            codegen-units = 16
+            incremental = true
+
+            [profile.release]
+            opt-level = 3
+            lto = true
+            "#,
+        )
+        .unwrap();
+
+        let dev = ProfileSettings {
+            codegen_units: Some(4),
+            ..ProfileSettings::default()
+        }
+        .or(&modifications.profile.dev);
+        assert_eq!(dev.codegen_units, Some(4));
+        assert_eq!(dev.incremental, Some(true));
+        assert_eq!(dev.opt_level, None);
+
+        let release = ProfileSettings {
+            opt_level: Some("s".to_owned()),
+            ..ProfileSettings::default()
+        }
+        .or(&modifications.profile.release);
+        assert_eq!(release.codegen_units, None);
+        assert_eq!(release.incremental, None);
+        assert_eq!(release.opt_level.as_deref(), Some("s"));
+        assert_eq!(release.lto, Some(true));
+
+        let err = toml::from_str::<Modifications>("profile.dev.opt-level = 4")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid opt-level `4`"), "{}", err);
+
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [profile]
+            omit = ["dev"]
+
+            [profile.test]
+            opt-level = 1
+            "#,
+        )
+        .unwrap();
+        let profile = ProfileModifications {
+            test: Some(ProfileSettings {
+                codegen_units: Some(4),
+                ..ProfileSettings::default()
+            }),
+            ..ProfileModifications::default()
+        }
+        .or(&modifications.profile);
+        let test = profile.test.unwrap();
+        assert_eq!(test.codegen_units, Some(4));
+        assert_eq!(test.opt_level.as_deref(), Some("1"));
+        assert!(profile.bench.is_none());
+        assert!(profile.omit.contains("dev"));
+    }
+
+    #[test]
+    fn package_settings() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            [package]
+            name = "sandbox"
+            version = "1.2.0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(modifications.package.name.as_deref(), Some("sandbox"));
+        assert_eq!(modifications.package.version, Some(Version::new(1, 2, 0)));
+        assert_eq!(modifications.package.authors, None);
+
+        let invalid = toml::from_str::<Modifications>("package.version = \"1.2\"");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn exclusions_ignore_hyphens_and_case() {
+        let modifications: Modifications = toml::from_str(
+            r#"
+            exclusions = ["foo_bar", "Baz-Qux"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(modifications.excluded("foo-bar"));
+        assert!(modifications.excluded("foo_bar"));
+        assert!(modifications.excluded("baz_qux"));
+        assert!(!modifications.excluded("foobar"));
+    }
+
+    #[test]
+    fn invalid_exclusion_pattern() {
+        let err = toml::from_str::<Modifications>(r#"exclusion-patterns = ["(unclosed"]"#)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid pattern `(unclosed`"), "{}", err);
+    }
+
+    #[test]
+    fn registry_urls() {
+        for url in [
+            "https://github.com/rust-lang/crates.io-index",
+            "sparse+https://index.crates.io/",
+            "file:///srv/mirror/index",
+        ] {
+            assert_eq!(parse_registry_url(url).as_deref(), Ok(url));
+        }
+        assert!(parse_registry_url("index.crates.io").is_err());
+        assert!(parse_registry_url("ftp://mirror.example/index").is_err());
+        assert!(parse_registry_url("https://").is_err());
+    }
+
+    #[test]
+    fn top_crates_sorts() {
+        for sort in [
+            "downloads",
+            "recent-downloads",
+            "recent-updates",
+            "new",
+            "alpha",
+        ] {
+            assert_eq!(sort.parse::<TopCratesSort>().unwrap().as_str(), sort);
+        }
+        assert_eq!(TopCratesSort::default(), TopCratesSort::Downloads);
+        assert!("relevance".parse::<TopCratesSort>().is_err());
+    }
+
+    #[test]
+    fn version_formats() {
+        let version = "1.2.3-rc.1+build.5".parse().unwrap();
+        assert_eq!(
+            VersionFormat::Raw.normalize(&version).to_string(),
+            "1.2.3-rc.1+build.5"
+        );
+        assert_eq!(
+            VersionFormat::Semver3.normalize(&version).to_string(),
+            "1.2.3-rc.1"
+        );
+    }
+
+    #[test]
+    fn packages_from_two_registries() {
+        let config = Config::default().unwrap();
+        let crates_io = SourceId::crates_io(&config).unwrap();
+        let mirror_url = "https://mirror.example.com/index".into_url().unwrap();
+        let mirror = SourceId::for_registry(&mirror_url).unwrap();
+        let package_ids = [
+            PackageId::new("rand", "0.8.5", crates_io).unwrap(),
+            PackageId::new("rand", "0.8.5", mirror).unwrap(),
+            PackageId::new("libc", "0.2.139", mirror).unwrap(),
+        ];
+
+        assert_eq!(
+            other_sources(&package_ids, crates_io),
+            BTreeSet::from([mirror])
+        );
+        assert_eq!(
+            other_sources(&package_ids, mirror),
+            BTreeSet::from([crates_io])
+        );
+        // The same version of the same crate is kept apart.
+        assert_ne!(
+            crate_file(&config, package_ids[0]),
+            crate_file(&config, package_ids[1])
+        );
+    }
+
+    #[test]
+    fn too_few_top_crates() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(check_top_crates_count(100, 100, false, &mut diagnostics).is_ok());
+        assert!(check_top_crates_count(50, 100, false, &mut diagnostics).is_ok());
+        assert!(!diagnostics.has_warnings());
+
+        assert!(matches!(
+            check_top_crates_count(0, 100, false, &mut diagnostics),
+            Err(TopCratesError::TooFewTopCrates {
+                fetched: 0,
+                count: 100
+            })
+        ));
+        assert!(check_top_crates_count(3, 100, true, &mut diagnostics).is_ok());
+        assert_eq!(diagnostics.count(Severity::Warning), 1);
+    }
+}
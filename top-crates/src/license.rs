@@ -0,0 +1,142 @@
+//! Just enough SPDX license expression handling to decide if a
+//! crate's license is acceptable.
+//!
+//! Expressions are made of license identifiers combined with `AND`,
+//! `OR`, `WITH` and parentheses. The legacy `/` separator used by
+//! older crates is treated as `OR`.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    License(String),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    expression: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid license expression `{}`", self.expression)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, ParseError> {
+        let error = || ParseError {
+            expression: expression.to_owned(),
+        };
+
+        let spaced = expression
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace('/', " OR ");
+        let mut tokens = spaced.split_whitespace().peekable();
+
+        let parsed = parse_or(&mut tokens).ok_or_else(error)?;
+        match tokens.next() {
+            Some(_) => Err(error()),
+            None => Ok(parsed),
+        }
+    }
+
+    /// Whether the expression can be satisfied using only licenses
+    /// for which `allowed` returns true.
+    pub fn satisfied_by(&self, allowed: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            Expression::License(id) => allowed(id),
+            Expression::And(l, r) => l.satisfied_by(allowed) && r.satisfied_by(allowed),
+            Expression::Or(l, r) => l.satisfied_by(allowed) || r.satisfied_by(allowed),
+        }
+    }
+}
+
+type Tokens<'a> = std::iter::Peekable<std::str::SplitWhitespace<'a>>;
+
+fn parse_or(tokens: &mut Tokens<'_>) -> Option<Expression> {
+    let mut lhs = parse_and(tokens)?;
+    while tokens.peek() == Some(&"OR") {
+        tokens.next();
+        let rhs = parse_and(tokens)?;
+        lhs = Expression::Or(Box::new(lhs), Box::new(rhs));
+    }
+    Some(lhs)
+}
+
+fn parse_and(tokens: &mut Tokens<'_>) -> Option<Expression> {
+    let mut lhs = parse_term(tokens)?;
+    while tokens.peek() == Some(&"AND") {
+        tokens.next();
+        let rhs = parse_term(tokens)?;
+        lhs = Expression::And(Box::new(lhs), Box::new(rhs));
+    }
+    Some(lhs)
+}
+
+fn parse_term(tokens: &mut Tokens<'_>) -> Option<Expression> {
+    match tokens.next()? {
+        "(" => {
+            let inner = parse_or(tokens)?;
+            match tokens.next()? {
+                ")" => Some(inner),
+                _ => None,
+            }
+        }
+        ")" | "AND" | "OR" | "WITH" => None,
+        id => {
+            // An exception only ever grants extra permissions, so
+            // whether the license is acceptable depends on the base
+            // license alone.
+            if tokens.peek() == Some(&"WITH") {
+                tokens.next();
+                tokens.next()?;
+            }
+            Some(Expression::License(id.trim_end_matches('+').to_owned()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn allowed(expression: &str) -> bool {
+        let allowlist = ["MIT", "Apache-2.0"];
+        Expression::parse(expression)
+            .unwrap()
+            .satisfied_by(&|id| allowlist.contains(&id))
+    }
+
+    #[test]
+    fn single_license() {
+        assert!(allowed("MIT"));
+        assert!(!allowed("GPL-3.0"));
+    }
+
+    #[test]
+    fn combinations() {
+        assert!(allowed("MIT OR Apache-2.0"));
+        assert!(allowed("GPL-3.0 OR MIT"));
+        assert!(!allowed("MIT AND GPL-3.0"));
+        assert!(allowed("(MIT OR GPL-3.0) AND Apache-2.0"));
+        assert!(allowed("Apache-2.0 WITH LLVM-exception"));
+    }
+
+    #[test]
+    fn legacy_slash_separator() {
+        assert!(allowed("MIT/Apache-2.0"));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Expression::parse("MIT OR").is_err());
+        assert!(Expression::parse("(MIT").is_err());
+        assert!(Expression::parse("").is_err());
+    }
+}
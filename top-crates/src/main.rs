@@ -1,115 +1,1776 @@
 #![deny(rust_2018_idioms)]
 
+use cargo::core::Edition;
+use clap::Parser;
+use log::info;
 use rust_playground_top_crates::*;
-use serde::Serialize;
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
-    fs::File,
-    io::{Read, Write},
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    io::{self, BufRead, Write},
     path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
 };
 
-/// A Cargo.toml file.
+/// The number of crates listed when reporting the largest feature sets.
+const FEATURE_REPORT_LIMIT: usize = 10;
+
+/// The exit code used by `--diff` when the manifest has changed.
+const DIFF_EXIT_CODE: i32 = 2;
+
+/// The exit code when the registry or crates.io could not be reached,
+/// which may well work if tried again.
+const NETWORK_EXIT_CODE: i32 = 3;
+
+/// The exit code when the crates could not be resolved together.
+const RESOLUTION_EXIT_CODE: i32 = 4;
+
+/// The exit code when a file could not be read, written, or
+/// (de)serialized.
+const IO_EXIT_CODE: i32 = 5;
+
+/// The exit code when the modifications, crate list, or generated
+/// manifest are not valid.
+const VALIDATION_EXIT_CODE: i32 = 6;
+
+/// The exit code when the registry failed in a way that retrying is
+/// unlikely to fix, such as a corrupt index.
+const REGISTRY_EXIT_CODE: i32 = 7;
+
+/// The exit code when a downloaded package does not match the
+/// checksum the registry published for it.
+const CHECKSUM_EXIT_CODE: i32 = 8;
+
+/// The exit code when something had to be downloaded while working
+/// offline.
+const OFFLINE_EXIT_CODE: i32 = 9;
+
+/// The exit code when the generated manifest and crate information
+/// don't describe the same crates.
+const INCONSISTENT_EXIT_CODE: i32 = 10;
+
+/// Calculates what crates should be included in the playground.
+///
+/// Exits with status 1 when errors were reported, 2 when `--diff`
+/// found a difference, 3 when the network failed (worth retrying),
+/// 4 when the crates could not be resolved, 5 when a file could not
+/// be read, written, or (de)serialized, 6 when the modifications,
+/// crate list, or generated manifest are invalid, 7 when the registry
+/// failed for good, 8 when a download does not match its checksum, 9
+/// when something is not available offline, and 10 when the generated
+/// files disagree with each other.
+#[derive(Debug, Parser)]
+struct Cli {
+    /// Directory to write `Cargo.toml` and `crate-information.json` into
+    #[arg(default_value = "../compiler/base")]
+    base_directory: PathBuf,
+
+    /// Read hand-curated changes to the crate list from PATH
+    ///
+    /// May be given more than once, to layer environment-specific
+    /// files over a shared one. Lists and sets from every file are
+    /// combined, while per-crate settings and single values from a
+    /// later file replace those of an earlier one.
+    #[arg(
+        long = "modifications",
+        value_name = "PATH",
+        default_value = "crate-modifications.toml"
+    )]
+    modifications: Vec<PathBuf>,
+
+    /// Write `Cargo.toml` to PATH instead of into the base directory
+    ///
+    /// The `Cargo.lock` (and any workspace members) are written next
+    /// to it.
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+
+    /// Write `crate-information.json` to PATH instead of into the base directory
+    #[arg(long, value_name = "PATH")]
+    crate_info_path: Option<PathBuf>,
+
+    /// Also bundle `Cargo.toml`, `Cargo.lock`, and `crate-information.json` into PATH
+    ///
+    /// PATH is a gzip-compressed tarball with the files at its root,
+    /// for deploying the compiler base as a single artifact. The files
+    /// are still written where they otherwise would be.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["stdout_manifest", "dry_run", "workspace_manifest"]
+    )]
+    archive: Option<PathBuf>,
+
+    /// Fail if more than N features are enabled across all crates
+    #[arg(long, value_name = "N")]
+    max_features: Option<usize>,
+
+    /// Use a saved crates.io top crates response instead of the live one
+    ///
+    /// The file should contain the JSON returned by
+    /// `https://crates.io/api/v1/crates?page=1&per_page=100&sort=downloads`,
+    /// allowing the crate list of an earlier run to be reproduced.
+    /// Curated additions are still applied on top of it.
+    #[arg(long, value_name = "FILE", visible_alias = "crates-file")]
+    top_crates_snapshot: Option<PathBuf>,
+
+    /// Use the crates listed in PATH (`-` for stdin) instead of the top crates
+    ///
+    /// Each line names a crate, optionally followed by `@` and a
+    /// version requirement, such as `serde@1.0.150`. Blank lines and
+    /// those starting with `#` are skipped. Nothing is downloaded from
+    /// crates.io for the list; curated additions and exclusions are
+    /// still applied to it.
+    #[arg(long, value_name = "PATH", conflicts_with = "top_crates_snapshot")]
+    crates_from: Option<PathBuf>,
+
+    /// Keep the versions of the crates in the lockfile at PATH
+    ///
+    /// The versions of the registry's crates in the lockfile, such as
+    /// the playground's own `Cargo.lock`, are preferred when resolving,
+    /// so changing the top-level crates leaves the others where they
+    /// were. Top-level crates are still updated to their newest
+    /// version, and a warning names every other crate whose version had
+    /// to change.
+    #[arg(long, value_name = "PATH")]
+    locked_from: Option<PathBuf>,
+
+    /// Use only the curated additions, not the top crates
+    ///
+    /// Nothing is downloaded from crates.io to pick the crates; the
+    /// `additions` of the modifications and their dependencies are
+    /// resolved on their own. Exclusions and features still apply.
+    #[arg(long, conflicts_with_all = ["top_crates_snapshot", "crates_from"])]
+    include_only: bool,
+
+    /// Exit with an error if any warnings were emitted
+    ///
+    /// This includes crates whose playground metadata could not be
+    /// parsed or enables features they don't have, and top crates
+    /// left out for having no library.
+    #[arg(long, visible_alias = "strict")]
+    deny_warnings: bool,
+
+    /// Exit with an error if a resolved crate has a known vulnerability
+    ///
+    /// Needs `advisory-db` in crate-modifications.toml to name a local
+    /// checkout of the RustSec advisory database. Informational
+    /// advisories, such as a crate being unmaintained, stay warnings.
+    #[arg(long)]
+    deny_advisories: bool,
+
+    /// Also write a list of `name = "version"` lines to PATH
+    #[arg(long, value_name = "PATH")]
+    requirements_out: Option<PathBuf>,
+
+    /// Also write a Markdown table of the top-level crates to PATH
+    ///
+    /// Curated additions are listed apart from the crates included
+    /// for their downloads, which makes it suitable for release notes.
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Fail if any curated addition is missing from the output
+    #[arg(long)]
+    require_all_additions: bool,
+
+    /// Remove curated additions that are listed anyway from the modifications files
+    ///
+    /// Additions with a version requirement are kept. Each file is
+    /// rewritten in place, keeping its comments and layout.
+    #[arg(long)]
+    prune_redundant: bool,
+
+    /// Include the N most downloaded crates on crates.io
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    count: usize,
+
+    /// Carry on even if crates.io lists fewer than half of the top crates
+    ///
+    /// An empty or short list usually means the crates.io API changed
+    /// or failed, so by default nothing is written rather than replacing
+    /// the playground's crates with the curated additions alone. The
+    /// same goes for crates left out with `--offline`, such as the Rust
+    /// Cookbook's, and for crates whose owners can't be checked then.
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Order crates.io's crates by CRITERION before taking the top ones
+    ///
+    /// One of `downloads`, `recent-downloads`, `recent-updates`, `new`,
+    /// or `alpha`.
+    #[arg(long, value_name = "CRITERION", default_value = "downloads")]
+    sort: TopCratesSort,
+
+    /// Generate for the toolchain channel CHANNEL
+    ///
+    /// One of `stable`, `beta`, or `nightly`. The `[channels.CHANNEL]`
+    /// tables of the modifications are layered over the rest of them,
+    /// and the files are written to a directory named after the
+    /// channel within the base directory.
+    #[arg(long, value_name = "CHANNEL")]
+    channel: Option<Channel>,
+
+    /// Retry network requests and registry queries that fail transiently up to N times
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    retries: u32,
+
+    /// Wait at most SECS before retrying a request that was rate limited
+    ///
+    /// Otherwise, as long as crates.io's `Retry-After` header asks for
+    /// is waited.
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    max_retry_after: u64,
+
+    /// Download at most N packages at once
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    download_concurrency: usize,
+
+    /// Leave out crates that still fail to download after being retried
+    ///
+    /// Each is left out with a warning, along with the top-level crates
+    /// that need it, rather than stopping the run.
+    #[arg(long)]
+    skip_failed_downloads: bool,
+
+    /// Have at most N registry queries in flight at once
+    ///
+    /// Package downloads are limited by `--download-concurrency` instead.
+    ///
+    /// Defaults to the `TOP_CRATES_CONCURRENCY` environment variable,
+    /// or 8 when that isn't set.
+    #[arg(long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// How many crates to request from crates.io at once (at most 100)
+    #[arg(long, value_name = "N", default_value_t = CRATES_IO_MAX_PAGE_SIZE)]
+    page_size: u32,
+
+    /// Print only the generated `Cargo.toml` to stdout instead of writing any files
+    #[arg(long)]
+    stdout_manifest: bool,
+
+    /// Print what would be written instead of writing any files
+    ///
+    /// Both the `Cargo.toml` and `crate-information.json` are printed
+    /// to stdout, one after the other.
+    #[arg(long, conflicts_with = "stdout_manifest")]
+    dry_run: bool,
+
+    /// Show how the dependencies and profiles differ from the existing `Cargo.toml`
+    ///
+    /// The difference is printed before anything is written, or to
+    /// stderr with `--dry-run` so stdout only has the generated files.
+    /// If there is any, the tool exits with status 2 once it is done,
+    /// which also happens with `--dry-run`.
+    #[arg(long, conflicts_with_all = ["stdout_manifest", "workspace_manifest"])]
+    diff: bool,
+
+    /// List the crates added, removed, or changed in version since an earlier crate-information.json at PATH
+    ///
+    /// The list is printed before anything is written, for writing
+    /// release notes. With `--dry-run` it goes to stderr instead.
+    #[arg(long, value_name = "PATH", conflicts_with = "stdout_manifest")]
+    since: Option<PathBuf>,
+
+    /// Enable the same features on every version of a duplicated crate
+    #[arg(long)]
+    unify_duplicate_features: bool,
+
+    /// List `default` among a crate's features instead of folding it
+    /// into `default-features`
+    ///
+    /// The features are then written exactly as they were enabled,
+    /// which helps when debugging where they came from, but expect
+    /// larger diffs against manifests generated without it.
+    #[arg(long = "no-default-feature-dedup")]
+    keep_default_feature: bool,
+
+    /// Depend on every crate with no features and no default features
+    ///
+    /// Everything is resolved that way too, so the manifest shows
+    /// which crates don't build without their default features. The
+    /// files are written to a `minimal-features` directory within the
+    /// base directory, so they don't replace the usual ones.
+    #[arg(long)]
+    minimal_features: bool,
+
+    /// Write the complete resolve to PATH in cargo's lockfile format
+    ///
+    /// Unlike the other outputs, which describe the crates exposed to
+    /// users, this is the resolve exactly as cargo encodes it, so tools
+    /// that understand `Cargo.lock` can consume it directly. It has no
+    /// entry for the playground package itself.
+    #[arg(long, value_name = "PATH")]
+    cargo_resolve_out: Option<PathBuf>,
+
+    /// Remember registry query results in DIR to speed up later runs
+    ///
+    /// Entries are kept apart for each registry, and only reused while
+    /// its index is at the same revision. They hold every published
+    /// version of a crate rather than the chosen one, so changing pins
+    /// or `--allow-prerelease` takes effect without clearing them.
+    #[arg(long, value_name = "DIR")]
+    query_cache: Option<PathBuf>,
+
+    /// Neither use nor update the query cache or the remembered top crates for this run
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Remember the list of top crates from crates.io in PATH
+    ///
+    /// A remembered list is only reused for the same `--api`.
+    #[arg(long, value_name = "PATH")]
+    top_crates_cache: Option<PathBuf>,
+
+    /// Reuse the remembered list of top crates for up to HOURS after downloading it
+    #[arg(long, value_name = "HOURS", default_value_t = 24)]
+    top_crates_ttl: u64,
+
+    /// Download the list of top crates even if the remembered one is recent
+    #[arg(long, conflicts_with = "offline")]
+    refresh: bool,
+
+    /// Don't use the network at all
+    ///
+    /// The list of top crates must have been remembered by an earlier
+    /// run (see `--top-crates-cache`), and every crate needed must
+    /// already be in cargo's local registry cache. Crates that can
+    /// only be listed online, such as the Rust Cookbook's, fail the
+    /// run unless `--allow-empty` is given.
+    #[arg(long)]
+    offline: bool,
+
+    /// Use the local registry index without updating it
+    ///
+    /// Useful when the index was just updated, as updating it is the
+    /// slowest part of a run. An index that is out of date may lead to
+    /// older versions being chosen than are really the newest.
+    #[arg(long)]
+    skip_update: bool,
+
+    /// Resolve against the registry index as it was at git revision SHA
+    ///
+    /// The same versions are then chosen as when the index was at that
+    /// revision, to reproduce an earlier run. The index is only updated
+    /// when the revision isn't there already, which fails with
+    /// `--offline`. The shared index itself stays at its newest
+    /// revision.
+    #[arg(long, value_name = "SHA")]
+    index_rev: Option<String>,
+
+    /// Prefer versions of the top crates that build with Rust X.Y
+    ///
+    /// Each crate's newest version whose `rust-version` is at most X.Y
+    /// is used. When there is none, its newest version is used with a
+    /// warning.
+    #[arg(long, value_name = "X.Y", value_parser = parse_rust_version)]
+    msrv: Option<Version>,
+
+    /// Prefer versions of the top crates written in EDITION or older
+    ///
+    /// Each crate's newest version whose edition is at most EDITION is
+    /// used. With `--msrv` as well, it is the newest version that
+    /// satisfies both; when there is none, the newest version is used
+    /// with a warning.
+    #[arg(long, value_name = "EDITION")]
+    max_edition: Option<Edition>,
+
+    /// Let every top-level crate use its newest version, even if it is a prerelease
+    ///
+    /// This affects the whole crate set, unlike `allow-prerelease` in
+    /// crate-modifications.toml, which names the crates it applies to.
+    /// Yanked versions are never used.
+    #[arg(long)]
+    allow_prerelease: bool,
+
+    /// Use the registry whose index is at URL instead of crates.io
+    ///
+    /// Can also be set with the `TOP_CRATES_REGISTRY` environment
+    /// variable. The top crates are listed by the web API named in the
+    /// index's `config.json`. With crates.io's own index, the
+    /// `CRATES_IO_API_BASE` environment variable can point at another
+    /// web API, such as a local mock.
+    #[arg(long, value_name = "URL", value_parser = parse_registry_url)]
+    registry: Option<String>,
+
+    /// Include the dependencies of the Rust Cookbook's manifest at URL
+    #[arg(long, value_name = "URL", default_value = RUST_COOKBOOK_MANIFEST)]
+    rust_cookbook_manifest: String,
+
+    /// Write every resolved crate to PATH as JSON
+    ///
+    /// Crates that are only needed to build other crates (such as the
+    /// dependencies of build scripts) are marked with `build_only`.
+    #[arg(long, value_name = "PATH")]
+    resolved_crates_out: Option<PathBuf>,
+
+    /// Write the full resolved dependency graph to PATH as JSON
+    ///
+    /// This is an array with an object for every package cargo
+    /// resolved, sorted by `id`: its `id` (`name version`, followed by
+    /// its source in parentheses if that isn't the registry), `name`,
+    /// `version`, `source`, and the sorted `dependencies` it has, given
+    /// by their `id`. Diffing two of these shows which transitive
+    /// crates changed between regenerations. Crates only needed by
+    /// build scripts are left out when `--exclude-build-dependencies`
+    /// is given.
+    #[arg(long, value_name = "PATH")]
+    resolution_out: Option<PathBuf>,
+
+    /// Leave crates only needed by build scripts out of the resolution graph and download size
+    ///
+    /// Cargo always resolves the build-dependencies of build scripts,
+    /// and they are always written to `Cargo.lock`; this only leaves
+    /// them out of `--resolution-out` and the download size reported
+    /// at the end.
+    #[arg(long)]
+    exclude_build_dependencies: bool,
+
+    /// Suggest which top crates to exclude to have at most N crates in total
+    ///
+    /// The suggestion is only printed, not applied. It is computed
+    /// greedily and is not guaranteed to be the smallest possible set.
+    #[arg(long, value_name = "N")]
+    trim_to: Option<usize>,
+
+    /// Write a virtual workspace with one member package per crate (experimental)
+    ///
+    /// Instead of a single package depending on every crate, the
+    /// `Cargo.toml` is a `[workspace]` whose members each depend on one
+    /// crate, using the same pinned versions. This isolates each crate's
+    /// features to the members that are built together, but building
+    /// the whole workspace still unifies them, and the playground
+    /// cannot use a member to get access to all crates at once the way
+    /// it can with the flat manifest.
+    #[arg(long)]
+    workspace_manifest: bool,
+
+    /// How to write versions: `raw` (as published) or `semver3` (without build metadata)
+    #[arg(long, value_name = "FORMAT", default_value = "raw")]
+    version_format: VersionFormat,
+
+    /// Leave out crates that are already dependencies in this Cargo.toml
+    ///
+    /// The crates are still resolved so that versions unify with the
+    /// rest of the set; they just aren't repeated in the output.
+    #[arg(long, value_name = "PATH")]
+    base_manifest: Option<PathBuf>,
+
+    /// Report which crates each of the comma-separated Rust VERSIONS could not build
+    ///
+    /// This is based on the `rust-version` each resolved crate
+    /// declares, so crates that don't declare one are assumed to build
+    /// with every version. Nothing is excluded from the output.
+    #[arg(
+        long,
+        value_name = "VERSIONS",
+        value_delimiter = ',',
+        value_parser = parse_rust_version
+    )]
+    msrv_matrix: Vec<Version>,
+
+    /// Only use manifest fields that cargo X.Y understands
+    ///
+    /// By default the manifest is written for the latest cargo. Older
+    /// versions reject some of what it contains, such as the `resolver`
+    /// field (cargo 1.51) and `build-override` profiles (cargo 1.41).
+    #[arg(long, value_name = "X.Y", value_parser = parse_rust_version)]
+    cargo_compat: Option<Version>,
+
+    /// Write why each feature of each resolved crate is enabled to PATH as JSON
+    ///
+    /// A feature can be requested by the crate's playground metadata
+    /// (`metadata`), be one of its default features (`default`), be
+    /// asked for by a crate that depends on it (`unified-by-<crate>`),
+    /// or be enabled by another of its features (`implied-by-<feature>`).
+    #[arg(long, value_name = "PATH")]
+    feature_provenance: Option<PathBuf>,
+
+    /// Write the unified features of each shared crate to PATH as JSON
+    ///
+    /// For every crate that other crates depend on and that is included
+    /// at only one version, this lists the features enabled once every
+    /// request is unified, which crates asked for which, and which are
+    /// only there because some crate's metadata sets `all-features`.
+    #[arg(long, value_name = "PATH")]
+    feature_unification: Option<PathBuf>,
+
+    /// Print how CRATE was pulled in, then exit without writing anything
+    ///
+    /// The shortest chain of dependencies from a top-level crate to
+    /// each version of CRATE in the resolve is shown.
+    #[arg(long, value_name = "CRATE")]
+    explain: Option<String>,
+
+    /// List the crates in the existing Cargo.toml that have newer versions, then exit
+    ///
+    /// The registry is asked for the newest non-prerelease version of
+    /// every dependency, and those behind it are printed with both
+    /// versions. Nothing is resolved or written.
+    #[arg(long)]
+    outdated: bool,
+
+    /// Rank the crates that aren't in the existing Cargo.toml by how many crates they pull in, then exit
+    ///
+    /// The crates already in Cargo.toml are resolved on their own,
+    /// and then with each of the others in turn, to find which of them
+    /// makes the resolve grow the most. Nothing is written.
+    #[arg(long, conflicts_with = "outdated")]
+    bisect_growth: bool,
+
+    /// Only include the comma-separated CRATES and their dependencies
+    ///
+    /// This is for quickly looking into how a few crates come out;
+    /// they are chosen, named, and given features the same way as in a
+    /// full run, whether or not they are among the top crates.
+    #[arg(long, value_name = "CRATES", value_delimiter = ',')]
+    only: Vec<String>,
+
+    /// Check `crate-information.json` against its published schema before writing it
+    #[arg(long)]
+    validate_schema: bool,
+
+    /// Check that cargo accepts the generated manifest before writing it
+    ///
+    /// The manifest is written to a temporary directory and read with
+    /// `cargo metadata --no-deps`; the run fails with cargo's error if
+    /// that does. Set the `CARGO` environment variable to use another
+    /// cargo than the one on the `PATH`.
+    #[arg(long)]
+    validate: bool,
+
+    /// Print the JSON Schema of `crate-information.json`, then exit
+    ///
+    /// Consumers of the file can use it to validate what they read.
+    /// Nothing is resolved or written.
+    #[arg(long)]
+    emit_schema: bool,
+
+    /// Check the `crate-information.json` at PATH, then exit
+    ///
+    /// Every entry must match the schema, have an id that is a Rust
+    /// identifier and is used by no other entry, and have a semver
+    /// version. Each problem is printed, and the exit status is 6 if
+    /// there are any. Nothing is resolved or written.
+    #[arg(long, value_name = "PATH")]
+    check: Option<PathBuf>,
+
+    /// Also print warnings and errors to stdout as GitHub Actions annotations
+    #[arg(long, conflicts_with_all = ["stdout_manifest", "dry_run"])]
+    github_annotations: bool,
+
+    /// How to report the outcome of the run: `text` or `json`
+    ///
+    /// With `json`, a single JSON object is printed to stdout at the
+    /// end, with how many crates were listed, left out, and resolved,
+    /// every diagnostic, and the paths of the files that were written.
+    /// It is also printed when errors stop anything from being
+    /// written. Logs stay on stderr either way.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        conflicts_with_all = [
+            "stdout_manifest",
+            "dry_run",
+            "diff",
+            "since",
+            "explain",
+            "outdated",
+            "bisect_growth",
+            "github_annotations",
+        ]
+    )]
+    format: OutputFormat,
+
+    /// Log each crate's chosen version and features as they are decided
+    ///
+    /// This is the same as setting `RUST_LOG=rust_playground_top_crates=debug`,
+    /// which takes precedence when it is set.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Only print warnings, errors, and the output that was asked for
+    ///
+    /// Progress, notes, and the summary of what was resolved are left
+    /// out. Whatever is printed to stdout, such as with `--dry-run` or
+    /// `--format json`, is unaffected.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print how long each phase took once everything is written
+    #[arg(long)]
+    timings: bool,
+
+    /// Set `codegen-units` of the dev profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "N")]
+    dev_codegen_units: Option<u32>,
+
+    /// Set `incremental` of the dev profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "BOOL")]
+    dev_incremental: Option<bool>,
+
+    /// Set `codegen-units` of the release profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "N")]
+    release_codegen_units: Option<u32>,
+
+    /// Set `incremental` of the release profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "BOOL")]
+    release_incremental: Option<bool>,
+
+    /// Set `opt-level` of the dev profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "LEVEL", value_parser = parse_opt_level)]
+    dev_opt_level: Option<String>,
+
+    /// Set `lto` of the dev profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "BOOL")]
+    dev_lto: Option<bool>,
+
+    /// Set `opt-level` of the release profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "LEVEL", value_parser = parse_opt_level)]
+    release_opt_level: Option<String>,
+
+    /// Set `lto` of the release profile, overriding crate-modifications.toml
+    #[arg(long, value_name = "BOOL")]
+    release_lto: Option<bool>,
+}
+
+/// How the outcome of a run is reported
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum OutputFormat {
+    /// Only in the logs and summary on stderr.
+    Text,
+    /// As a [`RunSummary`] on stdout, too.
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown format `{}`; expected text or json", s)),
+        }
+    }
+}
+
+/// What `--format json` prints about a run.
 #[derive(Serialize)]
-struct TomlManifest {
-    package: TomlPackage,
+struct RunSummary<'a> {
+    listed: usize,
+    /// The listed crates that aren't top-level crates in the end, for
+    /// any reason.
+    left_out: usize,
+    top_level: usize,
+    packages: usize,
+    duplicated: &'a [String],
+    warnings: usize,
+    errors: usize,
+    diagnostics: Vec<&'a Diagnostic>,
+    written: &'a [PathBuf],
+}
+
+impl<'a> RunSummary<'a> {
+    fn new(
+        summary: &'a ResolutionSummary,
+        diagnostics: &'a Diagnostics,
+        written: &'a [PathBuf],
+    ) -> Self {
+        RunSummary {
+            listed: summary.listed,
+            left_out: summary.listed.saturating_sub(summary.top_level),
+            top_level: summary.top_level,
+            packages: summary.packages,
+            duplicated: &summary.duplicated,
+            warnings: diagnostics.count(Severity::Warning),
+            errors: diagnostics.count(Severity::Error),
+            diagnostics: diagnostics.iter().collect(),
+            written,
+        }
+    }
+
+    fn print(&self) -> Result<(), TopCratesError> {
+        let json = serde_json::to_string(self)
+            .map_err(TopCratesError::json("Unable to serialize the run summary"))?;
+        println!("{}", json);
+        Ok(())
+    }
+}
+
+/// A Cargo.toml file for a virtual workspace.
+#[derive(Serialize)]
+struct TomlWorkspaceManifest {
+    workspace: TomlWorkspace,
+    #[serde(skip_serializing_if = "Profiles::is_empty")]
     profile: Profiles,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    patch: BTreeMap<String, BTreeMap<String, Patch>>,
+}
+
+#[derive(Serialize)]
+struct TomlWorkspace {
+    members: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolver: Option<String>,
+}
+
+/// A Cargo.toml file for a workspace member that depends on one crate.
+#[derive(Serialize)]
+struct TomlMemberManifest {
+    package: TomlMemberPackage,
     #[serde(serialize_with = "toml::ser::tables_last")]
     dependencies: BTreeMap<String, DependencySpec>,
     #[serde(serialize_with = "toml::ser::tables_last")]
     build_dependencies: BTreeMap<String, DependencySpec>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    target: BTreeMap<String, TomlTarget>,
 }
 
-/// Header of Cargo.toml file.
 #[derive(Serialize)]
-struct TomlPackage {
+struct TomlMemberPackage {
     name: String,
     version: String,
-    authors: Vec<String>,
-    resolver: String,
+    publish: bool,
 }
 
-/// Profile used for build dependencies (build scripts, proc macros, and their
-/// dependencies).
-#[derive(Serialize)]
-#[serde(rename_all = "kebab-case")]
-struct BuildOverride {
-    codegen_units: u32,
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(exit_code(&e));
+    }
 }
 
-/// A profile section in a Cargo.toml file
-#[derive(Serialize)]
-#[serde(rename_all = "kebab-case")]
-struct Profile {
-    codegen_units: u32,
-    incremental: bool,
-    build_override: BuildOverride,
-}
+/// The exit status for each kind of error, as described in the help.
+fn exit_code(error: &TopCratesError) -> i32 {
+    use TopCratesError::*;
 
-/// Available profile types
-#[derive(Serialize)]
-struct Profiles {
-    dev: Profile,
-    release: Profile,
+    match error {
+        Network { .. } | HttpStatus { .. } => NETWORK_EXIT_CODE,
+        Registry { .. } if error.is_transient() => NETWORK_EXIT_CODE,
+        Resolution { .. } | NoLibrary { .. } => RESOLUTION_EXIT_CODE,
+        Io { .. } | Json { .. } | TomlSerialize { .. } => IO_EXIT_CODE,
+        InvalidCrateList { .. }
+        | UnknownAdditions { .. }
+        | TooFewTopCrates { .. }
+        | InvalidManifest { .. }
+        | TomlParse { .. } => VALIDATION_EXIT_CODE,
+        Registry { .. } => REGISTRY_EXIT_CODE,
+        ChecksumMismatch { .. } => CHECKSUM_EXIT_CODE,
+        Offline { .. } => OFFLINE_EXIT_CODE,
+        Inconsistent { .. } => INCONSISTENT_EXIT_CODE,
+    }
 }
 
-fn main() {
-    let mut f =
-        File::open("crate-modifications.toml").expect("unable to open crate modifications file");
+fn run() -> Result<(), TopCratesError> {
+    let cli = Cli::parse();
+
+    if cli.emit_schema {
+        print!("{}", CRATE_INFORMATION_SCHEMA);
+        return Ok(());
+    }
 
-    let mut d = Vec::new();
-    f.read_to_end(&mut d)
-        .expect("unable to read crate modifications file");
+    if let Some(path) = &cli.check {
+        return check(path);
+    }
 
-    let modifications: Modifications =
-        toml::from_slice(&d).expect("unable to parse crate modifications file");
+    // Progress is logged at INFO, and what happens to each crate at
+    // DEBUG; cargo's own logging is left out unless asked for.
+    let level = if cli.verbose {
+        "debug"
+    } else if cli.quiet {
+        "warn"
+    } else {
+        "info"
+    };
+    let filter = format!("rust_playground_top_crates={}", level);
+    let env = env_logger::Env::default().default_filter_or(filter);
+    env_logger::Builder::from_env(env).init();
 
-    let (dependencies, infos) = rust_playground_top_crates::generate_info(&modifications);
+    let modifications = read_modifications(&cli.modifications)?.for_channel(cli.channel);
+    let mut base_directory = match cli.channel {
+        Some(channel) => cli.base_directory.join(channel.as_str()),
+        None => cli.base_directory.clone(),
+    };
+    if cli.minimal_features {
+        base_directory.push("minimal-features");
+    }
+    let crate_list = cli
+        .crates_from
+        .as_deref()
+        .map(read_crate_list)
+        .transpose()?;
 
-    // Construct playground's Cargo.toml.
-    let manifest = TomlManifest {
-        package: TomlPackage {
-            name: "playground".to_owned(),
-            version: "0.0.1".to_owned(),
-            authors: vec!["The Rust Playground".to_owned()],
-            resolver: "2".to_owned(),
+    let options = GenerateOptions {
+        top_crates_snapshot: cli.top_crates_snapshot,
+        locked_from: cli.locked_from,
+        crate_list,
+        include_only: cli.include_only,
+        count: cli.count,
+        allow_empty: cli.allow_empty,
+        deny_advisories: cli.deny_advisories,
+        sort: cli.sort,
+        page_size: cli.page_size,
+        unify_duplicate_features: cli.unify_duplicate_features,
+        keep_default_feature: cli.keep_default_feature,
+        minimal_features: cli.minimal_features,
+        query_cache: if cli.no_cache { None } else { cli.query_cache },
+        top_crates_cache: if cli.no_cache {
+            None
+        } else {
+            cli.top_crates_cache
         },
-        profile: Profiles {
-            dev: Profile {
-                codegen_units: 1,
-                incremental: false,
-                build_override: BuildOverride { codegen_units: 1 },
+        top_crates_ttl: Duration::from_secs(cli.top_crates_ttl * 60 * 60),
+        refresh_top_crates: cli.refresh,
+        offline: cli.offline,
+        skip_update: cli.skip_update,
+        index_rev: cli.index_rev,
+        msrv: cli.msrv,
+        max_edition: cli.max_edition,
+        allow_prerelease: cli.allow_prerelease,
+        registry: cli.registry,
+        rust_cookbook_manifest: cli.rust_cookbook_manifest,
+        trim_to: cli.trim_to,
+        version_format: cli.version_format,
+        feature_provenance: cli.feature_provenance.is_some(),
+        feature_unification: cli.feature_unification.is_some(),
+        resolution_graph: cli.resolution_out.is_some(),
+        exclude_build_dependencies: cli.exclude_build_dependencies,
+        explain: cli.explain,
+        only: cli.only,
+        retries: cli.retries,
+        download_concurrency: cli.download_concurrency,
+        skip_failed_downloads: cli.skip_failed_downloads,
+        jobs: cli.jobs,
+        max_retry_after: Duration::from_secs(cli.max_retry_after),
+        profile: ProfileModifications {
+            dev: ProfileSettings {
+                codegen_units: cli.dev_codegen_units,
+                incremental: cli.dev_incremental,
+                opt_level: cli.dev_opt_level,
+                lto: cli.dev_lto,
             },
-            release: Profile {
-                codegen_units: 1,
-                incremental: false,
-                build_override: BuildOverride { codegen_units: 1 },
+            release: ProfileSettings {
+                codegen_units: cli.release_codegen_units,
+                incremental: cli.release_incremental,
+                opt_level: cli.release_opt_level,
+                lto: cli.release_lto,
             },
+            ..ProfileModifications::default()
         },
-        dependencies: dependencies.clone(),
-        build_dependencies: dependencies,
+        cargo_compat: cli.cargo_compat,
     };
 
+    let mut diagnostics = Diagnostics::streaming();
+    if cli.quiet {
+        diagnostics = diagnostics.quiet();
+    }
+
+    if cli.outdated {
+        let cargo_toml = cli
+            .manifest_path
+            .clone()
+            .unwrap_or_else(|| base_directory.join("Cargo.toml"));
+        let current = manifest_versions(&cargo_toml)?;
+        let outdated = rust_playground_top_crates::outdated(
+            &current,
+            &modifications,
+            &options,
+            &mut diagnostics,
+        )?;
+        diagnostics.report();
+        if diagnostics.has_errors() {
+            process::exit(1);
+        }
+        print_outdated(&outdated);
+        return Ok(());
+    }
+
+    if cli.bisect_growth {
+        let cargo_toml = cli
+            .manifest_path
+            .clone()
+            .unwrap_or_else(|| base_directory.join("Cargo.toml"));
+        // Without a manifest, every crate is measured on its own.
+        let baseline = if cargo_toml.exists() {
+            manifest_versions(&cargo_toml)?.into_keys().collect()
+        } else {
+            BTreeSet::new()
+        };
+        let growth = rust_playground_top_crates::bisect_growth(
+            &baseline,
+            &modifications,
+            &options,
+            &mut diagnostics,
+        )?;
+        diagnostics.report();
+        if diagnostics.has_errors() {
+            process::exit(1);
+        }
+        print_growth(&growth);
+        return Ok(());
+    }
+
+    let GeneratedOutput {
+        mut manifest,
+        mut infos,
+        resolved_crates,
+        cargo_resolve,
+        trim_suggestion,
+        feature_provenance,
+        feature_unification,
+        resolution_graph,
+        explanation,
+        summary,
+        download_size,
+        feature_counts,
+        metadata_errors,
+        redundant_additions,
+        mut timings,
+    } = rust_playground_top_crates::generate(&modifications, &options, &mut diagnostics)?;
+
+    if let Some(path) = &cli.base_manifest {
+        let in_base = base_manifest_crates(path)?;
+        let skipped = manifest
+            .all_dependencies()
+            .iter()
+            .filter(|(_, spec)| in_base.contains(&spec.package))
+            .map(|(exposed_name, _)| exposed_name.clone())
+            .collect::<BTreeSet<_>>();
+
+        manifest.retain_dependencies(|exposed_name| !skipped.contains(exposed_name));
+        infos.retain(|info| !skipped.contains(&info.id));
+
+        if !skipped.is_empty() && !cli.quiet {
+            let skipped = skipped.into_iter().collect::<Vec<_>>();
+            eprintln!(
+                "Skipped {} crates already in {}: {}",
+                skipped.len(),
+                path.display(),
+                skipped.join(", "),
+            );
+        }
+    }
+
+    if cli.validate_schema {
+        for error in validate_crate_information(&infos) {
+            diagnostics.error(format!(
+                "crate-information.json does not match its schema: {}",
+                error
+            ));
+        }
+    }
+
+    if cli.validate {
+        if let Err(e) = validate_manifest(&manifest) {
+            diagnostics.error(e.to_string());
+        }
+    }
+
+    if let Some(suggestion) = &trim_suggestion {
+        print_trim_suggestion(suggestion);
+    }
+
+    if !cli.msrv_matrix.is_empty() {
+        print_msrv_matrix(&resolved_crates, &cli.msrv_matrix);
+    }
+
+    // With `--only`, the additions are left out on purpose.
+    if options.only.is_empty() {
+        verify_additions(
+            &modifications,
+            &manifest.all_dependencies(),
+            cli.require_all_additions,
+            &mut diagnostics,
+        );
+    }
+
+    if let Some(max_features) = cli.max_features {
+        check_feature_count(&manifest.all_dependencies(), max_features, &mut diagnostics);
+    }
+
+    // Reported together once everything is resolved, rather than
+    // among the progress output.
+    for error in &metadata_errors {
+        diagnostics.warn(error.to_string());
+    }
+    if cli.deny_warnings && diagnostics.has_warnings() {
+        diagnostics.error(format!(
+            "{} warnings were emitted, which --deny-warnings (or --strict) treats as errors",
+            diagnostics.count(Severity::Warning),
+        ));
+    }
+
+    diagnostics.report();
+    if cli.github_annotations {
+        for diagnostic in diagnostics.iter() {
+            println!("{}", diagnostic.github_annotation());
+        }
+    }
+    if diagnostics.has_errors() {
+        if cli.format == OutputFormat::Json {
+            RunSummary::new(&summary, &diagnostics, &[]).print()?;
+        }
+        process::exit(1);
+    }
+
+    let show_timings = cli.timings;
+    let quiet = cli.quiet;
+    let print_summary = |timings: &Timings| {
+        if !quiet {
+            eprintln!("{}", summary);
+            eprint!("{}", download_size);
+            eprint!("{}", feature_counts);
+        }
+        if show_timings {
+            eprintln!("Time spent in each phase:\n{}", timings);
+        }
+    };
+
+    if let Some(explanation) = &explanation {
+        print_explanation(explanation);
+        print_summary(&timings);
+        return Ok(());
+    }
+
+    if cli.stdout_manifest {
+        let content = serialize_manifest(&manifest)?;
+        io::stdout()
+            .write_all(&content)
+            .map_err(TopCratesError::io("Couldn't write Cargo.toml to stdout"))?;
+        print_summary(&timings);
+        return Ok(());
+    }
+
+    let cargo_toml = cli
+        .manifest_path
+        .unwrap_or_else(|| base_directory.join("Cargo.toml"));
+    let crate_information = cli
+        .crate_info_path
+        .unwrap_or_else(|| base_directory.join("crate-information.json"));
+
+    // Under `--dry-run`, stdout is left to the generated files.
+    let mut report: Box<dyn Write> = if cli.dry_run {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    let changed = cli.diff && print_manifest_diff(&manifest, &cargo_toml, &mut report)?;
+    if let Some(path) = &cli.since {
+        print_changelog(&infos, path, &mut report)?;
+    }
+    let finish = |timings: &Timings| {
+        print_summary(timings);
+        if changed {
+            process::exit(DIFF_EXIT_CODE);
+        }
+        Ok(())
+    };
+
+    if cli.dry_run {
+        let manifest = serialize_manifest(&manifest)?;
+        let infos = serde_json::to_string_pretty(&infos).map_err(TopCratesError::json(
+            "Unable to serialize crate-information.json",
+        ))?;
+
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "# Cargo.toml")
+            .and_then(|_| stdout.write_all(&manifest))
+            .and_then(|_| writeln!(stdout, "\n# crate-information.json\n{}", infos))
+            .map_err(TopCratesError::io("Couldn't write to stdout"))?;
+        return finish(&timings);
+    }
+
+    let writing = Instant::now();
+    let mut written = Vec::new();
+    // What goes into `--archive`, under the name each has there.
+    let mut archived = Vec::new();
+
     // Write manifest file.
-    let base_directory: PathBuf = std::env::args_os()
-        .nth(1)
-        .unwrap_or_else(|| "../compiler/base".into())
-        .into();
-
-    let cargo_toml = base_directory.join("Cargo.toml");
-    write_manifest(manifest, &cargo_toml);
-    println!("wrote {}", cargo_toml.display());
-
-    let path = base_directory.join("crate-information.json");
-    let mut f = File::create(&path)
-        .unwrap_or_else(|e| panic!("Unable to create {}: {}", path.display(), e));
-    serde_json::to_writer_pretty(&mut f, &infos)
-        .unwrap_or_else(|e| panic!("Unable to write {}: {}", path.display(), e));
-    println!("Wrote {}", path.display());
-}
-
-fn write_manifest(manifest: TomlManifest, path: impl AsRef<Path>) {
-    let mut f = File::create(path).expect("Unable to create Cargo.toml");
-    let content = toml::to_vec(&manifest).expect("Couldn't serialize TOML");
-    f.write_all(&content).expect("Couldn't write Cargo.toml");
+    if cli.workspace_manifest {
+        write_workspace(manifest, &cargo_toml)?;
+        info!("Wrote {}", cargo_toml.display());
+        archived.push(("Cargo.toml", cargo_toml.clone()));
+        written.push(cargo_toml);
+    } else {
+        write_manifest(&manifest, &cargo_toml)?;
+        info!("Wrote {}", cargo_toml.display());
+        archived.push(("Cargo.toml", cargo_toml.clone()));
+        written.push(cargo_toml.clone());
+
+        // Lock the transitive dependencies too, so that building the
+        // playground uses exactly what was resolved here.
+        let lockfile = playground_lockfile(
+            &cargo_resolve,
+            &manifest.package.name,
+            &manifest.package.version,
+            &manifest.all_dependencies(),
+        )?;
+        let cargo_lock = cargo_toml.with_file_name("Cargo.lock");
+        write_atomically(&cargo_lock, lockfile.as_bytes())?;
+        info!("Wrote {}", cargo_lock.display());
+        archived.push(("Cargo.lock", cargo_lock.clone()));
+        written.push(cargo_lock);
+    }
+
+    write_json(&infos, &crate_information)?;
+    info!("Wrote {}", crate_information.display());
+    archived.push(("crate-information.json", crate_information.clone()));
+    written.push(crate_information);
+
+    if let Some(path) = cli.archive {
+        write_archive(&archived, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let Some(path) = cli.requirements_out {
+        write_requirements(&infos, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let Some(path) = cli.report {
+        let report = markdown_report(&infos);
+        create_parent_directory(&path)?;
+        fs::write(&path, report).map_err(TopCratesError::io(format!(
+            "Unable to write {}",
+            path.display()
+        )))?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let Some(path) = cli.resolved_crates_out {
+        write_json(&resolved_crates, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let (Some(path), Some(resolution_graph)) = (cli.resolution_out, resolution_graph) {
+        write_json(&resolution_graph, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let (Some(path), Some(feature_provenance)) = (cli.feature_provenance, feature_provenance) {
+        write_json(&feature_provenance, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let (Some(path), Some(feature_unification)) = (cli.feature_unification, feature_unification)
+    {
+        write_json(&feature_unification, &path)?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if let Some(path) = cli.cargo_resolve_out {
+        fs::write(&path, cargo_resolve).map_err(TopCratesError::io(format!(
+            "Unable to write {}",
+            path.display()
+        )))?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+
+    if cli.prune_redundant && !redundant_additions.is_empty() {
+        let names = redundant_additions.into_iter().collect();
+        for path in &cli.modifications {
+            prune_additions(path, &names)?;
+        }
+    }
+
+    timings.since("write files", writing);
+    if cli.format == OutputFormat::Json {
+        RunSummary::new(&summary, &diagnostics, &written).print()?;
+    }
+    finish(&timings)
+}
+
+/// Removes the additions called one of `names` from the crate
+/// modifications file at `path`, leaving it alone if it has none.
+fn prune_additions(path: &Path, names: &BTreeSet<String>) -> Result<(), TopCratesError> {
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read crate modifications file {}",
+        path.display()
+    )))?;
+    let pruned = without_additions(&content, names)?;
+    if pruned != content {
+        write_atomically(path, pruned.as_bytes())?;
+        info!("Removed redundant additions from {}", path.display());
+    }
+    Ok(())
+}
+
+/// Prints how `manifest` differs from the one at `path` to `out`,
+/// returning whether there are any differences.
+fn print_manifest_diff(
+    manifest: &TomlManifest,
+    path: &Path,
+    out: &mut dyn Write,
+) -> Result<bool, TopCratesError> {
+    let old = match fs::read_to_string(path) {
+        Ok(content) => content.parse().map_err(TopCratesError::toml_parse(format!(
+            "Unable to parse {}",
+            path.display()
+        )))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Value::Table(Default::default()),
+        Err(e) => {
+            return Err(TopCratesError::io(format!(
+                "Unable to read {}",
+                path.display()
+            ))(e))
+        }
+    };
+    let new = toml::Value::try_from(manifest)
+        .map_err(TopCratesError::toml_serialize("Couldn't serialize TOML"))?;
+
+    let diff = manifest_diff(&old, &new);
+    if diff.is_empty() {
+        return Ok(false);
+    }
+    writeln!(out, "--- {}", path.display())
+        .and_then(|_| writeln!(out, "+++ {} (generated)", path.display()))
+        .and_then(|_| write!(out, "{}", diff))
+        .map_err(TopCratesError::io("Couldn't print the difference"))?;
+    Ok(true)
+}
+
+/// Prints which crates changed since the `crate-information.json` at
+/// `path` was written to `out`.
+fn print_changelog(
+    infos: &[CrateInformation],
+    path: &Path,
+    out: &mut dyn Write,
+) -> Result<(), TopCratesError> {
+    #[derive(Deserialize)]
+    struct Previous {
+        name: String,
+        version: Version,
+    }
+
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let previous: Vec<Previous> = serde_json::from_str(&content).map_err(TopCratesError::json(
+        format!("Unable to parse {}", path.display()),
+    ))?;
+
+    let changelog = crate_changelog(
+        previous.into_iter().map(|p| (p.name, p.version)),
+        infos
+            .iter()
+            .map(|info| (info.name.clone(), info.version.clone())),
+    );
+    let printed = if changelog.is_empty() {
+        writeln!(out, "No crates changed since {}", path.display())
+    } else {
+        writeln!(out, "Changes since {}:", path.display())
+            .and_then(|_| write!(out, "{}", changelog))
+    };
+    printed.map_err(TopCratesError::io("Couldn't print the changes"))
+}
+
+fn serialize_manifest(manifest: &impl Serialize) -> Result<Vec<u8>, TopCratesError> {
+    toml::to_vec(manifest).map_err(TopCratesError::toml_serialize("Couldn't serialize TOML"))
+}
+
+/// Makes sure the directory `path` will be written into exists.
+fn create_parent_directory(path: &Path) -> Result<(), TopCratesError> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent).map_err(
+            TopCratesError::io(format!("Unable to create {}", parent.display())),
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn write_manifest(manifest: &impl Serialize, path: impl AsRef<Path>) -> Result<(), TopCratesError> {
+    let path = path.as_ref();
+    let content = serialize_manifest(manifest)?;
+    create_parent_directory(path)?;
+    write_atomically(path, &content)
+}
+
+/// Writes `content` to a temporary file next to `path` and then
+/// renames it over `path`, so that an interrupted run never leaves a
+/// partly written file behind.
+///
+/// Both the file and the rename are synced to disk before returning,
+/// so a crash right afterwards leaves either the old file or the new
+/// one. The new file keeps the permissions of the one it replaces.
+fn write_atomically(path: &Path, content: &[u8]) -> Result<(), TopCratesError> {
+    let context = || format!("Unable to write {}", path.display());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file =
+        tempfile::NamedTempFile::new_in(directory).map_err(TopCratesError::io(format!(
+            "Unable to create a temporary file in {}",
+            directory.display()
+        )))?;
+    file.write_all(content)
+        .map_err(TopCratesError::io(context()))?;
+
+    // Temporary files are only readable by their owner, unlike what
+    // `fs::write` would have created.
+    let permissions = match fs::metadata(path) {
+        Ok(existing) => Some(existing.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => new_file_permissions(),
+        Err(e) => return Err(TopCratesError::io(context())(e)),
+    };
+    if let Some(permissions) = permissions {
+        file.as_file()
+            .set_permissions(permissions)
+            .map_err(TopCratesError::io(context()))?;
+    }
+
+    file.as_file()
+        .sync_all()
+        .map_err(TopCratesError::io(context()))?;
+    file.persist(path)
+        .map_err(|e| TopCratesError::io(context())(e.error))?;
+
+    // The rename is only durable once the directory is synced, which
+    // can't be done on Windows.
+    #[cfg(unix)]
+    fs::File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .map_err(TopCratesError::io(format!(
+            "Unable to sync {}",
+            directory.display()
+        )))?;
+    Ok(())
+}
+
+/// The permissions `fs::write` gives a new file under the usual umask.
+#[cfg(unix)]
+fn new_file_permissions() -> Option<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(fs::Permissions::from_mode(0o644))
+}
+
+#[cfg(not(unix))]
+fn new_file_permissions() -> Option<fs::Permissions> {
+    None
+}
+
+/// Has cargo read `manifest` as the manifest of a package of its own.
+fn validate_manifest(manifest: &TomlManifest) -> Result<(), TopCratesError> {
+    let dir = tempfile::tempdir().map_err(TopCratesError::io(
+        "Unable to create a directory to validate the manifest in",
+    ))?;
+    let src = dir.path().join("src");
+    fs::create_dir(&src)
+        .and_then(|_| fs::write(src.join("main.rs"), "fn main() {}\n"))
+        .map_err(TopCratesError::io(
+            "Unable to create a package to validate the manifest in",
+        ))?;
+    let cargo_toml = dir.path().join("Cargo.toml");
+    write_manifest(manifest, &cargo_toml)?;
+
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = process::Command::new(cargo)
+        .args([
+            "metadata",
+            "--no-deps",
+            "--format-version",
+            "1",
+            "--manifest-path",
+        ])
+        .arg(&cargo_toml)
+        .output()
+        .map_err(TopCratesError::io("Unable to run cargo metadata"))?;
+    if !output.status.success() {
+        return Err(TopCratesError::InvalidManifest {
+            message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Writes the root of the workspace to `path` along with a member
+/// package next to it for each dependency of `manifest`.
+fn write_workspace(manifest: TomlManifest, path: &Path) -> Result<(), TopCratesError> {
+    let base_directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut members = Vec::new();
+
+    let targeted = manifest.target.into_iter().flat_map(|(cfg, section)| {
+        section
+            .dependencies
+            .into_iter()
+            .map(move |(exposed_name, spec)| (exposed_name, spec, Some(cfg.clone())))
+    });
+    let dependencies = manifest
+        .dependencies
+        .into_iter()
+        .map(|(exposed_name, spec)| (exposed_name, spec, None))
+        .chain(targeted);
+
+    for (exposed_name, spec, cfg) in dependencies {
+        let member = format!("members/{}", exposed_name);
+        let member_directory = base_directory.join(&member);
+        let src_directory = member_directory.join("src");
+        fs::create_dir_all(&src_directory).map_err(TopCratesError::io(format!(
+            "Unable to create {}",
+            src_directory.display()
+        )))?;
+
+        let dependencies = BTreeMap::from([(exposed_name.clone(), spec)]);
+        let section = TomlTarget {
+            dependencies: dependencies.clone(),
+            build_dependencies: dependencies,
+        };
+        // Targeted crates keep their `cfg` in the member that uses them.
+        let (section, target) = match cfg {
+            Some(cfg) => (TomlTarget::default(), BTreeMap::from([(cfg, section)])),
+            None => (section, BTreeMap::new()),
+        };
+        let member_manifest = TomlMemberManifest {
+            package: TomlMemberPackage {
+                name: format!("{}_{}", manifest.package.name, exposed_name),
+                version: manifest.package.version.clone(),
+                publish: false,
+            },
+            dependencies: section.dependencies,
+            build_dependencies: section.build_dependencies,
+            target,
+        };
+        write_manifest(&member_manifest, member_directory.join("Cargo.toml"))?;
+
+        let lib_rs = src_directory.join("lib.rs");
+        fs::write(&lib_rs, "").map_err(TopCratesError::io(format!(
+            "Unable to write {}",
+            lib_rs.display()
+        )))?;
+
+        members.push(member);
+    }
+
+    let workspace_manifest = TomlWorkspaceManifest {
+        workspace: TomlWorkspace {
+            members,
+            resolver: manifest.package.resolver,
+        },
+        profile: manifest.profile,
+        patch: manifest.patch,
+    };
+    write_manifest(&workspace_manifest, path)
+}
+
+/// Prints every problem with the `crate-information.json` at `path`,
+/// exiting with [`VALIDATION_EXIT_CODE`] if there are any.
+fn check(path: &Path) -> Result<(), TopCratesError> {
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let value: serde_json::Value = serde_json::from_str(&content).map_err(TopCratesError::json(
+        format!("Unable to parse {}", path.display()),
+    ))?;
+
+    let errors = check_crate_information(&value);
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}: {}", path.display(), error);
+        }
+        eprintln!("Found {} problems in {}", errors.len(), path.display());
+        process::exit(VALIDATION_EXIT_CODE);
+    }
+    let entries = value.as_array().map_or(0, Vec::len);
+    println!("{} crates in {} are valid", entries, path.display());
+    Ok(())
+}
+
+/// The lines of the crate list at `path`, or of stdin when it is `-`.
+fn read_crate_list(path: &Path) -> Result<Vec<String>, TopCratesError> {
+    if path == Path::new("-") {
+        return io::stdin()
+            .lock()
+            .lines()
+            .collect::<Result<_, _>>()
+            .map_err(TopCratesError::io(
+                "Unable to read the crate list from stdin",
+            ));
+    }
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    Ok(content.lines().map(str::to_owned).collect())
+}
+
+/// Reads each of the crate modifications files, layering later ones
+/// over earlier ones.
+fn read_modifications(paths: &[PathBuf]) -> Result<Modifications, TopCratesError> {
+    let mut modifications = Modifications::default();
+    for path in paths {
+        let d = fs::read(path).map_err(TopCratesError::io(format!(
+            "Unable to read crate modifications file {}",
+            path.display()
+        )))?;
+        let file: Modifications =
+            toml::from_slice(&d).map_err(TopCratesError::toml_parse(format!(
+                "Unable to parse crate modifications file {}",
+                path.display()
+            )))?;
+        modifications = modifications.merge(file);
+    }
+    Ok(modifications)
+}
+
+/// The `[dependencies]` of a manifest, including those only for some
+/// targets.
+fn manifest_dependencies(path: &Path) -> Result<toml::value::Table, TopCratesError> {
+    let content = fs::read_to_string(path).map_err(TopCratesError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let manifest: toml::Value = content.parse().map_err(TopCratesError::toml_parse(format!(
+        "Unable to parse {}",
+        path.display()
+    )))?;
+
+    let targets = manifest
+        .get("target")
+        .and_then(|t| t.as_table())
+        .into_iter()
+        .flat_map(|targets| targets.values());
+    let dependencies = std::iter::once(&manifest)
+        .chain(targets)
+        .filter_map(|section| section.get("dependencies")?.as_table())
+        .flat_map(|dependencies| dependencies.clone())
+        .collect();
+    Ok(dependencies)
+}
+
+/// The name of the package a dependency refers to.
+fn dependency_package<'a>(name: &'a str, spec: &'a toml::Value) -> &'a str {
+    // Renamed dependencies name the real package separately.
+    spec.get("package").and_then(|p| p.as_str()).unwrap_or(name)
+}
+
+/// The names of the packages that a manifest depends on.
+fn base_manifest_crates(path: &Path) -> Result<BTreeSet<String>, TopCratesError> {
+    let crates = manifest_dependencies(path)?
+        .iter()
+        .map(|(name, spec)| dependency_package(name, spec).to_owned())
+        .collect();
+    Ok(crates)
+}
+
+/// The exact versions of each package that a manifest depends on, as
+/// written by `=x.y.z` requirements, oldest first. A package can be
+/// depended on more than once under different names, such as `rand`
+/// and `rand_0_7`. Other requirements are skipped.
+fn manifest_versions(path: &Path) -> Result<BTreeMap<String, Vec<Version>>, TopCratesError> {
+    let mut versions: BTreeMap<String, Vec<Version>> = BTreeMap::new();
+    for (name, spec) in &manifest_dependencies(path)? {
+        let requirement = match spec {
+            toml::Value::String(requirement) => Some(requirement.as_str()),
+            _ => spec.get("version").and_then(|v| v.as_str()),
+        };
+        let version = requirement
+            .and_then(|r| r.strip_prefix('='))
+            .and_then(|v| v.trim().parse().ok());
+        if let Some(version) = version {
+            versions
+                .entry(dependency_package(name, spec).to_owned())
+                .or_default()
+                .push(version);
+        }
+    }
+    for versions in versions.values_mut() {
+        versions.sort();
+    }
+    Ok(versions)
+}
+
+fn write_json(value: &impl Serialize, path: &Path) -> Result<(), TopCratesError> {
+    create_parent_directory(path)?;
+    let content = serde_json::to_vec_pretty(value).map_err(TopCratesError::json(format!(
+        "Unable to serialize {}",
+        path.display()
+    )))?;
+    write_atomically(path, &content)
+}
+
+/// Bundles the files that were just written into a gzip-compressed
+/// tarball at `path`, each under its name in `files`.
+///
+/// Every entry has the same owner, permissions, and modification time,
+/// so the archive only changes when the files do.
+fn write_archive(files: &[(&str, PathBuf)], path: &Path) -> Result<(), TopCratesError> {
+    let context = || format!("Unable to write {}", path.display());
+    let mut tarball = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (name, file) in files {
+        let content = fs::read(file).map_err(TopCratesError::io(format!(
+            "Unable to read {}",
+            file.display()
+        )))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        tarball
+            .append_data(&mut header, name, content.as_slice())
+            .map_err(TopCratesError::io(context()))?;
+    }
+    let content = tarball
+        .into_inner()
+        .and_then(|gz| gz.finish())
+        .map_err(TopCratesError::io(context()))?;
+
+    create_parent_directory(path)?;
+    write_atomically(path, &content)
+}
+
+fn write_requirements(infos: &[CrateInformation], path: &Path) -> Result<(), TopCratesError> {
+    let mut requirements = infos
+        .iter()
+        .map(|info| (&info.name, &info.version))
+        .collect::<Vec<_>>();
+    requirements.sort();
+
+    let mut content = String::new();
+    for (name, version) in requirements {
+        content += &format!("{} = \"{}\"\n", name, version);
+    }
+
+    create_parent_directory(path)?;
+    write_atomically(path, content.as_bytes())
+}
+
+fn print_trim_suggestion(suggestion: &TrimSuggestion) {
+    let TrimSuggestion {
+        target,
+        total,
+        remaining,
+        exclusions,
+    } = suggestion;
+
+    eprintln!("Heuristic suggestion to reduce {total} crates to at most {target}:");
+    if exclusions.is_empty() {
+        eprintln!("  nothing needs to be excluded");
+    }
+    for (name, removed) in exclusions {
+        eprintln!("  exclude {name} (removes {removed} crates)");
+    }
+    eprintln!("This would leave {remaining} crates");
+}
+
+fn print_explanation(explanation: &Explanation) {
+    if explanation.paths.is_empty() {
+        println!("{} is not among the resolved crates", explanation.name);
+    }
+    for path in &explanation.paths {
+        let path = path
+            .iter()
+            .map(|(name, version)| format!("{name} {version}"))
+            .collect::<Vec<_>>();
+        println!("{}", path.join(" -> "));
+    }
+}
+
+fn print_growth(growth: &[Growth]) {
+    if growth.is_empty() {
+        println!("Every crate is already in the manifest");
+        return;
+    }
+
+    for krate in growth {
+        println!(
+            "{:>5}  {} {}: {}",
+            krate.added.len(),
+            krate.name,
+            krate.version,
+            krate.added.join(", ")
+        );
+    }
+}
+
+fn print_outdated(outdated: &[OutdatedCrate]) {
+    if outdated.is_empty() {
+        println!("Every crate is at its newest version");
+        return;
+    }
+
+    let name_width = outdated
+        .iter()
+        .map(|krate| krate.name.len())
+        .chain(["crate".len()])
+        .max()
+        .unwrap_or(0);
+    let current_width = outdated
+        .iter()
+        .map(|krate| krate.current.to_string().len())
+        .chain(["current".len()])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:name_width$}  {:current_width$}  latest",
+        "crate", "current"
+    );
+    for krate in outdated {
+        println!(
+            "{:name_width$}  {:current_width$}  {}",
+            krate.name,
+            krate.current.to_string(),
+            krate.latest
+        );
+    }
+}
+
+fn print_msrv_matrix(resolved_crates: &[ResolvedCrate], rust_versions: &[Version]) {
+    eprintln!("Crates that declare a newer rust-version than each Rust version:");
+    for rust_version in rust_versions {
+        let excluded = msrv_exclusions(resolved_crates, rust_version);
+        let names = excluded
+            .iter()
+            .map(|krate| format!("{} {}", krate.name, krate.version))
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            eprintln!("  {rust_version}: 0 crates");
+        } else {
+            eprintln!(
+                "  {rust_version}: {} crates ({})",
+                names.len(),
+                names.join(", ")
+            );
+        }
+    }
+}
+
+fn check_feature_count(
+    dependencies: &BTreeMap<String, DependencySpec>,
+    max_features: usize,
+    diagnostics: &mut Diagnostics,
+) {
+    let counts = feature_counts(dependencies);
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    if total <= max_features {
+        return;
+    }
+
+    let mut message = format!(
+        "{total} features are enabled, but at most {max_features} are allowed. \
+         The crates enabling the most features are:"
+    );
+    for (exposed_name, count) in counts.iter().take(FEATURE_REPORT_LIMIT) {
+        message += &format!("\n  {exposed_name}: {count}");
+    }
+    diagnostics.error(message);
 }
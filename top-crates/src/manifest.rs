@@ -0,0 +1,645 @@
+use crate::{
+    DependencySpec, FeatureResolver, PackageSettings, ProfileModifications, ProfileOverride,
+    ProfileSettings,
+};
+use cargo::util::interning::InternedString;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The name `[patch]` uses for crates.io.
+const CRATES_IO_PATCH: &str = "crates-io";
+
+/// A Cargo.toml file.
+///
+/// Sections and keys are written in the order they are declared here,
+/// so reordering fields shows up as a diff in every generated manifest.
+#[derive(Debug, Serialize)]
+pub struct TomlManifest {
+    pub package: TomlPackage,
+    #[serde(skip_serializing_if = "Profiles::is_empty")]
+    pub profile: Profiles,
+    /// Features of the playground package itself.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(serialize_with = "toml::ser::tables_last")]
+    pub dependencies: BTreeMap<String, DependencySpec>,
+    #[serde(serialize_with = "toml::ser::tables_last")]
+    pub build_dependencies: BTreeMap<String, DependencySpec>,
+    /// Dependencies only used for some targets, keyed by the
+    /// `cfg(...)` expression of those targets.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub target: BTreeMap<String, TomlTarget>,
+    /// Replacements for crates, keyed by the registry they replace
+    /// crates of.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub patch: BTreeMap<String, BTreeMap<String, Patch>>,
+}
+
+/// The dependencies of a `[target.'cfg(...)']` section.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TomlTarget {
+    #[serde(serialize_with = "toml::ser::tables_last")]
+    pub dependencies: BTreeMap<String, DependencySpec>,
+    #[serde(serialize_with = "toml::ser::tables_last")]
+    pub build_dependencies: BTreeMap<String, DependencySpec>,
+}
+
+/// Where to build a crate from instead of its registry, such as to try
+/// out a fix that hasn't been released yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Patch {
+    Git {
+        git: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+    },
+    Path {
+        path: String,
+    },
+}
+
+/// Header of Cargo.toml file.
+#[derive(Debug, Serialize)]
+pub struct TomlPackage {
+    pub name: String,
+    pub version: String,
+    pub authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+}
+
+/// Profile used for build dependencies (build scripts, proc macros, and their
+/// dependencies).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildOverride {
+    pub codegen_units: u32,
+}
+
+/// A profile section in a Cargo.toml file
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    pub codegen_units: u32,
+    pub incremental: bool,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "opt_level")]
+    pub opt_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lto: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_override: Option<BuildOverride>,
+    /// Settings for single crates, keyed by crate name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub package: BTreeMap<String, PackageProfile>,
+}
+
+/// A `[profile.*.package."name"]` section, for one crate
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codegen_units: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incremental: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "opt_level")]
+    pub opt_level: Option<String>,
+}
+
+/// Cargo only accepts strings for the named levels, so the numbered
+/// ones are written as integers.
+fn opt_level<S>(level: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match level.as_deref().map(|level| level.parse::<u32>()) {
+        Some(Ok(level)) => serializer.serialize_u32(level),
+        _ => level.serialize(serializer),
+    }
+}
+
+/// The profile sections, keyed by profile name
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Profiles(pub BTreeMap<String, Profile>);
+
+impl Profiles {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl TomlManifest {
+    /// The playground's manifest, depending on each of `dependencies`
+    /// both normally and to build.
+    ///
+    /// The `package` settings replace the playground's name, version,
+    /// authors, and feature resolver, and add an edition and
+    /// description, while `features` become the package's own. Crates
+    /// in `patches` are built from their replacement instead of
+    /// crates.io, and those in `target_deps` are only depended on for
+    /// the targets their `cfg` expression matches. When `cargo_compat`
+    /// is set, anything cargo of that version does not understand is
+    /// left out.
+    pub fn playground(
+        mut dependencies: BTreeMap<String, DependencySpec>,
+        package: &PackageSettings,
+        features: &BTreeMap<String, Vec<String>>,
+        profile: &ProfileModifications,
+        patches: &BTreeMap<InternedString, Patch>,
+        target_deps: &BTreeMap<InternedString, String>,
+        cargo_compat: Option<&Version>,
+    ) -> TomlManifest {
+        let supports = |major, minor| {
+            cargo_compat.is_none_or(|compat| *compat >= Version::new(major, minor, 0))
+        };
+        let resolver =
+            FeatureResolver::written(package, cargo_compat).map(|r| r.as_str().to_owned());
+        let section = |settings: &ProfileSettings| Profile {
+            codegen_units: settings.codegen_units.unwrap_or(1),
+            incremental: settings.incremental.unwrap_or(false),
+            opt_level: settings.opt_level.clone(),
+            lto: settings.lto,
+            build_override: supports(1, 41).then_some(BuildOverride { codegen_units: 1 }),
+            package: BTreeMap::new(),
+        };
+        let profiles = [
+            ("dev", Some(&profile.dev)),
+            ("release", Some(&profile.release)),
+            ("test", profile.test.as_ref()),
+            ("bench", profile.bench.as_ref()),
+        ]
+        .iter()
+        .copied()
+        .filter(|(name, _)| !profile.omit.contains(*name))
+        .filter_map(|(name, settings)| Some((name.to_owned(), section(settings?))))
+        .collect();
+        let mut patch = BTreeMap::new();
+        if !patches.is_empty() {
+            let crates_io = patches
+                .iter()
+                .map(|(name, patch)| (name.to_string(), patch.clone()))
+                .collect();
+            patch.insert(CRATES_IO_PATCH.to_owned(), crates_io);
+        }
+        let mut target = BTreeMap::<String, TomlTarget>::new();
+        dependencies.retain(|exposed_name, spec| {
+            let package = if spec.package.is_empty() {
+                exposed_name
+            } else {
+                &spec.package
+            };
+            let cfg = match target_deps.get(package.as_str()) {
+                Some(cfg) => cfg_key(cfg),
+                None => return true,
+            };
+            let section = target.entry(cfg).or_default();
+            section
+                .dependencies
+                .insert(exposed_name.clone(), spec.clone());
+            section
+                .build_dependencies
+                .insert(exposed_name.clone(), spec.clone());
+            false
+        });
+
+        TomlManifest {
+            package: TomlPackage {
+                name: package
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| "playground".to_owned()),
+                version: package
+                    .version
+                    .as_ref()
+                    .map_or_else(|| "0.0.1".to_owned(), Version::to_string),
+                authors: package
+                    .authors
+                    .clone()
+                    .unwrap_or_else(|| vec!["The Rust Playground".to_owned()]),
+                edition: package.edition.clone().filter(|_| supports(1, 31)),
+                description: package.description.clone(),
+                resolver,
+            },
+            profile: Profiles(profiles),
+            features: features.clone(),
+            dependencies: dependencies.clone(),
+            build_dependencies: dependencies,
+            target,
+            patch,
+        }
+    }
+
+    /// Gives each crate of `overrides` its own settings in every
+    /// profile. Only cargo 1.41 and later understand these.
+    pub fn override_profiles(&mut self, overrides: &BTreeMap<InternedString, ProfileOverride>) {
+        for profile in self.profile.0.values_mut() {
+            profile.package = overrides
+                .iter()
+                .map(|(name, settings)| {
+                    let package = PackageProfile {
+                        codegen_units: settings.codegen_units,
+                        incremental: settings.incremental,
+                        opt_level: settings.opt_level.clone(),
+                    };
+                    (name.to_string(), package)
+                })
+                .collect();
+        }
+    }
+
+    /// Keeps only the dependencies, normal and build, whose exposed
+    /// name `keep` accepts.
+    pub fn retain_dependencies(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.dependencies.retain(|name, _| keep(name));
+        self.build_dependencies.retain(|name, _| keep(name));
+        for section in self.target.values_mut() {
+            section.dependencies.retain(|name, _| keep(name));
+            section.build_dependencies.retain(|name, _| keep(name));
+        }
+        self.target
+            .retain(|_, section| !section.dependencies.is_empty());
+    }
+
+    /// Every dependency, whether or not it is only for some targets.
+    pub fn all_dependencies(&self) -> BTreeMap<String, DependencySpec> {
+        let targeted = self
+            .target
+            .values()
+            .flat_map(|section| section.dependencies.clone());
+        self.dependencies
+            .clone()
+            .into_iter()
+            .chain(targeted)
+            .collect()
+    }
+}
+
+/// The key of the `[target]` section for `cfg`, which may be written
+/// either as a bare predicate such as `unix` or already wrapped, as in
+/// `cfg(unix)`.
+fn cfg_key(cfg: &str) -> String {
+    let cfg = cfg.trim();
+    if cfg.starts_with("cfg(") {
+        cfg.to_owned()
+    } else {
+        format!("cfg({})", cfg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs, path::Path};
+
+    #[test]
+    fn old_cargo_compatibility() {
+        let dependencies = BTreeMap::from([(
+            "rand".to_owned(),
+            DependencySpec {
+                package: "rand".to_owned(),
+                version: Version::new(0, 8, 5),
+                features: Default::default(),
+                default_features: true,
+            },
+        )]);
+        let profile = ProfileModifications {
+            release: ProfileSettings {
+                opt_level: Some("3".to_owned()),
+                ..ProfileSettings::default()
+            },
+            ..ProfileModifications::default()
+        };
+
+        let patches = BTreeMap::new();
+        let package = PackageSettings::default();
+        let manifest = TomlManifest::playground(
+            dependencies.clone(),
+            &package,
+            &BTreeMap::new(),
+            &profile,
+            &patches,
+            &BTreeMap::new(),
+            None,
+        );
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.contains("name = \"playground\"\nversion = \"0.0.1\""));
+        assert!(manifest.contains("resolver = \"2\""));
+        assert!(manifest.contains("[profile.dev.build-override]"));
+        assert!(manifest.contains("opt-level = 3\n"));
+        assert!(manifest.contains("[build_dependencies.rand]"));
+        assert!(!manifest.contains("patch"));
+        assert!(!manifest.contains("[features]"));
+
+        let cargo_1_40 = Version::new(1, 40, 0);
+        let manifest = TomlManifest::playground(
+            dependencies,
+            &package,
+            &BTreeMap::new(),
+            &profile,
+            &patches,
+            &BTreeMap::new(),
+            Some(&cargo_1_40),
+        );
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(!manifest.contains("resolver"));
+        assert!(!manifest.contains("build-override"));
+    }
+
+    #[test]
+    fn chosen_profiles() {
+        let profile: ProfileModifications = toml::from_str(
+            r#"
+            omit = ["dev"]
+
+            [bench]
+            lto = true
+            "#,
+        )
+        .unwrap();
+        let manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &PackageSettings::default(),
+            &BTreeMap::new(),
+            &profile,
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        );
+        let profiles = manifest.profile.0.keys().collect::<Vec<_>>();
+        assert_eq!(profiles, ["bench", "release"]);
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.contains("[profile.bench]\ncodegen-units = 1"));
+        assert!(!manifest.contains("[profile.dev]"));
+
+        let profile = ProfileModifications {
+            omit: ["dev".to_owned(), "release".to_owned()].into(),
+            ..ProfileModifications::default()
+        };
+        let manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &PackageSettings::default(),
+            &BTreeMap::new(),
+            &profile,
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        );
+        assert!(!toml::to_string(&manifest).unwrap().contains("profile"));
+    }
+
+    #[test]
+    fn feature_resolver() {
+        let package: PackageSettings = toml::from_str(r#"resolver = "1""#).unwrap();
+        let manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &package,
+            &BTreeMap::new(),
+            &ProfileModifications::default(),
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        );
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.contains("resolver = \"1\""));
+
+        assert!(toml::from_str::<PackageSettings>(r#"resolver = "3""#).is_err());
+    }
+
+    #[test]
+    fn package_metadata() {
+        let package: PackageSettings = toml::from_str(
+            r#"
+            authors = ["The Rust Playground", "A Fork"]
+            edition = "2021"
+            description = "Crates available on the playground"
+            "#,
+        )
+        .unwrap();
+        let playground = |package: &PackageSettings| {
+            let manifest = TomlManifest::playground(
+                BTreeMap::new(),
+                package,
+                &BTreeMap::new(),
+                &ProfileModifications::default(),
+                &BTreeMap::new(),
+                &BTreeMap::new(),
+                None,
+            );
+            toml::to_string(&manifest).unwrap()
+        };
+
+        let manifest = playground(&package);
+        assert!(manifest.contains("authors = [\"The Rust Playground\", \"A Fork\"]\n"));
+        assert!(manifest.contains("edition = \"2021\"\n"));
+        assert!(manifest.contains("description = \"Crates available on the playground\"\n"));
+
+        let manifest = playground(&PackageSettings::default());
+        assert!(manifest.contains("authors = [\"The Rust Playground\"]\n"));
+        assert!(!manifest.contains("edition"));
+        assert!(!manifest.contains("description"));
+    }
+
+    #[test]
+    fn patches() {
+        let patches: BTreeMap<InternedString, Patch> = toml::from_str(
+            r#"
+            rand = { git = "https://github.com/rust-random/rand", rev = "abc123" }
+            serde = { path = "../serde/serde" }
+            "#,
+        )
+        .unwrap();
+        let manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &PackageSettings::default(),
+            &BTreeMap::new(),
+            &ProfileModifications::default(),
+            &patches,
+            &BTreeMap::new(),
+            None,
+        );
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.contains(
+            "[patch.crates-io.rand]\n\
+             git = \"https://github.com/rust-random/rand\"\n\
+             rev = \"abc123\"\n"
+        ));
+        assert!(manifest.contains("[patch.crates-io.serde]\npath = \"../serde/serde\"\n"));
+
+        let invalid = toml::from_str::<BTreeMap<InternedString, Patch>>(r#"rand = { tag = "1" }"#);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn target_dependencies() {
+        let spec = |package: &str| DependencySpec {
+            package: package.to_owned(),
+            version: Version::new(1, 0, 0),
+            features: Default::default(),
+            default_features: true,
+        };
+        let dependencies = BTreeMap::from([
+            ("rand".to_owned(), spec("rand")),
+            ("nix".to_owned(), spec("nix")),
+            ("winapi".to_owned(), spec("winapi")),
+        ]);
+        let target_deps = BTreeMap::from([
+            ("nix".into(), "unix".to_owned()),
+            ("winapi".into(), "cfg(windows)".to_owned()),
+        ]);
+
+        let mut manifest = TomlManifest::playground(
+            dependencies,
+            &PackageSettings::default(),
+            &BTreeMap::new(),
+            &ProfileModifications::default(),
+            &BTreeMap::new(),
+            &target_deps,
+            None,
+        );
+        assert_eq!(manifest.dependencies.keys().collect::<Vec<_>>(), ["rand"]);
+        assert_eq!(manifest.all_dependencies().len(), 3);
+
+        let written: toml::Value = toml::to_string(&manifest).unwrap().parse().unwrap();
+        assert!(written["target"]["cfg(unix)"]["dependencies"]
+            .get("nix")
+            .is_some());
+        assert!(written["target"]["cfg(windows)"]["build_dependencies"]
+            .get("winapi")
+            .is_some());
+
+        manifest.retain_dependencies(|name| name != "nix");
+        assert_eq!(manifest.target.keys().collect::<Vec<_>>(), ["cfg(windows)"]);
+    }
+
+    #[test]
+    fn playground_features() {
+        let features = BTreeMap::from([("small".to_owned(), vec!["rand/small_rng".to_owned()])]);
+        let manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &PackageSettings::default(),
+            &features,
+            &ProfileModifications::default(),
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        );
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.contains("[features]\nsmall = [\"rand/small_rng\"]\n"));
+    }
+
+    /// Compares a manifest using every kind of section to the one in
+    /// `tests/golden`, so that a change in how the toml crate writes
+    /// it is noticed. Set `UPDATE_GOLDEN=1` to overwrite it instead.
+    #[test]
+    fn stable_serialization() {
+        let dependencies = BTreeMap::from([
+            (
+                "rand".to_owned(),
+                DependencySpec {
+                    package: "rand".to_owned(),
+                    version: Version::new(0, 8, 5),
+                    features: ["small_rng", "std"]
+                        .iter()
+                        .copied()
+                        .map(InternedString::new)
+                        .collect(),
+                    default_features: false,
+                },
+            ),
+            (
+                "serde1".to_owned(),
+                DependencySpec {
+                    package: "serde".to_owned(),
+                    version: Version::new(1, 0, 152),
+                    features: Default::default(),
+                    default_features: true,
+                },
+            ),
+        ]);
+        let package = PackageSettings {
+            edition: Some("2021".to_owned()),
+            description: Some("Crates available on the playground".to_owned()),
+            ..PackageSettings::default()
+        };
+        let features = BTreeMap::from([("small".to_owned(), vec!["rand/small_rng".to_owned()])]);
+        let profile = ProfileModifications {
+            release: ProfileSettings {
+                opt_level: Some("3".to_owned()),
+                lto: Some(true),
+                ..ProfileSettings::default()
+            },
+            test: Some(ProfileSettings {
+                codegen_units: Some(4),
+                ..ProfileSettings::default()
+            }),
+            ..ProfileModifications::default()
+        };
+        let patches = BTreeMap::from([(
+            "serde".into(),
+            Patch::Git {
+                git: "https://github.com/serde-rs/serde".to_owned(),
+                rev: Some("abc123".to_owned()),
+            },
+        )]);
+
+        let manifest = TomlManifest::playground(
+            dependencies,
+            &package,
+            &features,
+            &profile,
+            &patches,
+            &BTreeMap::new(),
+            None,
+        );
+        let manifest = String::from_utf8(toml::to_vec(&manifest).unwrap()).unwrap();
+
+        let golden =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/playground-manifest.toml");
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &manifest).unwrap();
+        }
+        let expected = fs::read_to_string(&golden).unwrap();
+        assert_eq!(
+            manifest, expected,
+            "The manifest differs from its golden file"
+        );
+    }
+
+    #[test]
+    fn profile_overrides() {
+        let overrides: BTreeMap<InternedString, ProfileOverride> = toml::from_str(
+            r#"
+            image = { opt-level = 3, codegen-units = 16 }
+            "#,
+        )
+        .unwrap();
+        let mut manifest = TomlManifest::playground(
+            BTreeMap::new(),
+            &PackageSettings::default(),
+            &BTreeMap::new(),
+            &ProfileModifications::default(),
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            None,
+        );
+        manifest.override_profiles(&overrides);
+
+        let manifest = toml::to_string(&manifest).unwrap();
+        for profile in ["dev", "release"] {
+            let section = format!(
+                "[profile.{}.package.image]\ncodegen-units = 16\nopt-level = 3\n",
+                profile
+            );
+            assert!(manifest.contains(&section), "{}", manifest);
+        }
+
+        let lto = toml::from_str::<ProfileOverride>("lto = true");
+        assert!(lto.is_err());
+    }
+}
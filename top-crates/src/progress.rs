@@ -0,0 +1,75 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, log_enabled, Level};
+use std::{
+    fmt,
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
+
+/// How often progress is logged when there's no terminal to draw a bar
+/// on, such as in CI.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shows how far through a long phase, such as querying the registry
+/// for every crate, generating is.
+///
+/// On a terminal this is a bar with the crate currently being worked
+/// on; otherwise a line is logged every [`LOG_INTERVAL`] so that logs
+/// stay readable. The bar is cleared when this is dropped, and is left
+/// out along with the logs when they don't include `info`.
+pub(crate) struct Progress {
+    what: &'static str,
+    total: usize,
+    done: usize,
+    bar: Option<ProgressBar>,
+    logged: Instant,
+}
+
+impl Progress {
+    pub(crate) fn new(what: &'static str, total: usize) -> Self {
+        let shown = io::stderr().is_terminal() && log_enabled!(Level::Info);
+        let bar = shown.then(|| {
+            let style = ProgressStyle::with_template("{prefix} [{bar:30}] {pos}/{len} {wide_msg}")
+                .expect("Progress template is invalid")
+                .progress_chars("=> ");
+            let bar = ProgressBar::new(total as u64).with_style(style);
+            bar.set_prefix(what);
+            bar
+        });
+        Progress {
+            what,
+            total,
+            done: 0,
+            bar,
+            logged: Instant::now(),
+        }
+    }
+
+    /// Shows `krate` as the one being worked on.
+    pub(crate) fn working_on(&self, krate: impl fmt::Display) {
+        if let Some(bar) = &self.bar {
+            bar.set_message(krate.to_string());
+        }
+    }
+
+    /// Counts one more crate as done.
+    pub(crate) fn done(&mut self) {
+        self.done += 1;
+        match &self.bar {
+            Some(bar) => bar.inc(1),
+            None if self.logged.elapsed() >= LOG_INTERVAL => {
+                info!("{}: {} of {}", self.what, self.done, self.total);
+                self.logged = Instant::now();
+            }
+            None => {}
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use cargo::{
+    core::{summary::FeatureMap, FeatureValue},
+    util::interning::InternedString,
+};
+use semver::Version;
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// Why a feature ended up enabled on a crate.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FeatureSource {
+    /// Requested by the crate's own playground metadata.
+    Metadata,
+    /// Part of the crate's default features.
+    Default,
+    /// Requested by a crate that depends on this one.
+    UnifiedBy(String),
+    /// Enabled by another enabled feature of the same crate.
+    ImpliedBy(InternedString),
+}
+
+impl fmt::Display for FeatureSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeatureSource::Metadata => "metadata".fmt(f),
+            FeatureSource::Default => "default".fmt(f),
+            FeatureSource::UnifiedBy(krate) => write!(f, "unified-by-{}", krate),
+            FeatureSource::ImpliedBy(feature) => write!(f, "implied-by-{}", feature),
+        }
+    }
+}
+
+impl Serialize for FeatureSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The reasons behind every feature enabled on one resolved crate.
+#[derive(Debug, Serialize)]
+pub struct FeatureProvenance {
+    pub name: String,
+    pub version: Version,
+    /// A feature without any sources is enabled for a reason that
+    /// could not be determined.
+    pub features: BTreeMap<InternedString, BTreeSet<FeatureSource>>,
+}
+
+/// The features enabled on a crate that other crates depend on, once
+/// every request for them has been unified.
+#[derive(Debug, Serialize)]
+pub struct FeatureUnification {
+    pub name: String,
+    pub version: Version,
+    pub features: BTreeSet<InternedString>,
+    /// The features each dependent crate asks for.
+    pub requested_by: BTreeMap<String, BTreeSet<InternedString>>,
+    /// The features that are only enabled because a crate's playground
+    /// metadata sets `all-features`, which are the most likely to be a
+    /// surprise.
+    pub from_all_features: BTreeSet<InternedString>,
+}
+
+/// The `enabled` features of a crate that come from `all-features`,
+/// either in its own metadata (`metadata` being what that asked for) or
+/// in that of a dependent that `expands_all_features`.
+///
+/// A feature that a dependent without `all-features` asks for as well
+/// would be enabled anyway, so it isn't counted.
+pub(crate) fn from_all_features(
+    enabled: &BTreeSet<InternedString>,
+    metadata: &BTreeSet<InternedString>,
+    all_features: bool,
+    dependents: &BTreeMap<String, BTreeSet<InternedString>>,
+    expands_all_features: impl Fn(&str) -> bool,
+) -> BTreeSet<InternedString> {
+    let requested_by = |feature: &InternedString, expanding: bool| {
+        dependents.iter().any(|(dependent, requested)| {
+            requested.contains(feature) && expands_all_features(dependent) == expanding
+        })
+    };
+    enabled
+        .iter()
+        .filter(|feature| {
+            ((all_features && metadata.contains(*feature)) || requested_by(feature, true))
+                && !requested_by(feature, false)
+        })
+        .copied()
+        .collect()
+}
+
+/// Works out where each of the `enabled` features of a crate came
+/// from.
+///
+/// `dependents` maps the name of each crate depending on this one to
+/// the features it asks for.
+pub(crate) fn explain(
+    declared: &FeatureMap,
+    enabled: &BTreeSet<InternedString>,
+    metadata: &BTreeSet<InternedString>,
+    dependents: &BTreeMap<String, BTreeSet<InternedString>>,
+) -> BTreeMap<InternedString, BTreeSet<FeatureSource>> {
+    let default = InternedString::new("default");
+    let implies = |feature: InternedString, implied: InternedString| {
+        declared
+            .get(&feature)
+            .is_some_and(|values| values.contains(&FeatureValue::Feature(implied)))
+    };
+
+    enabled
+        .iter()
+        .map(|&feature| {
+            let mut sources = BTreeSet::new();
+            if metadata.contains(&feature) {
+                sources.insert(FeatureSource::Metadata);
+            }
+            if feature == default || (enabled.contains(&default) && implies(default, feature)) {
+                sources.insert(FeatureSource::Default);
+            }
+            for (dependent, requested) in dependents {
+                if requested.contains(&feature) {
+                    sources.insert(FeatureSource::UnifiedBy(dependent.clone()));
+                }
+            }
+            for &other in enabled {
+                if other != default && other != feature && implies(other, feature) {
+                    sources.insert(FeatureSource::ImpliedBy(other));
+                }
+            }
+            (feature, sources)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn features(names: &[&str]) -> BTreeSet<InternedString> {
+        names.iter().map(|&name| name.into()).collect()
+    }
+
+    #[test]
+    fn every_source_is_recorded() {
+        let declared = FeatureMap::from([
+            ("default".into(), vec![FeatureValue::new("std".into())]),
+            ("std".into(), vec![]),
+            ("derive".into(), vec![FeatureValue::new("std".into())]),
+            ("rc".into(), vec![]),
+            ("unstable".into(), vec![]),
+        ]);
+        let enabled = features(&["default", "std", "derive", "rc", "unstable"]);
+        let metadata = features(&["rc"]);
+        let dependents = BTreeMap::from([("serde_json".to_owned(), features(&["derive"]))]);
+
+        let sources = explain(&declared, &enabled, &metadata, &dependents)
+            .into_iter()
+            .map(|(feature, sources)| {
+                let sources = sources.iter().map(ToString::to_string).collect::<Vec<_>>();
+                (feature.to_string(), sources.join(" "))
+            })
+            .collect::<Vec<_>>();
+
+        let expected = [
+            ("default", "default"),
+            ("derive", "unified-by-serde_json"),
+            ("rc", "metadata"),
+            ("std", "default implied-by-derive"),
+            ("unstable", ""),
+        ];
+        let expected = expected
+            .iter()
+            .map(|&(feature, sources)| (feature.to_owned(), sources.to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(sources, expected);
+    }
+
+    #[test]
+    fn features_from_all_features() {
+        let enabled = features(&["std", "serde", "rayon", "simd"]);
+        let dependents = BTreeMap::from([
+            ("everything".to_owned(), features(&["serde", "rayon"])),
+            ("picky".to_owned(), features(&["std", "serde"])),
+        ]);
+        let expands = |dependent: &str| dependent == "everything";
+
+        // picky asks for serde itself.
+        let metadata = features(&["simd"]);
+        assert_eq!(
+            from_all_features(&enabled, &metadata, false, &dependents, expands),
+            features(&["rayon"])
+        );
+        assert_eq!(
+            from_all_features(&enabled, &metadata, true, &dependents, expands),
+            features(&["rayon", "simd"])
+        );
+    }
+}
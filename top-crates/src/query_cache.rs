@@ -0,0 +1,82 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+/// Remembers the results of registry queries on disk so that repeated
+/// runs against the same registry index can skip them.
+///
+/// Every entry records the index revision it was computed from and
+/// is ignored once the index has moved on.
+#[derive(Debug)]
+pub(crate) struct QueryCache {
+    dir: PathBuf,
+    revision: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry<R, T> {
+    revision: R,
+    value: T,
+}
+
+impl QueryCache {
+    pub(crate) fn new(dir: impl Into<PathBuf>, revision: impl Into<String>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(QueryCache {
+            dir,
+            revision: revision.into(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Missing, unreadable, and outdated entries are all treated as
+    /// a cache miss.
+    pub(crate) fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let content = fs::read(self.path(key)).ok()?;
+        let entry: Entry<String, T> = serde_json::from_slice(&content).ok()?;
+        if entry.revision == self.revision {
+            Some(entry.value)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn put<T: Serialize>(&self, key: &str, value: &T) -> io::Result<()> {
+        let entry = Entry {
+            revision: &self.revision,
+            value,
+        };
+        let content = serde_json::to_vec(&entry)?;
+        fs::write(self.path(key), content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entries_are_tied_to_a_revision() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let cache = QueryCache::new(dir.path(), "aaaa").unwrap();
+        assert_eq!(cache.get::<Vec<u32>>("serde"), None);
+        cache.put("serde", &vec![1, 2, 3]).unwrap();
+        assert_eq!(cache.get::<Vec<u32>>("serde"), Some(vec![1, 2, 3]));
+
+        let cache = QueryCache::new(dir.path(), "bbbb").unwrap();
+        assert_eq!(cache.get::<Vec<u32>>("serde"), None);
+    }
+
+    #[test]
+    fn corrupt_entries_are_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("serde.json"), "{").unwrap();
+
+        let cache = QueryCache::new(dir.path(), "aaaa").unwrap();
+        assert_eq!(cache.get::<Vec<u32>>("serde"), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! Describes the included crates as Markdown, for release notes.
+
+use crate::{CrateInformation, CrateOrigin};
+use std::fmt::Write;
+
+/// Produces a Markdown document with a table of the curated additions
+/// followed by one of the rest of the crates.
+pub fn markdown_report(infos: &[CrateInformation]) -> String {
+    let (curated, ranked): (Vec<_>, Vec<_>) = infos
+        .iter()
+        .partition(|info| info.origin == Some(CrateOrigin::Curated));
+
+    let mut report = String::from("# Playground crates\n");
+    for (heading, infos) in [("Curated additions", curated), ("Most downloaded", ranked)] {
+        if infos.is_empty() {
+            continue;
+        }
+
+        writeln!(report, "\n## {}\n", heading).unwrap();
+        writeln!(report, "| Crate | Version | Exposed as | Features |").unwrap();
+        writeln!(report, "|-------|---------|------------|----------|").unwrap();
+        for info in infos {
+            writeln!(
+                report,
+                "| {} | {} | `{}` | {} |",
+                info.name,
+                info.version,
+                info.id,
+                features(info)
+            )
+            .unwrap();
+        }
+    }
+
+    report
+}
+
+fn features(info: &CrateInformation) -> String {
+    let mut features = info
+        .features
+        .iter()
+        .map(|feature| format!("`{}`", feature))
+        .collect::<Vec<_>>();
+    if info.default_features {
+        features.insert(0, "default".to_owned());
+    }
+    if features.is_empty() {
+        "none".to_owned()
+    } else {
+        features.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info(
+        name: &str,
+        version: &str,
+        id: &str,
+        origin: CrateOrigin,
+        features: &[&str],
+    ) -> CrateInformation {
+        CrateInformation {
+            name: name.to_owned(),
+            version: version.parse().unwrap(),
+            id: id.to_owned(),
+            origin: Some(origin),
+            created_at: None,
+            updated_at: None,
+            description: None,
+            repository: None,
+            license: None,
+            edition: None,
+            features: features.iter().map(|f| f.to_string()).collect(),
+            default_features: !features.is_empty(),
+        }
+    }
+
+    #[test]
+    fn additions_are_listed_separately() {
+        let infos = [
+            info(
+                "async-trait",
+                "0.1.61",
+                "async_trait",
+                CrateOrigin::Curated,
+                &[],
+            ),
+            info("serde", "1.0.152", "serde", CrateOrigin::Top, &["derive"]),
+        ];
+
+        assert_eq!(
+            markdown_report(&infos),
+            "# Playground crates\n\
+             \n\
+             ## Curated additions\n\
+             \n\
+             | Crate | Version | Exposed as | Features |\n\
+             |-------|---------|------------|----------|\n\
+             | async-trait | 0.1.61 | `async_trait` | none |\n\
+             \n\
+             ## Most downloaded\n\
+             \n\
+             | Crate | Version | Exposed as | Features |\n\
+             |-------|---------|------------|----------|\n\
+             | serde | 1.0.152 | `serde` | default, `derive` |\n"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use cargo::core::{resolver::Resolve, PackageId};
+use itertools::Itertools;
+use semver::Version;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// One package of the full resolve, with the packages it depends on.
+///
+/// Every package that cargo resolved is listed, including those that
+/// are only needed on other platforms or by build scripts (unless
+/// those are asked to be left out), so two snapshots can be diffed
+/// to see exactly which transitive crates changed. A package is
+/// referred to by its `id`, which is `name version` and only adds the
+/// source in parentheses when it is not the registry everything else
+/// comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResolvedPackage {
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    /// Such as `registry+https://github.com/rust-lang/crates.io-index`.
+    pub source: String,
+    /// The `id` of each package this one depends on, sorted.
+    pub dependencies: Vec<String>,
+}
+
+/// Every package of `resolve` besides those in `left_out`, sorted by
+/// `id`. Nothing is listed as depending on the packages left out.
+pub(crate) fn graph(resolve: &Resolve, left_out: &BTreeSet<PackageId>) -> Vec<ResolvedPackage> {
+    // The source that most packages come from isn't repeated in ids.
+    let common_source = resolve
+        .iter()
+        .map(|id| id.source_id())
+        .counts()
+        .into_iter()
+        .max_by_key(|&(source, count)| (count, source))
+        .map(|(source, _)| source);
+    let package_id = |id: PackageId| {
+        if Some(id.source_id()) == common_source {
+            format!("{} {}", id.name(), id.version())
+        } else {
+            format!(
+                "{} {} ({})",
+                id.name(),
+                id.version(),
+                id.source_id().as_url()
+            )
+        }
+    };
+
+    resolve
+        .iter()
+        .filter(|id| !left_out.contains(id))
+        .map(|id| ResolvedPackage {
+            id: package_id(id),
+            name: id.name().to_string(),
+            version: id.version().clone(),
+            source: id.source_id().as_url().to_string(),
+            dependencies: resolve
+                .deps(id)
+                .filter(|(dep, _)| !left_out.contains(dep))
+                .map(|(dep, _)| package_id(dep))
+                .sorted()
+                .dedup()
+                .collect(),
+        })
+        .sorted_by(|a, b| a.id.cmp(&b.id))
+        .collect()
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+/// Retries operations that fail for reasons that may go away on their
+/// own, waiting exponentially longer (plus some jitter) each time.
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    /// How many times to retry after the first attempt fails.
+    retries: u32,
+    /// How long to wait before the first retry.
+    base_delay: Duration,
+    /// The longest wait that a failure may ask for before its retry.
+    max_requested_delay: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(retries: u32, max_requested_delay: Duration) -> Self {
+        Backoff {
+            retries,
+            base_delay: Duration::from_millis(500),
+            max_requested_delay,
+        }
+    }
+
+    /// Runs `op` until it succeeds, fails with an error that
+    /// `is_transient` rejects, or has been retried too often.
+    ///
+    /// `on_retry` is told about each failed attempt that will be
+    /// retried, counting from 1.
+    pub(crate) fn run<T, E>(
+        &self,
+        op: impl FnMut() -> Result<T, E>,
+        is_transient: impl Fn(&E) -> bool,
+        on_retry: impl FnMut(u32, &E),
+    ) -> Result<T, E> {
+        self.run_with_requested_delays(op, is_transient, |_| None, on_retry)
+    }
+
+    /// Like [`Backoff::run`], but when `requested_delay` says how long
+    /// a failure asked to be waited for, such as with a `Retry-After`
+    /// header, that is waited instead (up to the maximum).
+    pub(crate) fn run_with_requested_delays<T, E>(
+        &self,
+        mut op: impl FnMut() -> Result<T, E>,
+        is_transient: impl Fn(&E) -> bool,
+        requested_delay: impl Fn(&E) -> Option<Duration>,
+        mut on_retry: impl FnMut(u32, &E),
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e) if attempt <= self.retries && is_transient(&e) => {
+                    on_retry(attempt, &e);
+                    thread::sleep(self.delay(attempt, requested_delay(&e)));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn delay(&self, attempt: u32, requested: Option<Duration>) -> Duration {
+        if let Some(requested) = requested {
+            return requested.min(self.max_requested_delay);
+        }
+        let delay = self.base_delay * 2u32.saturating_pow(attempt - 1);
+        delay + jitter(delay / 2)
+    }
+}
+
+/// A random duration up to `max`, so that several runs started at the
+/// same time don't all retry in lockstep.
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backoff(retries: u32) -> Backoff {
+        Backoff {
+            retries,
+            base_delay: Duration::ZERO,
+            max_requested_delay: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn requested_delays_are_capped() {
+        let backoff = backoff(3);
+        assert_eq!(backoff.delay(1, None), Duration::ZERO);
+        assert_eq!(
+            backoff.delay(1, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            backoff.delay(1, Some(Duration::from_secs(3600))),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let mut results = vec![Ok(3), Err("timeout"), Err("timeout")];
+        let mut retried = Vec::new();
+
+        let result = backoff(3).run(
+            || results.pop().unwrap(),
+            |_| true,
+            |attempt, _| retried.push(attempt),
+        );
+        assert_eq!(result, Ok(3));
+        assert_eq!(retried, [1, 2]);
+    }
+
+    #[test]
+    fn gives_up() {
+        let mut attempts = 0;
+        let result: Result<(), _> = backoff(2).run(
+            || {
+                attempts += 1;
+                Err("timeout")
+            },
+            |_| true,
+            |_, _| {},
+        );
+        assert_eq!(result, Err("timeout"));
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = backoff(2).run(
+            || {
+                attempts += 1;
+                Err("not found")
+            },
+            |e| *e == "timeout",
+            |_, _| {},
+        );
+        assert_eq!(result, Err("not found"));
+        assert_eq!(attempts, 1);
+    }
+}
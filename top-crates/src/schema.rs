@@ -0,0 +1,168 @@
+//! Checks JSON output against the schemas published alongside it.
+//!
+//! Only the parts of JSON Schema that those schemas use are
+//! supported: `type`, `properties`, `required`,
+//! `additionalProperties` (as a boolean), `items`, `enum` and
+//! `pattern`.
+
+use regex::Regex;
+use serde_json::Value;
+
+/// The schema of `crate-information.json`.
+pub const CRATE_INFORMATION_SCHEMA: &str = include_str!("../crate-information.schema.json");
+
+/// Returns a description of every place `value` does not match
+/// `schema`.
+pub fn validate(schema: &str, value: &Value) -> Vec<String> {
+    let schema: Value = serde_json::from_str(schema).expect("Schema is not valid JSON");
+    let mut errors = Vec::new();
+    check(&schema, value, "", &mut errors);
+    errors
+}
+
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let location = if path.is_empty() { "/" } else { path };
+
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if !has_type(value, expected) {
+            errors.push(format!(
+                "{}: expected {}, found {}",
+                location, expected, value
+            ));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{}: {} is not one of {}",
+                location,
+                value,
+                Value::Array(allowed.clone())
+            ));
+        }
+    }
+
+    if let (Some(pattern), Some(s)) = (
+        schema.get("pattern").and_then(Value::as_str),
+        value.as_str(),
+    ) {
+        let pattern = Regex::new(pattern).expect("Schema has an invalid pattern");
+        if !pattern.is_match(s) {
+            errors.push(format!(
+                "{}: `{}` does not match `{}`",
+                location, s, pattern
+            ));
+        }
+    }
+
+    if let (Some(items), Some(elements)) = (schema.get("items"), value.as_array()) {
+        for (i, element) in elements.iter().enumerate() {
+            check(items, element, &format!("{}/{}", path, i), errors);
+        }
+    }
+
+    if let Some(fields) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        let required = schema.get("required").and_then(Value::as_array);
+        for name in required.into_iter().flatten().filter_map(Value::as_str) {
+            if !fields.contains_key(name) {
+                errors.push(format!("{}: missing required field `{}`", location, name));
+            }
+        }
+
+        let additional_allowed = schema.get("additionalProperties") != Some(&Value::Bool(false));
+        for (name, field) in fields {
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property) => check(property, field, &format!("{}/{}", path, name), errors),
+                None if !additional_allowed => {
+                    errors.push(format!("{}: unexpected field `{}`", location, name))
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "array" => value.is_array(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "object" => value.is_object(),
+        "string" => value.is_string(),
+        _ => panic!("Schema uses unknown type `{}`", expected),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn crate_information_conforms() {
+        let infos = json!([
+            {
+                "name": "serde",
+                "version": "1.0.152",
+                "id": "serde",
+                "features": [],
+                "default_features": true,
+            },
+            {
+                "name": "rand",
+                "version": "0.8.5+build",
+                "id": "rand",
+                "created_at": "2015-02-03T06:17:14.169972+00:00",
+                "updated_at": "2022-02-14T00:26:26.239277+00:00",
+                "description": "Random number generators and other randomness functionality.",
+                "repository": "https://github.com/rust-random/rand",
+                "license": "MIT OR Apache-2.0",
+                "features": ["small_rng"],
+                "default_features": true,
+            },
+        ]);
+        assert_eq!(
+            validate(CRATE_INFORMATION_SCHEMA, &infos),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn crate_information_mistakes() {
+        let infos = json!([
+            {
+                "name": "serde",
+                "version": "1.0",
+                "crate_id": "serde",
+                "features": [],
+                "default_features": true,
+            },
+            {
+                "name": "rand",
+                "version": "0.8.5",
+                "id": 1,
+                "origin": "popular",
+                "features": [],
+                "default_features": "yes",
+            },
+        ]);
+        assert_eq!(
+            validate(CRATE_INFORMATION_SCHEMA, &infos),
+            [
+                "/0: missing required field `id`",
+                "/0: unexpected field `crate_id`",
+                "/0/version: `1.0` does not match \
+                 `^(0|[1-9][0-9]*)\\.(0|[1-9][0-9]*)\\.(0|[1-9][0-9]*)(-[0-9A-Za-z.-]+)?(\\+[0-9A-Za-z.-]+)?$`",
+                "/1/default_features: expected boolean, found \"yes\"",
+                "/1/id: expected string, found 1",
+                "/1/origin: \"popular\" is not one of [\"top\",\"curated\"]",
+            ]
+        );
+    }
+}
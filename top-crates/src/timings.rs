@@ -0,0 +1,97 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// How long each phase of generating the crate list took.
+///
+/// Phases that happen several times, such as resolving, are added up.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `duration` to the time spent in `phase`.
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    /// Adds the time since `start` to the time spent in `phase`.
+    pub fn since(&mut self, phase: &'static str, start: Instant) {
+        self.add(phase, start.elapsed());
+    }
+
+    /// Each phase, in the order it first happened.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        self.phases.iter().copied()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.iter().map(|(_, duration)| duration).sum()
+    }
+}
+
+/// A table of the phases, with how long each took in seconds and as
+/// a share of the total.
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        let width = self
+            .iter()
+            .map(|(phase, _)| phase.len())
+            .chain(Some("total".len()))
+            .max()
+            .unwrap_or_default();
+
+        for (phase, duration) in self.iter() {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * duration.as_secs_f64() / total.as_secs_f64()
+            };
+            writeln!(
+                f,
+                "{:width$}  {:>8.2}s  {:>5.1}%",
+                phase,
+                duration.as_secs_f64(),
+                share,
+                width = width,
+            )?;
+        }
+        write!(
+            f,
+            "{:width$}  {:>8.2}s",
+            "total",
+            total.as_secs_f64(),
+            width = width
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repeated_phases_are_added_up() {
+        let mut timings = Timings::new();
+        timings.add("resolve", Duration::from_millis(1500));
+        timings.add("download packages", Duration::from_millis(2000));
+        timings.add("resolve", Duration::from_millis(500));
+
+        assert_eq!(
+            timings.to_string(),
+            "resolve                2.00s   50.0%\n\
+             download packages      2.00s   50.0%\n\
+             total                  4.00s"
+        );
+    }
+}
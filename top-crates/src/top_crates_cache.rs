@@ -0,0 +1,149 @@
+use crate::{Crate, TopCrates, TopCratesSort};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Remembers the list of top crates downloaded from crates.io so that
+/// runs made shortly after one another don't need to download it again.
+///
+/// A listing is only used for the same API it was downloaded from, so
+/// pointing `--api` at a mirror or a test server doesn't pick up the
+/// crates of another.
+#[derive(Debug)]
+pub(crate) struct TopCratesCache {
+    path: PathBuf,
+    api: String,
+    ttl: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Listing<C> {
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    /// How many crates were asked for.
+    count: usize,
+    /// How crates.io was asked to order them. Listings remembered
+    /// before this was recorded were ordered by downloads.
+    #[serde(default = "default_sort")]
+    sort: String,
+    /// The API the crates were listed by. Listings remembered before
+    /// this was recorded aren't used for any.
+    #[serde(default)]
+    api: String,
+    crates: C,
+}
+
+fn default_sort() -> String {
+    TopCratesSort::Downloads.as_str().to_owned()
+}
+
+impl TopCratesCache {
+    pub(crate) fn new(path: impl Into<PathBuf>, api: impl Into<String>, ttl: Duration) -> Self {
+        TopCratesCache {
+            path: path.into(),
+            api: api.into(),
+            ttl,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// The `count` top crates by `sort`, if at least that many were
+    /// downloaded in that order from the same API recently enough.
+    ///
+    /// Missing, unreadable, corrupt and outdated listings are all
+    /// treated as a cache miss.
+    pub(crate) fn get(&self, count: usize, sort: TopCratesSort) -> Option<TopCrates> {
+        self.get_at(count, sort, SystemTime::now())
+    }
+
+    fn get_at(&self, count: usize, sort: TopCratesSort, now: SystemTime) -> Option<TopCrates> {
+        let content = fs::read(&self.path).ok()?;
+        let listing: Listing<Vec<Crate>> = serde_json::from_slice(&content).ok()?;
+
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(listing.fetched_at);
+        let age = now.duration_since(fetched_at).unwrap_or_default();
+        if age > self.ttl
+            || listing.count < count
+            || listing.sort != sort.as_str()
+            || listing.api != self.api
+        {
+            return None;
+        }
+
+        let mut crates = listing.crates;
+        crates.truncate(count);
+        Some(TopCrates { crates })
+    }
+
+    pub(crate) fn put(&self, count: usize, sort: TopCratesSort, top: &TopCrates) -> io::Result<()> {
+        let fetched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let listing = Listing {
+            fetched_at,
+            count,
+            sort: sort.as_str().to_owned(),
+            api: self.api.clone(),
+            crates: &top.crates,
+        };
+        let content = serde_json::to_vec(&listing)?;
+        fs::write(&self.path, content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cargo::util::interning::InternedString;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    const API: &str = "https://crates.io/api/v1";
+
+    fn names(top: Option<TopCrates>) -> Option<Vec<String>> {
+        top.map(|top| top.crates.iter().map(|c| c.name.to_string()).collect())
+    }
+
+    #[test]
+    fn listings_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = TopCratesCache::new(dir.path().join("top.json"), API, DAY);
+        assert!(cache.get(2, TopCratesSort::Downloads).is_none());
+
+        let top = TopCrates {
+            crates: ["serde", "rand", "libc"]
+                .map(|name| Crate::named(InternedString::new(name)))
+                .to_vec(),
+        };
+        cache.put(3, TopCratesSort::Downloads, &top).unwrap();
+
+        assert_eq!(
+            names(cache.get(2, TopCratesSort::Downloads)),
+            Some(vec!["serde".to_owned(), "rand".to_owned()])
+        );
+        assert!(cache.get(4, TopCratesSort::Downloads).is_none());
+        assert!(cache.get(2, TopCratesSort::RecentDownloads).is_none());
+
+        let later = SystemTime::now() + 2 * DAY;
+        assert!(cache.get_at(2, TopCratesSort::Downloads, later).is_none());
+
+        let mirror = TopCratesCache::new(cache.path(), "http://localhost:8080/api/v1", DAY);
+        assert!(mirror.get(2, TopCratesSort::Downloads).is_none());
+    }
+
+    #[test]
+    fn corrupt_listings_are_misses() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("top.json");
+        fs::write(&path, r#"{"fetched_at": 1, "count": 100, "crates": [{"#).unwrap();
+
+        let cache = TopCratesCache::new(path, API, DAY);
+        assert!(cache.get(1, TopCratesSort::Downloads).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A heuristic suggestion of which top-level crates to exclude to
+/// bring the total number of crates down to a target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrimSuggestion {
+    pub target: usize,
+    /// The number of crates before anything is excluded.
+    pub total: usize,
+    /// The number of crates after the suggested exclusions.
+    pub remaining: usize,
+    /// In the order they were chosen, each crate to exclude and how
+    /// many crates excluding it removed.
+    pub exclusions: Vec<(String, usize)>,
+}
+
+/// Greedily picks top-level crates to exclude, each time choosing the
+/// one that is the only user of the most crates.
+///
+/// `closures` maps each top-level crate to every crate it needs,
+/// including itself.
+pub(crate) fn suggest<T: Ord>(
+    closures: &BTreeMap<String, BTreeSet<T>>,
+    target: usize,
+) -> TrimSuggestion {
+    let mut users: BTreeMap<&T, usize> = BTreeMap::new();
+    for closure in closures.values() {
+        for id in closure {
+            *users.entry(id).or_default() += 1;
+        }
+    }
+
+    let total = users.len();
+    let mut kept: BTreeSet<&str> = closures.keys().map(String::as_str).collect();
+    let mut exclusions = Vec::new();
+
+    while users.len() > target {
+        let unique_contribution = |name: &str| {
+            closures[name]
+                .iter()
+                .filter(|id| users.get(id) == Some(&1))
+                .count()
+        };
+
+        // Ties go to the alphabetically-first crate.
+        let best = kept
+            .iter()
+            .map(|&name| (unique_contribution(name), name))
+            .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(a.1)));
+
+        let (removed, name) = match best {
+            Some((removed, name)) if removed > 0 => (removed, name),
+            _ => break,
+        };
+
+        kept.remove(name);
+        for id in &closures[name] {
+            if let Some(count) = users.get_mut(id) {
+                *count -= 1;
+                if *count == 0 {
+                    users.remove(id);
+                }
+            }
+        }
+        exclusions.push((name.to_owned(), removed));
+    }
+
+    TrimSuggestion {
+        target,
+        total,
+        remaining: users.len(),
+        exclusions,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn closures(input: &[(&str, &[u32])]) -> BTreeMap<String, BTreeSet<u32>> {
+        input
+            .iter()
+            .map(|(name, ids)| (name.to_string(), ids.iter().copied().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn removes_the_largest_unique_contributor_first() {
+        let closures = closures(&[
+            ("small", &[1, 2]),
+            ("big", &[3, 4, 5, 6, 2]),
+            ("shared", &[7, 2]),
+        ]);
+
+        let suggestion = suggest(&closures, 4);
+        assert_eq!(suggestion.total, 7);
+        assert_eq!(suggestion.remaining, 3);
+        assert_eq!(suggestion.exclusions, [("big".to_owned(), 4)]);
+    }
+
+    #[test]
+    fn nothing_to_do_under_target() {
+        let closures = closures(&[("a", &[1]), ("b", &[2])]);
+
+        let suggestion = suggest(&closures, 10);
+        assert_eq!(suggestion.remaining, 2);
+        assert!(suggestion.exclusions.is_empty());
+    }
+}
@@ -0,0 +1,708 @@
+//! Runs the whole tool against a registry and web API that only exist
+//! on this machine, comparing what it writes to the files in `golden`.
+//!
+//! Set `UPDATE_GOLDEN=1` to overwrite the golden files with what is
+//! written instead.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    thread,
+};
+
+/// A crate published to the test registry.
+#[derive(Clone)]
+struct Published {
+    name: &'static str,
+    version: &'static str,
+    /// Each dependency's name and requirement.
+    dependencies: &'static [(&'static str, &'static str)],
+    features: &'static [(&'static str, &'static [&'static str])],
+    /// The `[package.metadata.playground]` table, if any.
+    metadata: &'static str,
+}
+
+const CRATES: &[Published] = &[
+    Published {
+        name: "alpha",
+        version: "1.2.0",
+        dependencies: &[],
+        features: &[("default", &["std"]), ("std", &[]), ("extra", &[])],
+        metadata: r#"features = ["extra"]"#,
+    },
+    Published {
+        name: "beta",
+        version: "0.2.1",
+        dependencies: &[("delta", "^0.1")],
+        features: &[],
+        metadata: "",
+    },
+    Published {
+        name: "delta",
+        version: "0.1.0",
+        dependencies: &[],
+        features: &[],
+        metadata: "",
+    },
+    Published {
+        name: "gamma",
+        version: "2.0.0",
+        dependencies: &[("alpha", "^1")],
+        features: &[],
+        metadata: "",
+    },
+    Published {
+        name: "epsilon-rs",
+        version: "0.3.0",
+        dependencies: &[],
+        features: &[],
+        metadata: "",
+    },
+];
+
+const TOP_CRATES: &str = r#"{
+    "crates": [
+        {"id": "alpha", "created_at": "2019-01-01T00:00:00Z", "downloads": 5000},
+        {"id": "beta", "created_at": "2020-01-01T00:00:00Z", "downloads": 4000}
+    ]
+}"#;
+
+const COOKBOOK: &str = r#"
+[package]
+name = "rust-cookbook"
+version = "1.0.0"
+
+[dependencies]
+gamma = "2"
+"#;
+
+const MODIFICATIONS: &str = r#"
+additions = ["epsilon-rs"]
+"#;
+
+#[test]
+fn matches_golden_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let output = generate(dir.path(), &index, &api, &["--deny-warnings"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for file in ["Cargo.toml", "crate-information.json"] {
+        let generated = fs::read_to_string(dir.path().join("out").join(file)).unwrap();
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(golden.join(file), &generated).unwrap();
+        }
+        let expected = fs::read_to_string(golden.join(file)).unwrap();
+        assert_eq!(generated, expected, "{} differs from its golden file", file);
+    }
+    assert!(dir.path().join("out/Cargo.lock").exists());
+}
+
+#[test]
+fn generation_is_deterministic() {
+    // The second run sees the index entries in another order and
+    // answers its queries one at a time, yet must write the same bytes.
+    let run = |crates: &[Published], args: &[&str]| {
+        let dir = tempfile::tempdir().unwrap();
+        let api = serve();
+        let index = publish(dir.path(), &api, crates);
+
+        fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+        let output = generate(dir.path(), &index, &api, args);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        ["Cargo.toml", "crate-information.json"]
+            .map(|file| fs::read(dir.path().join("out").join(file)).unwrap())
+    };
+
+    let mut crates = CRATES.to_vec();
+    crates.push(Published {
+        version: "1.1.0",
+        ..crates[0].clone()
+    });
+    let first = run(&crates, &[]);
+    crates.reverse();
+    let second = run(&crates, &["--jobs", "1"]);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn feature_resolvers_list_the_same_features() {
+    // Every crate is depended on with the features version 1 unifies,
+    // so version 2 enables the same ones for the playground's code.
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    let modifications = format!("{}\n[package]\nresolver = \"1\"\n", MODIFICATIONS);
+    fs::write(dir.path().join("crate-modifications.toml"), modifications).unwrap();
+    let output = generate(dir.path(), &index, &api, &["--deny-warnings"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let generated = fs::read_to_string(dir.path().join("out/crate-information.json")).unwrap();
+    let expected = fs::read_to_string(golden.join("crate-information.json")).unwrap();
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn pins_hold_against_dependents() {
+    // gamma would be happy with the newer alpha, but the pinned one is
+    // already a root, and cargo never resolves two semver-compatible
+    // versions of a crate, so gamma has to make do with it.
+    let mut crates = CRATES.to_vec();
+    crates.push(Published {
+        version: "1.3.0",
+        ..crates[0].clone()
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, &crates);
+
+    let modifications = format!("{}\n[pinned]\nalpha = \"=1.2.0\"\n", MODIFICATIONS);
+    fs::write(dir.path().join("crate-modifications.toml"), modifications).unwrap();
+    let output = generate(dir.path(), &index, &api, &["--deny-warnings"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let cargo_toml = fs::read_to_string(dir.path().join("out/Cargo.toml")).unwrap();
+    assert!(cargo_toml.contains("[dependencies.alpha]\npackage = \"alpha\"\nversion = \"=1.2.0\""));
+    let cargo_lock = fs::read_to_string(dir.path().join("out/Cargo.lock")).unwrap();
+    let locked: toml::Value = toml::from_str(&cargo_lock).unwrap();
+    let alpha = locked["package"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|package| package["name"].as_str() == Some("alpha"))
+        .map(|package| package["version"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(alpha, ["1.2.0"], "{}", cargo_lock);
+}
+
+#[test]
+fn unhonored_pins_name_their_dependents() {
+    // gamma needs alpha 1, so it is built too even though alpha is
+    // pinned to 2.
+    let mut crates = CRATES.to_vec();
+    crates.push(Published {
+        version: "2.0.0",
+        ..crates[0].clone()
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, &crates);
+
+    let modifications = format!("{}\n[pinned]\nalpha = \"=2.0.0\"\n", MODIFICATIONS);
+    fs::write(dir.path().join("crate-modifications.toml"), modifications).unwrap();
+    let output = generate(dir.path(), &index, &api, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let warning = stderr
+        .lines()
+        .find(|line| line.contains("Pinned to `=2.0.0`"))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    assert!(warning.contains("alpha v1.2.0"), "{}", warning);
+    assert!(warning.contains("but gamma v2.0.0"), "{}", warning);
+    assert!(
+        warning.ends_with("also needs 1.2.0, so that version is built too"),
+        "{}",
+        warning
+    );
+}
+
+#[test]
+fn index_revisions_are_reproduced() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+    let repo = git2::Repository::open(dir.path().join("index")).unwrap();
+    let rev = repo.head().unwrap().target().unwrap().to_string();
+
+    let mut crates = CRATES.to_vec();
+    crates.push(Published {
+        version: "1.3.0",
+        ..crates[0].clone()
+    });
+    publish(dir.path(), &api, &crates);
+    let newest = repo.head().unwrap().target().unwrap();
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let output = generate(
+        dir.path(),
+        &index,
+        &api,
+        &["--deny-warnings", "--index-rev", &rev],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let cargo_toml = fs::read_to_string(dir.path().join("out/Cargo.toml")).unwrap();
+    assert!(cargo_toml.contains("[dependencies.alpha]\npackage = \"alpha\"\nversion = \"=1.2.0\""));
+
+    // The shared index is only ever at the newest revision, for
+    // everything else that uses it.
+    let cached = fs::read_dir(dir.path().join("cargo-home/registry/index"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let cached = git2::Repository::open(cached.path()).unwrap();
+    let head = cached.refname_to_id("refs/remotes/origin/HEAD").unwrap();
+    assert_eq!(head, newest);
+
+    let output = generate(
+        dir.path(),
+        &index,
+        &api,
+        &[
+            "--offline",
+            "--index-rev",
+            "0000000000000000000000000000000000000000",
+        ],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn damaged_index_entries_are_read_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let output = generate(dir.path(), &index, &api, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Damage the cached copy of an entry along with the entry itself
+    // in the local index, so that reading it again doesn't help.
+    let cached = fs::read_dir(dir.path().join("cargo-home/registry/index"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    fs::write(cached.join(".cache/ep/si/epsilon-rs"), "damaged").unwrap();
+    let repo = git2::Repository::open(&cached).unwrap();
+    let head = repo.refname_to_id("refs/remotes/origin/HEAD").unwrap();
+    let entry = repo
+        .find_commit(head)
+        .unwrap()
+        .tree()
+        .unwrap()
+        .get_path(Path::new("ep/si/epsilon-rs"))
+        .unwrap()
+        .id();
+    damage_object(&cached, entry);
+
+    let output = generate(dir.path(), &index, &api, &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("epsilon-rs: Reading its registry index entry again"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("epsilon-rs: Left out because its registry index entry could not be read"),
+        "{}",
+        stderr
+    );
+    let cargo_toml = fs::read_to_string(dir.path().join("out/Cargo.toml")).unwrap();
+    assert!(!cargo_toml.contains("epsilon-rs"), "{}", cargo_toml);
+    assert!(
+        cargo_toml.contains("[dependencies.alpha]"),
+        "{}",
+        cargo_toml
+    );
+}
+
+#[test]
+fn bisect_growth_ranks_what_new_crates_add() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    let baseline = "[dependencies]\nalpha = \"=1.2.0\"\n";
+    fs::write(dir.path().join("out/Cargo.toml"), baseline).unwrap();
+    let output = generate(dir.path(), &index, &api, &["--bisect-growth"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // gamma only adds itself, as the alpha it needs is already there.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "    2  beta 0.2.1: beta 0.2.1, delta 0.1.0",
+            "    1  epsilon-rs 0.3.0: epsilon-rs 0.3.0",
+            "    1  gamma 2.0.0: gamma 2.0.0",
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/Cargo.toml")).unwrap(),
+        baseline
+    );
+}
+
+#[test]
+fn failed_downloads_can_be_skipped() {
+    // beta can't be built without delta, so it is left out as well.
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+    fs::remove_file(dir.path().join("crates/delta-0.1.0.crate")).unwrap();
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let output = generate(dir.path(), &index, &api, &[]);
+    assert!(!output.status.success());
+
+    let output = generate(dir.path(), &index, &api, &["--skip-failed-downloads"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for file in ["Cargo.toml", "crate-information.json"] {
+        let generated = fs::read_to_string(dir.path().join("out").join(file)).unwrap();
+        assert!(generated.contains("alpha"), "{}", generated);
+        assert!(!generated.contains("beta"), "{}", generated);
+        assert!(!generated.contains("delta"), "{}", generated);
+    }
+}
+
+#[test]
+fn offline_runs_need_allow_empty_to_leave_crates_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let cache = ["--top-crates-cache", "top-crates.json"];
+    let output = generate(dir.path(), &index, &api, &cache);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The cookbook's crates can't be listed offline.
+    let offline = [&cache[..], &["--offline"]].concat();
+    let output = generate(dir.path(), &index, &api, &offline);
+    assert_eq!(output.status.code(), Some(9));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Rust Cookbook"), "{}", stderr);
+
+    let allowed = [&offline[..], &["--allow-empty"]].concat();
+    let output = generate(dir.path(), &index, &api, &allowed);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn archive_bundles_the_written_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let api = serve();
+    let index = publish(dir.path(), &api, CRATES);
+
+    fs::write(dir.path().join("crate-modifications.toml"), MODIFICATIONS).unwrap();
+    let output = generate(
+        dir.path(),
+        &index,
+        &api,
+        &["--deny-warnings", "--archive", "base.tar.gz"],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let archive = fs::File::open(dir.path().join("base.tar.gz")).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    let mut names = Vec::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().display().to_string();
+        let mut archived = Vec::new();
+        entry.read_to_end(&mut archived).unwrap();
+        assert_eq!(
+            archived,
+            fs::read(dir.path().join("out").join(&name)).unwrap()
+        );
+        names.push(name);
+    }
+    assert_eq!(
+        names,
+        ["Cargo.toml", "Cargo.lock", "crate-information.json"]
+    );
+}
+
+/// Runs the tool against the registry at `index`, writing into `out`
+/// within `dir`.
+fn generate(dir: &Path, index: &str, api: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust-playground-top-crates"))
+        .current_dir(dir)
+        .env("CARGO_HOME", dir.join("cargo-home"))
+        .env("NO_PROXY", "127.0.0.1")
+        .env_remove("TOP_CRATES_REGISTRY")
+        .args(["--registry", index])
+        .args(["--rust-cookbook-manifest", &format!("{}/cookbook", api)])
+        .args(["--count", "2"])
+        .args(args)
+        .arg("out")
+        .output()
+        .unwrap()
+}
+
+/// Damages the object `id` of the repository at `path`, after writing
+/// every object out loose so that it can be got at.
+fn damage_object(path: &Path, id: git2::Oid) {
+    let repo = git2::Repository::open(path).unwrap();
+    let odb = repo.odb().unwrap();
+    let mut objects = Vec::new();
+    odb.foreach(|&oid| {
+        let object = odb.read(oid).unwrap();
+        objects.push((object.kind(), object.data().to_vec()));
+        true
+    })
+    .unwrap();
+    fs::remove_dir_all(repo.path().join("objects/pack")).unwrap();
+
+    let repo = git2::Repository::open(path).unwrap();
+    let odb = repo.odb().unwrap();
+    for (kind, data) in objects {
+        odb.write(kind, &data).unwrap();
+    }
+
+    let id = id.to_string();
+    let loose = repo.path().join("objects").join(&id[..2]).join(&id[2..]);
+    let mut damaged = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    damaged.write_all(b"damaged").unwrap();
+    fs::remove_file(&loose).unwrap();
+    fs::write(&loose, damaged.finish().unwrap()).unwrap();
+}
+
+/// Answers requests for the top crates and the cookbook's manifest,
+/// returning the base URL.
+fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap_or_default();
+            let (status, body) = if path.starts_with("/api/v1/crates?page=1&") {
+                ("200 OK", TOP_CRATES)
+            } else if path.starts_with("/api/v1/crates?") {
+                ("200 OK", r#"{"crates": []}"#)
+            } else if path == "/cookbook" {
+                ("200 OK", COOKBOOK)
+            } else {
+                ("404 Not Found", "")
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+
+    url
+}
+
+/// Creates a git registry index listing `crates`, whose `.crate`
+/// files are downloaded straight from disk, returning its URL. Doing
+/// so again in the same `dir` adds a commit to the index.
+fn publish(dir: &Path, api: &str, crates: &[Published]) -> String {
+    let index_dir = dir.join("index");
+    let crates_dir = dir.join("crates");
+    fs::create_dir_all(&index_dir).unwrap();
+    fs::create_dir_all(&crates_dir).unwrap();
+    let index = format!("file://{}", index_dir.display());
+
+    let config = format!(
+        r#"{{"dl": "file://{}/{{crate}}-{{version}}.crate", "api": "{}"}}"#,
+        crates_dir.display(),
+        api
+    );
+    fs::write(index_dir.join("config.json"), config).unwrap();
+
+    let mut entries = BTreeMap::<_, String>::new();
+    for krate in crates {
+        let contents = crate_file(krate, &index);
+        let checksum = cargo_util::Sha256::new().update(&contents).finish_hex();
+        let file = crates_dir.join(format!("{}-{}.crate", krate.name, krate.version));
+        fs::write(file, contents).unwrap();
+
+        let dependencies = krate
+            .dependencies
+            .iter()
+            .map(|(name, req)| {
+                format!(
+                    r#"{{"name": "{}", "req": "{}", "features": [], "optional": false, "default_features": true, "target": null, "kind": "normal"}}"#,
+                    name, req
+                )
+            })
+            .collect::<Vec<_>>();
+        let features = krate
+            .features
+            .iter()
+            .map(|(name, enables)| format!(r#""{}": {:?}"#, name, enables))
+            .collect::<Vec<_>>();
+        let entry = format!(
+            r#"{{"name": "{}", "vers": "{}", "deps": [{}], "cksum": "{}", "features": {{{}}}, "yanked": false}}"#,
+            krate.name,
+            krate.version,
+            dependencies.join(", "),
+            checksum,
+            features.join(", ")
+        );
+
+        *entries.entry(krate.name).or_default() += &(entry + "\n");
+    }
+    for (name, lines) in entries {
+        let path = index_dir.join(index_path(name));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, lines).unwrap();
+    }
+
+    let repo = git2::Repository::init(&index_dir).unwrap();
+    let mut git_index = repo.index().unwrap();
+    git_index
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    let tree = repo.find_tree(git_index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents = parent.iter().collect::<Vec<_>>();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        "Publish",
+        &tree,
+        &parents,
+    )
+    .unwrap();
+
+    index
+}
+
+/// Where cargo looks for a crate in a registry index.
+fn index_path(name: &str) -> PathBuf {
+    match name.len() {
+        1 => Path::new("1").join(name),
+        2 => Path::new("2").join(name),
+        3 => Path::new("3").join(&name[..1]).join(name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(name),
+    }
+}
+
+/// A gzipped tarball of a package, as `cargo publish` would upload.
+fn crate_file(krate: &Published, index: &str) -> Vec<u8> {
+    let mut manifest = format!(
+        "[package]\n\
+         name = \"{}\"\n\
+         version = \"{}\"\n\
+         description = \"The {} crate\"\n\
+         license = \"MIT\"\n\
+         \n\
+         [lib]\n\
+         path = \"src/lib.rs\"\n\
+         \n\
+         [dependencies]\n",
+        krate.name, krate.version, krate.name
+    );
+    for (name, req) in krate.dependencies {
+        manifest += &format!(
+            "{} = {{ version = \"{}\", registry-index = \"{}\" }}\n",
+            name, req, index
+        );
+    }
+    manifest += "\n[features]\n";
+    for (name, enables) in krate.features {
+        manifest += &format!("{} = {:?}\n", name, enables);
+    }
+    if !krate.metadata.is_empty() {
+        manifest += &format!("\n[package.metadata.playground]\n{}\n", krate.metadata);
+    }
+
+    let prefix = format!("{}-{}", krate.name, krate.version);
+    let mut tarball = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (path, contents) in [("Cargo.toml", manifest.as_str()), ("src/lib.rs", "")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tarball
+            .append_data(
+                &mut header,
+                format!("{}/{}", prefix, path),
+                contents.as_bytes(),
+            )
+            .unwrap();
+    }
+    tarball.into_inner().unwrap().finish().unwrap()
+}